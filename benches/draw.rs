@@ -0,0 +1,76 @@
+use azusa::raster::{Pixmap, TiledRasterizer};
+use azusa::{Azusa, Color, DrawTarget, FontInfo, Surface, UString};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// A scene of 10k commands, a third of them text, like a redraw of a busy UI
+fn scene() -> Azusa {
+    let mut azusa = Azusa::new();
+    azusa.set_source_color(Color::White);
+    azusa.clear();
+    azusa.set_border_color(Color::Black);
+    for i in 0..9_999u32 {
+        azusa.move_to(i % 100 * 12, i / 100 * 7);
+        match i % 3 {
+            0 => {
+                azusa.set_source_color(Color::Navy);
+                azusa.fill_rectangle(10, 6);
+            }
+            1 => {
+                azusa.set_source_color(Color::Red);
+                azusa.draw_rectangle(1, 10, 6);
+            }
+            _ => {
+                azusa.set_source_color(Color::Black);
+                azusa.draw_text(10, 6, UString::new("Label of a list item"), FontInfo::new(6, false, false));
+            }
+        }
+    }
+    azusa
+}
+
+/// Only reads the commands, so the benchmark measures handing the context to the surface
+struct NullSurface;
+
+impl Surface for NullSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        black_box(ctx);
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (1200, 700)
+    }
+}
+
+fn draw(c: &mut Criterion) {
+    let azusa = scene();
+    let mut group = c.benchmark_group("draw 10k commands");
+
+    group.bench_function("borrowed", |b| b.iter(|| azusa.draw(&mut NullSurface)));
+    // What every draw cost before Surface::draw borrowed the context
+    group.bench_function("cloned", |b| {
+        b.iter(|| {
+            let ctx = black_box(azusa.get_ctx().to_vec());
+            NullSurface.draw(&ctx)
+        })
+    });
+
+    let mut pixmap = Pixmap::new(1200, 700);
+    group.bench_function("rasterized", |b| b.iter(|| azusa.draw(&mut pixmap)));
+    group.finish();
+}
+
+fn rasterize_4k(c: &mut Criterion) {
+    let azusa = scene();
+    let ctx: Vec<DrawTarget> = azusa.get_ctx().iter().map(|i| i.scale(3.2)).collect();
+    let mut group = c.benchmark_group("rasterize 4K");
+    group.sample_size(10);
+
+    let mut pixmap = Pixmap::new(3840, 2240);
+    group.bench_function("single thread", |b| b.iter(|| pixmap.draw(&ctx)));
+    let rasterizer = TiledRasterizer::new(0);
+    group.bench_function("tiled", |b| b.iter(|| rasterizer.draw(&mut pixmap, &ctx)));
+    group.finish();
+}
+
+criterion_group!(benches, draw, rasterize_4k);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+use azusa::animation::{AnimationSurface, AnimationType};
+use azusa::{Azusa, Color};
+
+fn main() {
+    // Every draw becomes a frame of the animation
+    let mut apng = AnimationSurface::new(64.0, 64.0, AnimationType::Apng);
+    let mut gif = AnimationSurface::new(64.0, 64.0, AnimationType::Gif);
+    // Display each frame for 80ms
+    apng.set_delay(80);
+    gif.set_delay(80);
+
+    let mut azusa = Azusa::new();
+    // A block that runs around the edge of the image
+    let positions = [(8, 8), (28, 8), (48, 8), (48, 28), (48, 48), (28, 48), (8, 48), (8, 28)];
+    for (x, y) in positions {
+        azusa.set_source_color(Color::White);
+        azusa.clear();
+        azusa.set_source_color(Color::Silver);
+        azusa.move_to(4, 4);
+        azusa.draw_rectangle(2, 56, 56);
+        azusa.set_source_color(Color::Blue);
+        azusa.set_border_color(Color::Navy);
+        azusa.move_to(x, y);
+        azusa.fill_rectangle(8, 8);
+
+        azusa.draw(&mut apng);
+        azusa.draw(&mut gif);
+    }
+
+    apng.save("spinner.png").unwrap();
+    gif.save("spinner.gif").unwrap();
+}
@@ -0,0 +1,25 @@
+use azusa::framebuffer::{FramebufferSurface, PixelFormat};
+use azusa::{Azusa, Color, Surface};
+
+// Usage: cargo run --example framebuffer --features framebuffer [/dev/fb0 | /dev/dri/card0]
+// Without an argument, the frame is written to fake-fb.raw, which can be viewed with
+// ffmpeg -f rawvideo -pix_fmt bgr0 -s 320x240 -i fake-fb.raw fake-fb.png
+fn main() {
+    let mut surface = match std::env::args().nth(1) {
+        Some(path) if path.starts_with("/dev/dri/") => FramebufferSurface::open_drm(path),
+        Some(path) => FramebufferSurface::open(path),
+        None => FramebufferSurface::from_file("fake-fb.raw", 320, 240, PixelFormat::Xrgb8888),
+    }
+    .unwrap();
+
+    let (width, height) = surface.get_client_size();
+    let mut azusa = Azusa::new();
+    azusa.set_source_color(Color::Teal);
+    azusa.clear();
+    azusa.set_source_color(Color::Yellow);
+    azusa.set_border_color(Color::White);
+    azusa.move_to(width / 4, height / 4);
+    azusa.fill_rectangle(width / 2, height / 2);
+
+    azusa.draw(&mut surface);
+}
@@ -0,0 +1,38 @@
+use azusa::pdf::PdfSurface;
+use azusa::raster::Pixmap;
+use azusa::{Azusa, Color, DrawTarget, FontInfo, Surface, UString};
+
+fn main() {
+    // Create a surface for an A6 sized document (one pixel is one point)
+    let mut surface = PdfSurface::new(298.0, 420.0);
+
+    // A TrueType font can be passed as the first argument, only the glyphs that are used are embedded
+    if let Some(path) = std::env::args().nth(1) {
+        surface.set_font(std::fs::read(path).unwrap()).unwrap();
+    }
+
+    let mut azusa = Azusa::new();
+
+    // Each context is drawn on its own page
+    for (i, color) in [Color::Navy, Color::Teal, Color::Maroon].into_iter().enumerate() {
+        azusa.set_source_color(Color::White);
+        azusa.clear();
+        azusa.set_source_color(color);
+        azusa.set_border_color(Color::Black);
+        azusa.move_to(20, 20);
+        azusa.fill_rectangle(258, 60);
+        azusa.set_source_color(Color::Black);
+        azusa.move_to(20, 100);
+        azusa.draw_text(258, 40, UString::new(&format!("Page {}", i + 1)), FontInfo::new(24, false, true));
+
+        azusa.draw(&mut surface);
+
+        // Images are embedded as they are, e.g. a small rasterized badge
+        let mut badge = Pixmap::new(16, 16);
+        badge.draw(&[DrawTarget::FillRectangle(color, Color::Black, 0, 0, 16, 16)]);
+        surface.draw_image(&badge, 20, 160, 32, 32);
+        surface.show_page();
+    }
+
+    surface.save("sample.pdf").unwrap();
+}
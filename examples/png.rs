@@ -19,7 +19,7 @@ fn main() {
     azusa.draw_rectangle(1, 90, 90);
 
 
-    let mut ctx = azusa.get_ctx();
+    let ctx = azusa.get_ctx();
     println!("{:?}",ctx);
 
     // Performs the drawing scheduled for the context
@@ -0,0 +1,33 @@
+use azusa::stream::{StreamSurface, REPLAYER};
+use azusa::{Azusa, Color, FontInfo, UString};
+
+// Writes stream.html, a page that replays a frame without WebAssembly.
+// A server would send the lines of the stream over a WebSocket or a streamed response instead.
+fn main() {
+    let mut surface = StreamSurface::new(vec![], 320, 240);
+
+    let mut azusa = Azusa::new();
+    azusa.set_source_color(Color::White);
+    azusa.clear();
+    azusa.set_source_color(Color::Aqua);
+    azusa.set_border_color(Color::Navy);
+    azusa.move_to(20, 20);
+    azusa.fill_rectangle(280, 140);
+    azusa.set_source_color(Color::Maroon);
+    azusa.move_to(10, 10);
+    azusa.draw_rectangle(3, 300, 220);
+    azusa.set_source_color(Color::Black);
+    azusa.move_to(40, 180);
+    azusa.draw_text(240, 20, UString::new("Drawn by the replayer"), FontInfo::new(16, false, true));
+
+    azusa.draw(&mut surface);
+
+    let stream = String::from_utf8(surface.into_inner().unwrap()).unwrap();
+    print!("{}", stream);
+
+    let html = format!(
+        "<!DOCTYPE html>\n<canvas id=\"canvas\" width=\"320\" height=\"240\"></canvas>\n<script>\n{}\nconst context = document.getElementById(\"canvas\").getContext(\"2d\");\nfor (const line of {:?}.split(\"\\n\").filter(l => l)) azusaReplay(context, JSON.parse(line));\n</script>\n",
+        REPLAYER, stream
+    );
+    std::fs::write("stream.html", html).unwrap();
+}
@@ -0,0 +1,29 @@
+use azusa::terminal::{TerminalMode, TerminalSurface};
+use azusa::{Azusa, Color};
+
+// Usage: cargo run --example terminal [halfblock|sixel|kitty]
+fn main() {
+    let mode = match std::env::args().nth(1).as_deref() {
+        Some("sixel") => TerminalMode::Sixel,
+        Some("kitty") => TerminalMode::Kitty,
+        _ => TerminalMode::HalfBlock,
+    };
+
+    // The drawing covers 40x12 cells of the terminal
+    let mut surface = TerminalSurface::new(std::io::stdout(), 40, 12, mode);
+    let (width, height) = azusa::Surface::get_client_size(&surface);
+
+    let mut azusa = Azusa::new();
+    azusa.set_source_color(Color::Navy);
+    azusa.clear();
+    azusa.set_source_color(Color::Yellow);
+    azusa.set_border_color(Color::Red);
+    azusa.move_to(width / 8, height / 8);
+    azusa.fill_rectangle(width / 2, height / 2);
+    azusa.set_source_color(Color::Lime);
+    azusa.set_border_color(Color::Lime);
+    azusa.move_to(width / 2, height / 2);
+    azusa.fill_rectangle(width / 3, height / 3);
+
+    azusa.draw(&mut surface);
+}
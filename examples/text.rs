@@ -0,0 +1,22 @@
+use azusa::text::{TextCharset, TextSurface};
+use azusa::{Azusa, Color, FontInfo, UString};
+
+fn main() {
+    // A grid of 30x8 characters, each character covers 8x16 pixels of the context
+    let mut surface = TextSurface::new(30, 8, TextCharset::Unicode);
+
+    let mut azusa = Azusa::new();
+    azusa.set_source_color(Color::White);
+    azusa.clear();
+    azusa.set_source_color(Color::Gray);
+    azusa.move_to(0, 0);
+    azusa.fill_rectangle(120, 80);
+    azusa.set_source_color(Color::Black);
+    azusa.move_to(136, 16);
+    azusa.draw_text(100, 16, UString::new("Hello, Azusa"), FontInfo::new(14, false, false));
+
+    azusa.draw(&mut surface);
+
+    // The surface can be printed or compared with a snapshot
+    print!("{}", surface);
+}
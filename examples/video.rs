@@ -0,0 +1,26 @@
+use azusa::video::{VideoFormat, VideoSurface};
+use azusa::{Azusa, Color};
+
+// Usage: cargo run --example video | ffmpeg -i - video.mp4
+fn main() {
+    let stdout = std::io::stdout().lock();
+    let mut surface = VideoSurface::new(stdout, 320.0, 240.0, VideoFormat::Y4m);
+    surface.set_frame_rate(30, 1);
+
+    let mut azusa = Azusa::new();
+
+    // Two seconds of a square moving from left to right
+    for frame in 0..60 {
+        azusa.set_source_color(Color::Navy);
+        azusa.clear();
+        azusa.set_source_color(Color::Yellow);
+        azusa.set_border_color(Color::Olive);
+        azusa.move_to(frame * 4, 100);
+        azusa.fill_rectangle(40, 40);
+
+        azusa.draw(&mut surface);
+    }
+
+    // Flush everything that is still buffered
+    let _ = surface.into_inner().unwrap();
+}
@@ -1,5 +1,6 @@
-#![allow(clippy::single_match)]
+#![allow(clippy::single_match, clippy::collapsible_match)]
 
+use azusa::compose::MultiSurface;
 use azusa::window::WindowSurface;
 use azusa::{Azusa, Color, FontInfo, ImageSurface, ImageType, Surface, UString};
 
@@ -19,8 +20,11 @@ fn main() {
         .unwrap();
 
     let mut surface = WindowSurface::new(&window).unwrap();
+    let size = window.inner_size();
+    surface.resize(size.width, size.height);
     let mut png = ImageSurface::new(0.0, 0.0, "A fantastic window", ImageType::Png);
     let mut azusa = Azusa::new();
+    let mut screenshot = false;
 
     event_loop.run(move |event, _, control_flow| {
         control_flow.set_wait();
@@ -31,23 +35,20 @@ fn main() {
                 window_id,
             } if window_id == window.id() => control_flow.set_exit(),
             Event::WindowEvent {
-                event: WindowEvent::Resized(_size),
+                event: WindowEvent::Resized(size),
                 ..
-            } => {}
+            } => surface.resize(size.width, size.height),
             Event::WindowEvent {
                 event: WindowEvent::ReceivedCharacter(c),
                 ..
             } => {
                 if c == 's' {
-                    let (w, h) = surface.get_client_size();
-                    if w != 0 && h != 0 {
-                        png.resize(w as f64, h as f64);
-                    }
-                    azusa.draw(&mut png);
+                    screenshot = true;
                 }
             }
+            // The window system lost the contents of the window, e.g. because it was covered
+            Event::RedrawRequested(_) => azusa.invalidate(),
             Event::RedrawEventsCleared => {
-                window.request_redraw();
                 azusa.set_source_color(Color::White);
                 azusa.clear();
                 azusa.set_source_color(Color::Gray);
@@ -59,7 +60,18 @@ fn main() {
                 azusa.draw_text(500,150,UString::new("汉语"),FontInfo::new(14,false,false));
                 azusa.move_to(490,10);
                 azusa.draw_text(500,150,UString::new("اللغة العربية"),FontInfo::new(14,false,false));
-                azusa.draw(&mut surface);
+                if screenshot {
+                    screenshot = false;
+                    let (w, h) = surface.get_client_size();
+                    if w != 0 && h != 0 {
+                        png.resize(w as f64, h as f64);
+                    }
+                    // The frame goes to the window and the PNG file at once
+                    azusa.draw(&mut MultiSurface::new().with(&mut surface).with(&mut png));
+                } else {
+                    // Only the parts of the frame that changed since the last one are repainted
+                    azusa.present(&mut surface);
+                }
             }
             _ => (),
         }
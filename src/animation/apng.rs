@@ -0,0 +1,31 @@
+use super::{Frame, Region};
+
+use png::{BlendOp, DisposeOp};
+use std::io::Write;
+
+pub(super) fn encode<W: Write>(w: &mut W, frames: &[(&Frame, Region)], repeat: u16) -> std::io::Result<()> {
+    let first = &frames[0].0.pixmap;
+    let mut encoder = png::Encoder::new(w, first.width(), first.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, repeat as u32)?;
+
+    let mut writer = encoder.write_header()?;
+    for (frame, region) in frames {
+        // Delays are stored as a fraction of a second
+        let delay = frame.delay.min(u16::MAX as u32) as u16;
+        writer.set_frame_delay(delay, 1000)?;
+
+        writer.reset_frame_position()?;
+        writer.set_frame_dimension(region.width, region.height)?;
+        writer.set_frame_position(region.x, region.y)?;
+        // The region replaces what was there, everything else stays from the previous frame
+        writer.set_blend_op(BlendOp::Source)?;
+        writer.set_dispose_op(DisposeOp::None)?;
+
+        writer.write_image_data(&region.crop(&frame.pixmap))?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use super::{Frame, Region};
+use crate::raster::Pixmap;
+
+use gif::{DisposalMethod, Encoder, Repeat};
+use std::io::Write;
+
+/// Speed of the NeuQuant palette quantization, 1 is the best quality and 30 the fastest
+const QUANTIZATION_SPEED: i32 = 10;
+
+fn to_io(e: gif::EncodingError) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+pub(super) fn encode<W: Write>(w: &mut W, frames: &[(&Frame, Region)], repeat: u16) -> std::io::Result<()> {
+    let first = &frames[0].0.pixmap;
+    let mut encoder = Encoder::new(w, first.width() as u16, first.height() as u16, &[]).map_err(to_io)?;
+    encoder
+        .set_repeat(match repeat {
+            0 => Repeat::Infinite,
+            count => Repeat::Finite(count - 1),
+        })
+        .map_err(to_io)?;
+
+    // A frame drawn over the previous one cannot turn a pixel transparent, the old colour would show through.
+    // Such a frame is written whole, after the previous one is disposed to the transparent background.
+    let whole: Vec<bool> = (0..frames.len())
+        .map(|i| i > 0 && clears(&frames[i - 1].0.pixmap, &frames[i].0.pixmap))
+        .collect();
+
+    for (i, (frame, region)) in frames.iter().enumerate() {
+        let region = if whole[i] {
+            Region {
+                x: 0,
+                y: 0,
+                width: frame.pixmap.width(),
+                height: frame.pixmap.height(),
+            }
+        } else {
+            *region
+        };
+        // Every frame gets its own palette, quantized from the pixels that changed
+        let mut pixels = region.crop(&frame.pixmap);
+        // Transparent pixels have to share the single transparent entry of the palette
+        for pixel in pixels.chunks_exact_mut(4).filter(|pixel| pixel[3] == 0) {
+            pixel.copy_from_slice(&[0; 4]);
+        }
+        let mut output = gif::Frame::from_rgba_speed(
+            region.width as u16,
+            region.height as u16,
+            &mut pixels,
+            QUANTIZATION_SPEED,
+        );
+        output.left = region.x as u16;
+        output.top = region.y as u16;
+        // GIF delays are in hundredths of a second
+        output.delay = ((frame.delay + 5) / 10).min(u16::MAX as u32) as u16;
+        output.dispose = match whole.get(i + 1) {
+            Some(true) => DisposalMethod::Background,
+            _ => DisposalMethod::Keep,
+        };
+        encoder.write_frame(&output).map_err(to_io)?;
+    }
+
+    Ok(())
+}
+
+/// Whether a pixel turns transparent between two frames
+fn clears(previous: &Pixmap, next: &Pixmap) -> bool {
+    previous.as_slice().chunks_exact(4).zip(next.as_slice().chunks_exact(4)).any(|(a, b)| a[3] != 0 && b[3] == 0)
+}
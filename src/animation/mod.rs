@@ -0,0 +1,314 @@
+#[cfg(feature = "png")]
+mod apng;
+#[cfg(feature = "gif")]
+mod gif;
+
+use crate::raster::Pixmap;
+use crate::{DrawTarget, Surface};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationType {
+    #[cfg(feature = "png")]
+    Apng,
+    #[cfg(feature = "gif")]
+    Gif,
+}
+
+struct Frame {
+    pixmap: Pixmap,
+    /// Display time in milliseconds
+    delay: u32,
+}
+
+/// Area of a frame that differs from the previous frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Region {
+    /// Finds the bounding box of the pixels that changed
+    fn changed(previous: &Pixmap, next: &Pixmap) -> Option<Self> {
+        let width = next.width() as usize;
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+
+        let rows = previous.as_slice().chunks_exact(width * 4).zip(next.as_slice().chunks_exact(width * 4));
+        for (y, (a, b)) in rows.enumerate() {
+            if a == b {
+                continue;
+            }
+            let first = a.chunks_exact(4).zip(b.chunks_exact(4)).position(|(a, b)| a != b).unwrap();
+            let last = a.chunks_exact(4).zip(b.chunks_exact(4)).rposition(|(a, b)| a != b).unwrap();
+            left = left.min(first);
+            right = right.max(last);
+            top = top.min(y);
+            bottom = y;
+        }
+
+        if top == usize::MAX {
+            return None;
+        }
+
+        Some(Self {
+            x: left as u32,
+            y: top as u32,
+            width: (right - left + 1) as u32,
+            height: (bottom - top + 1) as u32,
+        })
+    }
+
+    fn full(pixmap: &Pixmap) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: pixmap.width(),
+            height: pixmap.height(),
+        }
+    }
+
+    /// Copies the region out of the pixmap
+    fn crop(&self, pixmap: &Pixmap) -> Vec<u8> {
+        let stride = pixmap.width() as usize * 4;
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for row in self.y..self.y + self.height {
+            let start = row as usize * stride + self.x as usize * 4;
+            data.extend_from_slice(&pixmap.as_slice()[start..start + self.width as usize * 4]);
+        }
+        data
+    }
+}
+
+/// Surface that collects every draw as a frame of an animated image.
+/// Frames that do not change anything only extend the display time of the previous frame,
+/// and the other frames only store the area that changed.
+pub struct AnimationSurface {
+    width: f64,
+    height: f64,
+    animation_type: AnimationType,
+
+    delay: u32,
+    repeat: u16,
+    frames: Vec<Frame>,
+}
+
+impl AnimationSurface {
+    pub fn new(width: f64, height: f64, animation_type: AnimationType) -> Self {
+        Self {
+            width,
+            height,
+            animation_type,
+            delay: 100,
+            repeat: 0,
+            frames: vec![],
+        }
+    }
+
+    /// Display time of the following frames in milliseconds
+    pub fn set_delay(&mut self, delay: u32) {
+        self.delay = delay;
+    }
+
+    /// How often the animation is played, 0 repeats it forever
+    pub fn set_repeat(&mut self, repeat: u16) {
+        self.repeat = repeat;
+    }
+
+    /// Number of frames after merging unchanged frames
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Discards all frames
+    pub fn reset(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Encodes the animation to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(file);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+
+    /// Encodes the animation
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        if self.frames.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the animation has no frames",
+            ));
+        }
+
+        // The first frame is always complete
+        let mut frames = vec![(&self.frames[0], Region::full(&self.frames[0].pixmap))];
+        for pair in self.frames.windows(2) {
+            let region = Region::changed(&pair[0].pixmap, &pair[1].pixmap).unwrap_or(Region {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            });
+            frames.push((&pair[1], region));
+        }
+
+        match self.animation_type {
+            #[cfg(feature = "png")]
+            AnimationType::Apng => apng::encode(w, &frames, self.repeat),
+            #[cfg(feature = "gif")]
+            AnimationType::Gif => gif::encode(w, &frames, self.repeat),
+        }
+    }
+}
+
+impl Surface for AnimationSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        let mut pixmap = Pixmap::new(self.width as u32, self.height as u32);
+        pixmap.draw(ctx);
+
+        match self.frames.last_mut() {
+            Some(last) if last.pixmap == pixmap => {
+                last.delay = last.delay.saturating_add(self.delay);
+            }
+            _ => self.frames.push(Frame {
+                pixmap,
+                delay: self.delay,
+            }),
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[cfg(feature = "png")]
+    const ANY: AnimationType = AnimationType::Apng;
+    #[cfg(not(feature = "png"))]
+    const ANY: AnimationType = AnimationType::Gif;
+
+    /// Three frames: a red background, the same again, and a blue square moved onto it
+    fn surface(animation_type: AnimationType) -> AnimationSurface {
+        let mut surface = AnimationSurface::new(8.0, 6.0, animation_type);
+        surface.set_repeat(3);
+        let mut ctx = vec![DrawTarget::FillRectangle(Color::Red, Color::Red, 0, 0, 8, 6)];
+        surface.draw(&ctx);
+        surface.draw(&ctx);
+        surface.set_delay(40);
+        ctx.push(DrawTarget::FillRectangle(Color::Blue, Color::Blue, 2, 1, 3, 2));
+        surface.draw(&ctx);
+        surface
+    }
+
+    #[test]
+    fn unchanged_frames_extend_the_previous_one() {
+        let surface = surface(ANY);
+        assert_eq!(surface.frame_count(), 2);
+        assert_eq!(surface.frames[0].delay, 200);
+        assert_eq!(surface.frames[1].delay, 40);
+    }
+
+    #[test]
+    fn changed_region() {
+        let surface = surface(ANY);
+        let region = Region::changed(&surface.frames[0].pixmap, &surface.frames[1].pixmap);
+        assert_eq!(region, Some(Region { x: 2, y: 1, width: 3, height: 2 }));
+        assert_eq!(Region::changed(&surface.frames[0].pixmap, &surface.frames[0].pixmap), None);
+    }
+
+    #[test]
+    fn empty_animation_is_an_error() {
+        let surface = AnimationSurface::new(8.0, 6.0, ANY);
+        assert!(surface.write_to(&mut vec![]).is_err());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn apng_frames() {
+        let mut data = vec![];
+        surface(AnimationType::Apng).write_to(&mut data).unwrap();
+
+        let mut reader = png::Decoder::new(data.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (8, 6));
+        let control = info.animation_control().unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (2, 3));
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let mut frames = vec![];
+        while reader.next_frame(&mut buffer).is_ok() {
+            let frame = reader.info().frame_control().unwrap();
+            frames.push((frame.x_offset, frame.y_offset, frame.width, frame.height, frame.delay_num, frame.delay_den));
+        }
+        assert_eq!(frames, [(0, 0, 8, 6, 200, 1000), (2, 1, 3, 2, 40, 1000)]);
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn gif_frames() {
+        let mut data = vec![];
+        surface(AnimationType::Gif).write_to(&mut data).unwrap();
+        assert!(data.starts_with(b"GIF89a"));
+
+        let mut options = ::gif::DecodeOptions::new();
+        options.set_color_output(::gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(data.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (8, 6));
+
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.left, frame.top, frame.width, frame.height, frame.delay, frame.buffer[..4].to_vec()));
+        }
+        assert_eq!(
+            frames,
+            [(0, 0, 8, 6, 20, vec![255, 0, 0, 255]), (2, 1, 3, 2, 4, vec![0, 0, 255, 255])]
+        );
+        assert_eq!(decoder.repeat(), ::gif::Repeat::Finite(2));
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn gif_frames_clearing_pixels_are_whole() {
+        use ::gif::DisposalMethod::{Background, Keep};
+
+        // A square moving over a transparent background, then a frame that only adds to it
+        let mut surface = AnimationSurface::new(8.0, 6.0, AnimationType::Gif);
+        surface.draw(&[DrawTarget::FillRectangle(Color::Red, Color::Red, 0, 0, 4, 4)]);
+        surface.draw(&[DrawTarget::FillRectangle(Color::Red, Color::Red, 2, 0, 4, 4)]);
+        surface.draw(&[
+            DrawTarget::FillRectangle(Color::Red, Color::Red, 2, 0, 4, 4),
+            DrawTarget::FillRectangle(Color::Blue, Color::Blue, 7, 5, 1, 1),
+        ]);
+        let mut data = vec![];
+        surface.write_to(&mut data).unwrap();
+
+        let mut options = ::gif::DecodeOptions::new();
+        options.set_color_output(::gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(data.as_slice()).unwrap();
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.left, frame.top, frame.width, frame.height, frame.dispose, frame.buffer[..4].to_vec()));
+        }
+        assert_eq!(
+            frames,
+            [
+                (0, 0, 8, 6, Background, vec![255, 0, 0, 255]),
+                // The first pixel turned transparent
+                (0, 0, 8, 6, Keep, vec![0, 0, 0, 0]),
+                (7, 5, 1, 1, Keep, vec![0, 0, 255, 255]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! A small text format for drawings, one statement per line:
+//!
+//! ```text
+//! # Comments start with a hash
+//! color white
+//! clear
+//! color navy
+//! border black
+//! move_to 10 10
+//! fill_rect 100 50
+//! rect 2 100 50
+//! text 200 20 14 "Hello\nworld"
+//! layer overlay z 1 opacity 0.5
+//! fill_rect 50 50
+//! ```
+//!
+//! `rect` takes the thickness first, `text` takes the width and height of its box and the font size,
+//! followed by `italic` and `underline` if wanted and the text, in quotes or as the rest of the line.
+//! `layer` selects the layer the following statements draw to, optionally with its z-index, opacity
+//! and `hidden`.
+
+use azusa::{Azusa, Color, FontInfo, UString};
+
+pub fn parse(source: &str) -> Result<Azusa, String> {
+    let mut azusa = Azusa::new();
+    for (number, line) in source.lines().enumerate() {
+        statement(&mut azusa, line.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+    }
+    Ok(azusa)
+}
+
+fn statement(azusa: &mut Azusa, line: &str) -> Result<(), String> {
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    let mut rest = line;
+    let keyword = word(&mut rest).unwrap_or_default();
+
+    match keyword {
+        "color" => azusa.set_source_color(color(word(&mut rest))?),
+        "border" => azusa.set_border_color(color(word(&mut rest))?),
+        "clear" => azusa.clear(),
+        "move_to" => {
+            let [x, y] = numbers(&mut rest)?;
+            azusa.move_to(x, y);
+        }
+        "fill_rect" => {
+            let [width, height] = numbers(&mut rest)?;
+            azusa.fill_rectangle(width, height);
+        }
+        "rect" => {
+            let [thickness, width, height] = numbers(&mut rest)?;
+            azusa.draw_rectangle(thickness, width, height);
+        }
+        "text" => {
+            let [width, height, px] = numbers(&mut rest)?;
+            let (mut italic, mut underline) = (false, false);
+            loop {
+                let mut next = rest;
+                match word(&mut next) {
+                    Some("italic") => italic = true,
+                    Some("underline") => underline = true,
+                    _ => break,
+                }
+                rest = next;
+            }
+            let string = text(rest.trim())?;
+            azusa.draw_text(width, height, UString::new(&string), FontInfo::new(px, italic, underline));
+            return Ok(());
+        }
+        "layer" => {
+            let name = word(&mut rest).ok_or("missing layer name")?;
+            let layer = azusa.layer(name);
+            while let Some(option) = word(&mut rest) {
+                match option {
+                    "z" => {
+                        let arg = word(&mut rest).ok_or("missing z-index")?;
+                        layer.set_z_index(arg.parse().map_err(|_| format!("`{}` is not a number", arg))?);
+                    }
+                    "opacity" => {
+                        let arg = word(&mut rest).ok_or("missing opacity")?;
+                        match arg.parse::<f64>() {
+                            Ok(opacity) if (0.0..=1.0).contains(&opacity) => layer.set_opacity(opacity),
+                            _ => return Err(format!("opacity must be from 0 to 1, not `{}`", arg)),
+                        };
+                    }
+                    "hidden" => {
+                        layer.set_visible(false);
+                    }
+                    option => return Err(format!("unknown layer option `{}`", option)),
+                }
+            }
+        }
+        keyword => return Err(format!("unknown statement `{}`", keyword)),
+    }
+
+    if !rest.trim().is_empty() {
+        return Err(format!("too many arguments for `{}`", keyword));
+    }
+    Ok(())
+}
+
+/// Takes the next word of a statement
+fn word<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let trimmed = rest.trim_start();
+    if trimmed.is_empty() {
+        return None;
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (word, remainder) = trimmed.split_at(end);
+    *rest = remainder;
+    Some(word)
+}
+
+fn color(name: Option<&str>) -> Result<Color, String> {
+    let name = name.ok_or("missing color")?;
+    let color = match name.to_ascii_lowercase().as_str() {
+        "white" => Color::White,
+        "olive" => Color::Olive,
+        "yellow" => Color::Yellow,
+        "fuchsia" => Color::Fuchsia,
+        "silver" => Color::Silver,
+        "aqua" => Color::Aqua,
+        "lime" => Color::Lime,
+        "red" => Color::Red,
+        "gray" | "grey" => Color::Gray,
+        "blue" => Color::Blue,
+        "green" => Color::Green,
+        "purple" => Color::Purple,
+        "black" => Color::Black,
+        "navy" => Color::Navy,
+        "teal" => Color::Teal,
+        "maroon" => Color::Maroon,
+        _ => return Err(format!("unknown color `{}`", name)),
+    };
+    Ok(color)
+}
+
+fn numbers<const N: usize>(rest: &mut &str) -> Result<[u32; N], String> {
+    let mut values = [0; N];
+    for value in values.iter_mut() {
+        let arg = word(rest).ok_or(format!("expected {} numbers", N))?;
+        *value = arg.parse().map_err(|_| format!("`{}` is not a number", arg))?;
+    }
+    Ok(values)
+}
+
+/// Text in quotes with \n, \" and \\ escapes, or the rest of the line as it is
+fn text(rest: &str) -> Result<String, String> {
+    let Some(quoted) = rest.strip_prefix('"') else {
+        return Ok(rest.to_string());
+    };
+    let quoted = quoted.strip_suffix('"').ok_or("missing closing quote")?;
+
+    let mut output = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some(c @ ('"' | '\\')) => output.push(c),
+            Some(c) => return Err(format!("unknown escape `\\{}`", c)),
+            None => return Err("unfinished escape".to_string()),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use azusa::DrawTarget;
+
+    fn commands(source: &str) -> Vec<DrawTarget> {
+        parse(source).unwrap().get_ctx().to_vec()
+    }
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn color_and_clear() {
+        assert_eq!(commands("color navy\nclear"), [DrawTarget::Clear(Color::Navy)]);
+        // Names are case-insensitive and grey is gray
+        assert_eq!(commands("color GREY\nclear"), [DrawTarget::Clear(Color::Gray)]);
+    }
+
+    #[test]
+    fn fill_rect_uses_color_border_and_position() {
+        assert_eq!(
+            commands("color red\nborder black\nmove_to 10 20\nfill_rect 30 40"),
+            [DrawTarget::FillRectangle(Color::Red, Color::Black, 10, 20, 30, 40)]
+        );
+    }
+
+    #[test]
+    fn rect_takes_the_thickness_first() {
+        assert_eq!(
+            commands("color teal\nmove_to 1 2\nrect 3 40 50"),
+            [DrawTarget::DrawRectangle(Color::Teal, 3, 1, 2, 40, 50)]
+        );
+    }
+
+    #[test]
+    fn text_with_flags_and_escapes() {
+        assert_eq!(
+            commands("move_to 5 6\ntext 200 20 14 italic underline \"Hello\\n\\\"world\\\"\\t\\\\\""),
+            [DrawTarget::DrawText(
+                Color::Black,
+                FontInfo::new(14, true, true),
+                5,
+                6,
+                200,
+                20,
+                UString::new("Hello\n\"world\"\t\\")
+            )]
+        );
+        // Without quotes the rest of the line is the text
+        assert_eq!(
+            commands("text 100 10 12 plain words  here"),
+            [DrawTarget::DrawText(Color::Black, FontInfo::new(12, false, false), 0, 0, 100, 10, UString::new("plain words  here"))]
+        );
+    }
+
+    #[test]
+    fn layer_options() {
+        let azusa = parse("fill_rect 1 1\nlayer overlay z 2 opacity 0.5\nfill_rect 2 2\nlayer grid z -1 hidden\nfill_rect 3 3").unwrap();
+        let overlay = azusa.get_layer("overlay").unwrap();
+        assert_eq!(overlay.z_index(), 2);
+        assert_eq!(overlay.opacity(), 0.5);
+        assert!(overlay.is_visible());
+        assert_eq!(overlay.get_ctx(), [DrawTarget::FillRectangle(Color::Black, Color::Black, 0, 0, 2, 2)]);
+
+        let grid = azusa.get_layer("grid").unwrap();
+        assert_eq!(grid.z_index(), -1);
+        assert!(!grid.is_visible());
+        assert_eq!(grid.get_ctx().len(), 1);
+        assert_eq!(azusa.get_layer("default").unwrap().get_ctx().len(), 1);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        assert_eq!(commands("# a comment\n\n   \ncolor red\n  # indented\nclear"), [DrawTarget::Clear(Color::Red)]);
+    }
+
+    #[test]
+    fn unknown_statements_and_options() {
+        assert_eq!(error("clear\ncircle 1 2"), "line 2: unknown statement `circle`");
+        assert_eq!(error("color pink"), "line 1: unknown color `pink`");
+        assert_eq!(error("layer top blur 2"), "line 1: unknown layer option `blur`");
+    }
+
+    #[test]
+    fn bad_numbers() {
+        assert_eq!(error("move_to 1 x"), "line 1: `x` is not a number");
+        assert_eq!(error("fill_rect -1 2"), "line 1: `-1` is not a number");
+        assert_eq!(error("layer top z high"), "line 1: `high` is not a number");
+        assert_eq!(error("layer top opacity 2"), "line 1: opacity must be from 0 to 1, not `2`");
+    }
+
+    #[test]
+    fn missing_and_extra_arguments() {
+        assert_eq!(error("color"), "line 1: missing color");
+        assert_eq!(error("border"), "line 1: missing color");
+        assert_eq!(error("move_to 1"), "line 1: expected 2 numbers");
+        assert_eq!(error("rect 1 2"), "line 1: expected 3 numbers");
+        assert_eq!(error("text 1 2"), "line 1: expected 3 numbers");
+        assert_eq!(error("layer"), "line 1: missing layer name");
+        assert_eq!(error("layer top z"), "line 1: missing z-index");
+        assert_eq!(error("layer top opacity"), "line 1: missing opacity");
+        assert_eq!(error("fill_rect 1 2 3"), "line 1: too many arguments for `fill_rect`");
+        assert_eq!(error("clear now"), "line 1: too many arguments for `clear`");
+    }
+
+    #[test]
+    fn bad_text() {
+        assert_eq!(error("text 1 2 3 \"open"), "line 1: missing closing quote");
+        assert_eq!(error("text 1 2 3 \"\\q\""), "line 1: unknown escape `\\q`");
+        assert_eq!(error("text 1 2 3 \"end\\\""), "line 1: unfinished escape");
+    }
+}
@@ -0,0 +1,229 @@
+mod dsl;
+
+use azusa::svg::SvgSurface;
+use azusa::terminal::{TerminalMode, TerminalSurface};
+use azusa::{Azusa, DrawTarget, ImageSurface, ImageType, Surface};
+
+use std::io::Read;
+use std::process::ExitCode;
+
+/// Largest width or height of an image in physical pixels
+const MAX_SIZE: u32 = 1 << 15;
+
+const USAGE: &str = "Usage: azusa [OPTIONS] [INPUT]
+
+Renders a drawing to PNG, SVG or the terminal.
+INPUT is a recording written by Azusa::save_to or a drawing in the text format,
+read from standard input if it is missing or `-`.
+
+Options:
+  -o, --output <PATH>      Output file, .png or .svg. Without it, the drawing is shown in the terminal
+      --width <PIXELS>     Width of the image, by default the width of the drawing
+      --height <PIXELS>    Height of the image, by default the height of the drawing
+  -s, --scale <FACTOR>     Scale factor, e.g. 2 for HiDPI images or 0.25 for small terminal output
+  -t, --terminal <MODE>    halfblock, sixel or kitty [default: halfblock]
+  -j, --threads <COUNT>    Threads rasterizing PNG images, 0 for one per CPU [default: 0]
+  -h, --help               Shows this message
+
+Text format:
+  color <name>                      Color of the following drawings
+  border <name>                     Border color of fill_rect
+  clear                             Fills the image and removes everything drawn before
+  move_to <x> <y>                   Position of the next drawing
+  fill_rect <width> <height>        Filled rectangle with a border
+  rect <thickness> <width> <height> Outlined rectangle
+  text <width> <height> <size> [italic] [underline] <text>
+  layer <name> [z <index>] [opacity <0-1>] [hidden]
+                                    Draws the following statements to a layer
+";
+
+struct Options {
+    input: Option<String>,
+    output: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    scale: f64,
+    terminal: TerminalMode,
+    threads: usize,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("azusa: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let Some(options) = parse_args(std::env::args().skip(1))? else {
+        print!("{}", USAGE);
+        return Ok(());
+    };
+
+    let mut source = vec![];
+    match options.input.as_deref() {
+        None | Some("-") => std::io::stdin().read_to_end(&mut source),
+        Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut source)),
+    }
+    .map_err(|e| format!("cannot read the input: {}", e))?;
+
+    let azusa = if source.starts_with(b"AZSA") {
+        Azusa::load_from(source.as_slice()).map_err(|e| format!("cannot read the recording: {}", e))?
+    } else {
+        let source = String::from_utf8(source).map_err(|_| "the input is neither a recording nor text".to_string())?;
+        dsl::parse(&source)?
+    };
+
+    let (content_width, content_height) = content_size(azusa.get_ctx());
+    let width = options.width.unwrap_or(content_width).max(1);
+    let height = options.height.unwrap_or(content_height).max(1);
+    if width as f64 * options.scale > MAX_SIZE as f64 || height as f64 * options.scale > MAX_SIZE as f64 {
+        return Err(format!(
+            "the image would be {}x{} pixels, larger than {} pixels on a side; use --width, --height or --scale",
+            width, height, MAX_SIZE
+        ));
+    }
+
+    match options.output.as_deref() {
+        Some(path) if path.ends_with(".png") => {
+            let name = path.trim_end_matches(".png");
+            let mut surface = ImageSurface::new(width as f64, height as f64, name, ImageType::Png);
+            surface.set_scale_factor(options.scale);
+            surface.set_thread_count(options.threads);
+            surface.save(azusa.get_ctx()).map_err(|e| format!("cannot write {}: {}", path, e))?;
+        }
+        Some(path) if path.ends_with(".svg") => {
+            let mut surface = SvgSurface::new(width as f64, height as f64);
+            surface.set_scale_factor(options.scale);
+            azusa.draw(&mut surface);
+            surface.save(path).map_err(|e| format!("cannot write {}: {}", path, e))?;
+        }
+        Some(path) => return Err(format!("unknown output format of {}, use .png or .svg", path)),
+        None => {
+            let width = (width as f64 * options.scale).ceil() as u32;
+            let height = (height as f64 * options.scale).ceil() as u32;
+            // A half block cell shows 1x2 pixels, the graphics protocols use cells of 8x16 pixels
+            let (cols, rows) = match options.terminal {
+                TerminalMode::HalfBlock => (width, height.div_ceil(2)),
+                TerminalMode::Sixel | TerminalMode::Kitty => (width.div_ceil(8), height.div_ceil(16)),
+            };
+            let mut surface = TerminalSurface::new(std::io::stdout().lock(), cols, rows, options.terminal);
+            let ctx: Vec<DrawTarget> = azusa.get_ctx().iter().map(|i| i.scale(options.scale)).collect();
+            surface.draw(&ctx);
+            surface.into_inner().map(drop).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses the command line, returns None if the usage should be shown
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        input: None,
+        output: None,
+        width: None,
+        height: None,
+        scale: 1.0,
+        terminal: TerminalMode::HalfBlock,
+        threads: 0,
+    };
+
+    while let Some(arg) = args.next() {
+        // --name=value is the same as --name value
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or(format!("{} needs a value", name))
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => options.output = Some(value()?),
+            "--width" => options.width = Some(number(&name, &value()?)?),
+            "--height" => options.height = Some(number(&name, &value()?)?),
+            "-j" | "--threads" => options.threads = number(&name, &value()?)? as usize,
+            "-s" | "--scale" => {
+                let scale = value()?;
+                options.scale = match scale.parse::<f64>() {
+                    Ok(scale) if scale > 0.0 && scale.is_finite() => scale,
+                    _ => return Err(format!("--scale needs a positive number, not {}", scale)),
+                };
+            }
+            "-t" | "--terminal" => {
+                options.terminal = match value()?.as_str() {
+                    "halfblock" => TerminalMode::HalfBlock,
+                    "sixel" => TerminalMode::Sixel,
+                    "kitty" => TerminalMode::Kitty,
+                    mode => return Err(format!("unknown terminal mode {}", mode)),
+                };
+            }
+            name if name.starts_with('-') && name != "-" => {
+                return Err(format!("unknown option {}, see --help", name));
+            }
+            _ if options.input.is_none() => options.input = Some(arg),
+            _ => return Err("only one input can be given".to_string()),
+        }
+    }
+    Ok(Some(options))
+}
+
+fn number(name: &str, value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("{} needs a number, not {}", name, value))
+}
+
+/// Size that covers everything in the context, u32::MAX if something reaches beyond it
+fn content_size(ctx: &[DrawTarget]) -> (u32, u32) {
+    ctx.iter()
+        .filter_map(|i| i.bounds())
+        .fold((0, 0), |(width, height), rect| (width.max(rect.right()), height.max(rect.bottom())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use azusa::Color;
+
+    #[test]
+    fn content_size_covers_every_command() {
+        let ctx = [
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Red, 10, 20, 30, 40),
+            DrawTarget::DrawRectangle(Color::Red, 2, 50, 0, 10, 10),
+        ];
+        assert_eq!(content_size(&ctx), (60, 60));
+        assert_eq!(content_size(&[]), (0, 0));
+    }
+
+    #[test]
+    fn content_size_does_not_overflow() {
+        let ctx = [DrawTarget::FillRectangle(Color::Red, Color::Red, u32::MAX, 0, 10, 10)];
+        assert_eq!(content_size(&ctx), (u32::MAX, 10));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let args = ["-o", "out.png", "--width=20", "--height", "30", "-s", "2", "-t", "kitty", "-j", "4", "in.txt"];
+        let options = parse_args(args.iter().map(|i| i.to_string())).unwrap().unwrap();
+        assert_eq!(options.output.as_deref(), Some("out.png"));
+        assert_eq!((options.width, options.height), (Some(20), Some(30)));
+        assert_eq!(options.scale, 2.0);
+        assert_eq!(options.terminal, TerminalMode::Kitty);
+        assert_eq!(options.threads, 4);
+        assert_eq!(options.input.as_deref(), Some("in.txt"));
+
+        assert!(parse_args(["--help".to_string()].into_iter()).unwrap().is_none());
+        let error = |args: &[&str]| parse_args(args.iter().map(|i| i.to_string())).err().unwrap();
+        assert_eq!(error(&["--scale", "0"]), "--scale needs a positive number, not 0");
+        assert_eq!(error(&["--width"]), "--width needs a value");
+        assert_eq!(error(&["--bogus"]), "unknown option --bogus, see --help");
+        assert_eq!(error(&["a", "b"]), "only one input can be given");
+    }
+}
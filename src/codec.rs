@@ -0,0 +1,293 @@
+//! Compact binary encoding of a command list.
+//! Every command starts with an opcode byte, numbers are little endian and colors are stored as their index.
+
+use crate::{Color, DrawTarget, FontInfo, UString};
+
+use std::io;
+
+const CLEAR: u8 = 0;
+const FILL_RECTANGLE: u8 = 1;
+const DRAW_RECTANGLE: u8 = 2;
+const DRAW_TEXT: u8 = 3;
+const BEGIN_LAYER: u8 = 4;
+const END_LAYER: u8 = 5;
+
+const COLORS: [Color; 16] = [
+    Color::White,
+    Color::Olive,
+    Color::Yellow,
+    Color::Fuchsia,
+    Color::Silver,
+    Color::Aqua,
+    Color::Lime,
+    Color::Red,
+    Color::Gray,
+    Color::Blue,
+    Color::Green,
+    Color::Purple,
+    Color::Black,
+    Color::Navy,
+    Color::Teal,
+    Color::Maroon,
+];
+
+pub(crate) fn encode(ctx: &[DrawTarget]) -> Vec<u8> {
+    let mut output = vec![];
+    for i in ctx {
+        match i {
+            DrawTarget::Clear(color) => {
+                output.extend_from_slice(&[CLEAR, color_index(*color)]);
+            }
+            DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                output.extend_from_slice(&[FILL_RECTANGLE, color_index(*color), color_index(*border_color)]);
+                put_u32s(&mut output, &[*x, *y, *width, *height]);
+            }
+            DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                output.extend_from_slice(&[DRAW_RECTANGLE, color_index(*color)]);
+                put_u32s(&mut output, &[*thickness, *x, *y, *width, *height]);
+            }
+            DrawTarget::DrawText(color, info, x, y, width, height, string) => {
+                let flags = u8::from(info.1) | u8::from(info.2) << 1;
+                output.extend_from_slice(&[DRAW_TEXT, color_index(*color), flags]);
+                let text = string.as_utf16();
+                put_u32s(&mut output, &[info.0, *x, *y, *width, *height, text.len() as u32]);
+                for unit in text {
+                    output.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            DrawTarget::BeginLayer(opacity) => {
+                output.extend_from_slice(&[BEGIN_LAYER, *opacity]);
+            }
+            DrawTarget::EndLayer => output.push(END_LAYER),
+        }
+    }
+    output
+}
+
+pub(crate) fn decode(mut input: &[u8]) -> io::Result<Vec<DrawTarget>> {
+    let mut ctx = vec![];
+    while let Some((&opcode, rest)) = input.split_first() {
+        input = rest;
+        let command = match opcode {
+            CLEAR => DrawTarget::Clear(read_color(&mut input)?),
+            FILL_RECTANGLE => {
+                let (color, border_color) = (read_color(&mut input)?, read_color(&mut input)?);
+                let [x, y, width, height] = read_u32s(&mut input)?;
+                DrawTarget::FillRectangle(color, border_color, x, y, width, height)
+            }
+            DRAW_RECTANGLE => {
+                let color = read_color(&mut input)?;
+                let [thickness, x, y, width, height] = read_u32s(&mut input)?;
+                DrawTarget::DrawRectangle(color, thickness, x, y, width, height)
+            }
+            DRAW_TEXT => {
+                let color = read_color(&mut input)?;
+                let [flags] = read_bytes(&mut input)?;
+                let [px, x, y, width, height, length] = read_u32s(&mut input)?;
+                let bytes = take(&mut input, length as usize * 2)?;
+                let text = bytes
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect::<Vec<_>>();
+                let info = FontInfo::new(px, flags & 1 != 0, flags & 2 != 0);
+                DrawTarget::DrawText(color, info, x, y, width, height, UString::from_utf16(&text))
+            }
+            BEGIN_LAYER => {
+                let [opacity] = read_bytes(&mut input)?;
+                DrawTarget::BeginLayer(opacity)
+            }
+            END_LAYER => DrawTarget::EndLayer,
+            opcode => return Err(invalid(format!("Unknown opcode {}", opcode))),
+        };
+        ctx.push(command);
+    }
+    Ok(ctx)
+}
+
+fn color_index(color: Color) -> u8 {
+    COLORS.iter().position(|c| *c == color).unwrap_or(0) as u8
+}
+
+fn put_u32s(output: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn take<'a>(input: &mut &'a [u8], length: usize) -> io::Result<&'a [u8]> {
+    if input.len() < length {
+        return Err(invalid("Unexpected end of the command list".to_string()));
+    }
+    let (bytes, rest) = input.split_at(length);
+    *input = rest;
+    Ok(bytes)
+}
+
+fn read_bytes<const N: usize>(input: &mut &[u8]) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(take(input, N)?);
+    Ok(bytes)
+}
+
+fn read_u32s<const N: usize>(input: &mut &[u8]) -> io::Result<[u32; N]> {
+    let mut values = [0; N];
+    for value in values.iter_mut() {
+        *value = u32::from_le_bytes(read_bytes(input)?);
+    }
+    Ok(values)
+}
+
+fn read_color(input: &mut &[u8]) -> io::Result<Color> {
+    let [index] = read_bytes(input)?;
+    COLORS
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| invalid(format!("Unknown color {}", index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Azusa, FORMAT_VERSION};
+
+    fn commands() -> Vec<DrawTarget> {
+        vec![
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Navy, 1, 2, 30, 40),
+            DrawTarget::BeginLayer(128),
+            DrawTarget::DrawRectangle(Color::Silver, 3, 5, 6, 70, 80),
+            DrawTarget::EndLayer,
+            DrawTarget::DrawText(Color::Maroon, FontInfo::new(14, true, false), 9, 10, 200, 20, UString::new("Hé 日本")),
+        ]
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let ctx = commands();
+        assert_eq!(decode(&encode(&ctx)).unwrap(), ctx);
+    }
+
+    #[test]
+    fn every_color_round_trips() {
+        let ctx: Vec<DrawTarget> = COLORS.iter().map(|&color| DrawTarget::Clear(color)).collect();
+        assert_eq!(decode(&encode(&ctx)).unwrap(), ctx);
+    }
+
+    #[test]
+    fn recording_round_trips() {
+        let azusa = Azusa::from_ctx(commands());
+        let mut recording = vec![];
+        azusa.save_to(&mut recording).unwrap();
+        assert_eq!(&recording[..4], b"AZSA");
+        assert_eq!(u16::from_le_bytes([recording[4], recording[5]]), FORMAT_VERSION);
+
+        let loaded = Azusa::load_from(recording.as_slice()).unwrap();
+        assert_eq!(loaded.get_ctx(), commands().as_slice());
+    }
+
+    #[test]
+    fn rectangles_keep_their_thickness() {
+        let mut azusa = Azusa::new();
+        azusa.set_source_color(Color::Navy);
+        azusa.move_to(3, 4);
+        azusa.draw_rectangle(2, 30, 40);
+        let expected = [DrawTarget::DrawRectangle(Color::Navy, 2, 3, 4, 30, 40)];
+        assert_eq!(azusa.get_ctx(), &expected);
+
+        let mut recording = vec![];
+        azusa.save_to(&mut recording).unwrap();
+        assert_eq!(Azusa::load_from(recording.as_slice()).unwrap().get_ctx(), &expected);
+    }
+
+    /// The messages of web::to_message and web::from_message. Only their conversion to and from a Uint8Array
+    /// needs a JavaScript engine, so it is not tested here.
+    #[test]
+    fn worker_messages_round_trip() {
+        let mut azusa = Azusa::new();
+        azusa.move_to(1, 2);
+        azusa.fill_rectangle(10, 20);
+        azusa.layer("overlay").set_opacity(0.5);
+        azusa.draw_rectangle(2, 30, 40);
+
+        let message = encode(azusa.get_ctx());
+        let received = Azusa::from_ctx(decode(&message).unwrap());
+        // The layers arrive flattened, the translucent one wrapped in BeginLayer and EndLayer
+        assert!(azusa.get_ctx().contains(&DrawTarget::EndLayer));
+        assert_eq!(received.get_ctx(), azusa.get_ctx());
+        assert_eq!(received.layers().len(), 1);
+    }
+
+    #[test]
+    fn recordings_follow_each_other() {
+        let mut recording = vec![];
+        Azusa::from_ctx(commands()).save_to(&mut recording).unwrap();
+        Azusa::from_ctx(vec![DrawTarget::Clear(Color::Lime)]).save_to(&mut recording).unwrap();
+
+        let mut reader = recording.as_slice();
+        assert_eq!(Azusa::load_from(&mut reader).unwrap().get_ctx(), commands().as_slice());
+        assert_eq!(Azusa::load_from(&mut reader).unwrap().get_ctx(), &[DrawTarget::Clear(Color::Lime)]);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn older_versions_are_read() {
+        let mut recording = vec![];
+        Azusa::from_ctx(commands()).save_to(&mut recording).unwrap();
+        recording[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(Azusa::load_from(recording.as_slice()).unwrap().get_ctx(), commands().as_slice());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut recording = vec![];
+        Azusa::from_ctx(commands()).save_to(&mut recording).unwrap();
+        recording[0] = b'X';
+        let error = Azusa::load_from(recording.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Not an Azusa recording");
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        for version in [0, FORMAT_VERSION + 1] {
+            let mut recording = vec![];
+            Azusa::from_ctx(commands()).save_to(&mut recording).unwrap();
+            recording[4..6].copy_from_slice(&version.to_le_bytes());
+            let error = Azusa::load_from(recording.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), format!("Unsupported recording version {}", version));
+        }
+    }
+
+    #[test]
+    fn truncated_recordings_are_rejected() {
+        let mut recording = vec![];
+        Azusa::from_ctx(commands()).save_to(&mut recording).unwrap();
+        recording.pop();
+        let error = Azusa::load_from(recording.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A command cut off inside of the payload
+        let payload = encode(&commands());
+        assert!(decode(&payload[..payload.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn unknown_opcodes_and_colors_are_rejected() {
+        assert_eq!(decode(&[42]).unwrap_err().to_string(), "Unknown opcode 42");
+        assert_eq!(decode(&[CLEAR, 16]).unwrap_err().to_string(), "Unknown color 16");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        let json = serde_json::to_string(&commands()).unwrap();
+        assert!(json.contains("\"Hé 日本\""), "text is stored as a string: {}", json);
+        let ctx: Vec<DrawTarget> = serde_json::from_str(&json).unwrap();
+        assert_eq!(ctx, commands());
+    }
+}
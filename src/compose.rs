@@ -0,0 +1,453 @@
+use crate::raster::outline;
+use crate::{Color, DrawTarget, Rect, Surface};
+
+/// Surface that draws every context to several surfaces, e.g. a window and a PNG screenshot of it.
+/// Surfaces can be added by value or as mutable references.
+/// The size and scale factor reported are the ones of the first surface, the other surfaces draw the same
+/// logical coordinates, so they should have the same logical size or be wrapped in a ScaledSurface or ClipSurface.
+#[derive(Default)]
+pub struct MultiSurface<'a> {
+    surfaces: Vec<Box<dyn Surface + 'a>>,
+}
+
+impl<'a> MultiSurface<'a> {
+    pub fn new() -> Self {
+        Self { surfaces: vec![] }
+    }
+
+    /// Adds a surface, builder style
+    pub fn with(mut self, surface: impl Surface + 'a) -> Self {
+        self.push(surface);
+        self
+    }
+
+    /// Adds a surface
+    pub fn push(&mut self, surface: impl Surface + 'a) {
+        self.surfaces.push(Box::new(surface));
+    }
+
+    pub fn len(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+}
+
+impl Surface for MultiSurface<'_> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        for surface in &mut self.surfaces {
+            surface.draw(ctx);
+        }
+    }
+
+    /// Surfaces that cannot keep their contents draw everything, as usual
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        for surface in &mut self.surfaces {
+            surface.draw_damaged(ctx, damage);
+        }
+    }
+
+    /// Size of the first surface, which Azusa::present compares to tell whether everything has to be repainted
+    fn get_client_size(&self) -> (u32, u32) {
+        self.surfaces.first().map_or((0, 0), |i| i.get_client_size())
+    }
+
+    /// Scale factor of the first surface
+    fn scale_factor(&self) -> f64 {
+        self.surfaces.first().map_or(1.0, |i| i.scale_factor())
+    }
+}
+
+impl<S: Surface + ?Sized> Surface for &mut S {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        (**self).draw(ctx);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        (**self).draw_damaged(ctx, damage);
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (**self).get_client_size()
+    }
+
+    fn scale_factor(&self) -> f64 {
+        (**self).scale_factor()
+    }
+}
+
+/// Surface that moves everything by x, y in logical pixels before drawing it to another surface.
+/// Clears become fills of the part of the other surface right and below the offset,
+/// so a translucent clear color is blended over the pixels instead of replacing them.
+pub struct OffsetSurface<S> {
+    inner: S,
+    x: u32,
+    y: u32,
+}
+
+impl<S: Surface> OffsetSurface<S> {
+    pub fn new(inner: S, x: u32, y: u32) -> Self {
+        Self { inner, x, y }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn offset_ctx(&self, ctx: &[DrawTarget]) -> Vec<DrawTarget> {
+        let (width, height) = self.get_client_size();
+        ctx.iter()
+            .map(|i| match *i {
+                DrawTarget::Clear(color) => solid(color, Rect::new(self.x, self.y, width, height)),
+                _ => i.translate(self.x, self.y),
+            })
+            .collect()
+    }
+}
+
+impl<S: Surface> Surface for OffsetSurface<S> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        let ctx = self.offset_ctx(ctx);
+        self.inner.draw(&ctx);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        let ctx = self.offset_ctx(ctx);
+        let damage: Vec<Rect> = damage
+            .iter()
+            .map(|i| Rect::new(i.x.saturating_add(self.x), i.y.saturating_add(self.y), i.width, i.height))
+            .collect();
+        self.inner.draw_damaged(&ctx, &damage);
+    }
+
+    /// Part of the other surface right and below the offset
+    fn get_client_size(&self) -> (u32, u32) {
+        let (width, height) = self.inner.get_client_size();
+        (width.saturating_sub(self.x), height.saturating_sub(self.y))
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.inner.scale_factor()
+    }
+}
+
+/// Surface that scales everything before drawing it to another surface, like DrawTarget::scale.
+/// The scale of the other surface is applied on top of it.
+pub struct ScaledSurface<S> {
+    inner: S,
+    scale: f64,
+}
+
+impl<S: Surface> ScaledSurface<S> {
+    pub fn new(inner: S, scale: f64) -> Self {
+        Self { inner, scale }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Surface> Surface for ScaledSurface<S> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        let ctx: Vec<DrawTarget> = ctx.iter().map(|i| i.scale(self.scale)).collect();
+        self.inner.draw(&ctx);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        let ctx: Vec<DrawTarget> = ctx.iter().map(|i| i.scale(self.scale)).collect();
+        let damage: Vec<Rect> = damage.iter().map(|i| i.scale(self.scale)).collect();
+        self.inner.draw_damaged(&ctx, &damage);
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        let (width, height) = self.inner.get_client_size();
+        ((width as f64 / self.scale).round() as u32, (height as f64 / self.scale).round() as u32)
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.inner.scale_factor()
+    }
+}
+
+/// Surface that only draws inside of a rectangle in logical pixels of another surface.
+/// Commands crossing the edge of the rectangle are split into the solid rectangles they paint,
+/// so the clip works with every surface, even those that cannot clip themselves.
+/// Text crossing the edge is left out, surfaces lay it out in its whole box, so it cannot be cut into a smaller one.
+/// Clears become fills of the rectangle, so a translucent clear color is blended over the pixels instead of replacing them.
+pub struct ClipSurface<S> {
+    inner: S,
+    clip: Rect,
+}
+
+impl<S: Surface> ClipSurface<S> {
+    pub fn new(inner: S, clip: Rect) -> Self {
+        Self { inner, clip }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Surface> Surface for ClipSurface<S> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.inner.draw(&clip_ctx(ctx, &self.clip));
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        let damage: Vec<Rect> = damage.iter().filter_map(|i| i.intersection(&self.clip)).collect();
+        if damage.is_empty() {
+            return;
+        }
+        self.inner.draw_damaged(&clip_ctx(ctx, &self.clip), &damage);
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        self.inner.get_client_size()
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.inner.scale_factor()
+    }
+}
+
+/// Cuts the commands of a context to a rectangle
+fn clip_ctx(ctx: &[DrawTarget], clip: &Rect) -> Vec<DrawTarget> {
+    let mut clipped = Vec::with_capacity(ctx.len());
+    for command in ctx {
+        let bounds = match command.bounds() {
+            Some(bounds) if bounds.is_empty() => continue,
+            Some(bounds) => bounds,
+            None => {
+                match *command {
+                    DrawTarget::Clear(color) => clipped.push(solid(color, *clip)),
+                    _ => clipped.push(command.clone()),
+                }
+                continue;
+            }
+        };
+        let Some(visible) = bounds.intersection(clip) else {
+            continue;
+        };
+        if visible == bounds {
+            clipped.push(command.clone());
+            continue;
+        }
+
+        let mut push = |rect: Rect, color| {
+            if let Some(rect) = rect.intersection(clip) {
+                clipped.push(solid(color, rect));
+            }
+        };
+        match *command {
+            DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                outline(1, bounds, |rect| push(rect, border_color));
+                if width > 2 && height > 2 {
+                    push(Rect::new(x.saturating_add(1), y.saturating_add(1), width - 2, height - 2), color);
+                }
+            }
+            DrawTarget::DrawRectangle(color, thickness, ..) => outline(thickness, bounds, |rect| push(rect, color)),
+            DrawTarget::DrawText(..) | DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+        }
+    }
+    clipped
+}
+
+/// Fill painting every pixel of a rectangle with a color
+fn solid(color: Color, rect: Rect) -> DrawTarget {
+    DrawTarget::FillRectangle(color, color, rect.x, rect.y, rect.width, rect.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::Pixmap;
+    use crate::recording::RecordingSurface;
+    use crate::{FontInfo, UString};
+
+    /// Surface remembering the last context and damage drawn to it
+    struct Probe {
+        ctx: Vec<DrawTarget>,
+        damage: Option<Vec<Rect>>,
+    }
+
+    impl Probe {
+        fn new() -> Self {
+            Self { ctx: vec![], damage: None }
+        }
+    }
+
+    impl Surface for Probe {
+        fn draw(&mut self, ctx: &[DrawTarget]) {
+            self.ctx = ctx.to_vec();
+            self.damage = None;
+        }
+
+        fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+            self.ctx = ctx.to_vec();
+            self.damage = Some(damage.to_vec());
+        }
+
+        fn get_client_size(&self) -> (u32, u32) {
+            (100, 50)
+        }
+
+        fn scale_factor(&self) -> f64 {
+            2.0
+        }
+    }
+
+    fn fill(color: Color, border_color: Color, x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, border_color, x, y, width, height)
+    }
+
+    fn text(x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::DrawText(Color::Black, FontInfo::new(10, false, false), x, y, width, height, UString::new("text"))
+    }
+
+    #[test]
+    fn multi_surface_draws_to_every_surface() {
+        let ctx = [DrawTarget::Clear(Color::White), fill(Color::Red, Color::Blue, 2, 2, 5, 5)];
+        let mut recording = RecordingSurface::new(10, 10);
+        let mut pixmap = Pixmap::new(10, 10);
+        let mut surface = MultiSurface::new().with(&mut recording).with(&mut pixmap);
+        assert_eq!(surface.len(), 2);
+        assert_eq!(surface.get_client_size(), (10, 10));
+        surface.draw(&ctx);
+        drop(surface);
+
+        let mut expected = Pixmap::new(10, 10);
+        expected.draw(&ctx);
+        assert_eq!(recording.get_ctx(), ctx);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+
+        let surface = MultiSurface::new();
+        assert!(surface.is_empty());
+        assert_eq!((surface.get_client_size(), surface.scale_factor()), ((0, 0), 1.0));
+    }
+
+    #[test]
+    fn offset_surface_moves_commands_and_damage() {
+        let mut surface = OffsetSurface::new(Probe::new(), 10, 20);
+        assert_eq!(surface.get_client_size(), (90, 30));
+        assert_eq!(surface.scale_factor(), 2.0);
+        surface.draw_damaged(&[fill(Color::Red, Color::Red, 1, 2, 3, 4)], &[Rect::new(1, 2, 3, 4)]);
+        let probe = surface.into_inner();
+        assert_eq!(probe.ctx, [fill(Color::Red, Color::Red, 11, 22, 3, 4)]);
+        assert_eq!(probe.damage, Some(vec![Rect::new(11, 22, 3, 4)]));
+    }
+
+    #[test]
+    fn offset_surface_draws_into_pixmaps() {
+        let ctx = [fill(Color::Red, Color::Blue, 0, 0, 4, 4)];
+        let mut pixmap = Pixmap::new(10, 10);
+        OffsetSurface::new(&mut pixmap, 3, 5).draw(&ctx);
+        let mut expected = Pixmap::new(10, 10);
+        expected.draw(&[fill(Color::Red, Color::Blue, 3, 5, 4, 4)]);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn offset_surface_clears_only_its_part() {
+        let mut surface = OffsetSurface::new(Probe::new(), 10, 20);
+        surface.draw(&[DrawTarget::Clear(Color::Lime)]);
+        assert_eq!(surface.get_mut().ctx, [fill(Color::Lime, Color::Lime, 10, 20, 90, 30)]);
+
+        let mut pixmap = Pixmap::new(6, 6);
+        pixmap.draw(&[DrawTarget::Clear(Color::Red)]);
+        OffsetSurface::new(&mut pixmap, 2, 3).draw_damaged(&[DrawTarget::Clear(Color::Silver)], &[Rect::new(0, 0, 4, 3)]);
+        let mut expected = Pixmap::new(6, 6);
+        expected.draw(&[DrawTarget::Clear(Color::Red), fill(Color::Silver, Color::Silver, 2, 3, 4, 3)]);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+        assert_eq!(pixmap.pixel(1, 3), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn scaled_surface_scales_commands_and_damage() {
+        let mut surface = ScaledSurface::new(Probe::new(), 2.0);
+        assert_eq!(surface.get_client_size(), (50, 25));
+        surface.draw_damaged(&[fill(Color::Red, Color::Red, 1, 2, 3, 4)], &[Rect::new(1, 2, 3, 4)]);
+        let probe = surface.into_inner();
+        assert_eq!(probe.ctx, [fill(Color::Red, Color::Red, 2, 4, 6, 8)]);
+        assert_eq!(probe.damage, Some(vec![Rect::new(2, 4, 6, 8)]));
+    }
+
+    #[test]
+    fn scaled_surface_draws_into_pixmaps() {
+        let mut pixmap = Pixmap::new(10, 10);
+        ScaledSurface::new(&mut pixmap, 2.0).draw(&[fill(Color::Red, Color::Blue, 1, 1, 3, 3)]);
+        let mut expected = Pixmap::new(10, 10);
+        expected.draw(&[fill(Color::Red, Color::Blue, 1, 1, 3, 3).scale(2.0)]);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn clip_cuts_fill_borders() {
+        let ctx = [
+            DrawTarget::Clear(Color::White),
+            fill(Color::Red, Color::Blue, 2, 2, 10, 10),
+            DrawTarget::DrawRectangle(Color::Lime, 2, 0, 6, 16, 6),
+        ];
+        let clip = Rect::new(4, 0, 6, 8);
+        let mut pixmap = Pixmap::new(16, 16);
+        ClipSurface::new(&mut pixmap, clip).draw(&ctx);
+
+        // Same pixels as a pixmap clipping itself
+        let mut expected = Pixmap::new(16, 16);
+        expected.set_clip(Some(clip));
+        expected.draw(&ctx);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+        assert_eq!(pixmap.pixel(3, 2), Some([0, 0, 0, 0]));
+        assert_eq!(pixmap.pixel(4, 2), Some([0, 0, 255, 255]));
+        assert_eq!(pixmap.pixel(4, 3), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn clip_blends_translucent_clears() {
+        let mut pixmap = Pixmap::new(4, 4);
+        pixmap.draw(&[DrawTarget::Clear(Color::Red)]);
+        ClipSurface::new(&mut pixmap, Rect::new(0, 0, 2, 4)).draw(&[DrawTarget::Clear(Color::Silver)]);
+
+        let mut expected = Pixmap::new(4, 4);
+        expected.draw(&[DrawTarget::Clear(Color::Red), fill(Color::Silver, Color::Silver, 0, 0, 2, 4)]);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+        assert_eq!(pixmap.pixel(3, 0), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn clip_drops_text_crossing_its_edge() {
+        let mut surface = ClipSurface::new(Probe::new(), Rect::new(0, 0, 50, 50));
+        surface.draw(&[text(10, 10, 20, 10), text(40, 10, 20, 10), text(60, 10, 20, 10)]);
+        assert_eq!(surface.into_inner().ctx, [text(10, 10, 20, 10)]);
+    }
+
+    #[test]
+    fn clip_limits_damage() {
+        let ctx = [fill(Color::Red, Color::Red, 0, 0, 30, 30)];
+        let mut surface = ClipSurface::new(Probe::new(), Rect::new(10, 10, 10, 10));
+        surface.draw_damaged(&ctx, &[Rect::new(0, 0, 15, 15)]);
+        assert_eq!(surface.get_mut().damage, Some(vec![Rect::new(10, 10, 5, 5)]));
+        assert_eq!(surface.get_mut().ctx, [fill(Color::Red, Color::Red, 10, 10, 10, 10)]);
+
+        // Damage outside of the clip draws nothing
+        *surface.get_mut() = Probe::new();
+        surface.draw_damaged(&ctx, &[Rect::new(25, 25, 5, 5)]);
+        assert_eq!(surface.get_mut().ctx, []);
+        assert_eq!(surface.get_mut().damage, None);
+    }
+}
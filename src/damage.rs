@@ -0,0 +1,225 @@
+use crate::{DrawTarget, Rect};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// More regions than this are merged into one, repainting a few extra pixels is cheaper than many passes
+const MAX_REGIONS: usize = 8;
+
+/// Parts of a surface that have to be repainted after the context changed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Damage {
+    /// Everything has to be repainted, e.g. because the clear color changed
+    Full,
+    /// Only these regions in logical pixels changed. Empty if nothing changed.
+    Regions(Vec<Rect>),
+}
+
+/// Compares the context shown on a surface with a new one.
+/// Commands both contexts start or end with are unchanged, the bounds of everything in between are damaged,
+/// both where the old commands were and where the new ones are.
+pub fn diff(old: &[DrawTarget], new: &[DrawTarget]) -> Damage {
+    diff_by(old, new, DrawTarget::bounds)
+}
+
+/// What Azusa::present keeps of a command to compare it with the next frame, without copying its text
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Summary {
+    hash: u64,
+    bounds: Option<Rect>,
+}
+
+impl Summary {
+    fn new(command: &DrawTarget) -> Self {
+        let mut hasher = DefaultHasher::new();
+        command.hash(&mut hasher);
+        Self {
+            hash: hasher.finish(),
+            bounds: command.bounds(),
+        }
+    }
+}
+
+/// Summaries of the commands of a context, written into a buffer so it can be reused for every frame
+pub(crate) fn summarize(ctx: &[DrawTarget], summaries: &mut Vec<Summary>) {
+    summaries.clear();
+    summaries.extend(ctx.iter().map(Summary::new));
+}
+
+/// Like diff, for summaries of the contexts.
+/// Two different commands with the same hash and bounds would be taken as unchanged, which 64 bit hashes make unlikely.
+pub(crate) fn diff_summaries(old: &[Summary], new: &[Summary]) -> Damage {
+    diff_by(old, new, |i| i.bounds)
+}
+
+fn diff_by<T: PartialEq>(old: &[T], new: &[T], bounds: impl Fn(&T) -> Option<Rect>) -> Damage {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut regions: Vec<Rect> = vec![];
+    for i in old[prefix..old.len() - suffix].iter().chain(&new[prefix..new.len() - suffix]) {
+        match bounds(i) {
+            // A clear covers the whole surface
+            None => return Damage::Full,
+            Some(rect) if rect.is_empty() => {}
+            Some(rect) => add_region(&mut regions, rect),
+        }
+    }
+
+    if regions.len() > MAX_REGIONS {
+        let rect = regions.iter().fold(regions[0], |a, b| a.union(b));
+        regions = vec![rect];
+    }
+    Damage::Regions(regions)
+}
+
+/// Adds a region, merging it with the regions it overlaps
+fn add_region(regions: &mut Vec<Rect>, mut rect: Rect) {
+    // A merged region can overlap regions that were checked before, so repeat until nothing overlaps
+    while let Some(index) = regions.iter().position(|i| i.intersects(&rect)) {
+        rect = rect.union(&regions.swap_remove(index));
+    }
+    regions.push(rect);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::Pixmap;
+    use crate::{Azusa, Color, Surface};
+
+    /// Surface remembering how it was drawn to last
+    struct Probe {
+        size: (u32, u32),
+        scale: f64,
+        drawn: Option<Damage>,
+    }
+
+    impl Probe {
+        fn new() -> Self {
+            Self {
+                size: (100, 100),
+                scale: 1.0,
+                drawn: None,
+            }
+        }
+    }
+
+    impl Surface for Probe {
+        fn draw(&mut self, _ctx: &[DrawTarget]) {
+            self.drawn = Some(Damage::Full);
+        }
+
+        fn draw_damaged(&mut self, _ctx: &[DrawTarget], damage: &[Rect]) {
+            self.drawn = Some(Damage::Regions(damage.to_vec()));
+        }
+
+        fn get_client_size(&self) -> (u32, u32) {
+            self.size
+        }
+
+        fn scale_factor(&self) -> f64 {
+            self.scale
+        }
+    }
+
+    fn fill(x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(Color::Red, Color::Red, x, y, width, height)
+    }
+
+    /// Draws a row of squares, the middle one moved down by offset
+    fn draw(azusa: &mut Azusa, offset: u32) {
+        azusa.layer("default").clear();
+        for i in 0..5 {
+            azusa.move_to(i * 20, if i == 2 { offset } else { 0 });
+            azusa.fill_rectangle(10, 10);
+        }
+    }
+
+    fn drawing() -> Azusa {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, 0);
+        azusa
+    }
+
+    #[test]
+    fn changed_command_damages_its_bounds() {
+        let old = [fill(0, 0, 10, 10), fill(20, 0, 10, 10), fill(40, 0, 10, 10)];
+        let new = [fill(0, 0, 10, 10), fill(20, 30, 10, 10), fill(40, 0, 10, 10)];
+        assert_eq!(
+            diff(&old, &new),
+            Damage::Regions(vec![Rect::new(20, 0, 10, 10), Rect::new(20, 30, 10, 10)])
+        );
+        assert_eq!(diff(&old, &old), Damage::Regions(vec![]));
+    }
+
+    #[test]
+    fn clears_damage_everything() {
+        let old = [fill(0, 0, 10, 10)];
+        let new = [DrawTarget::Clear(Color::White), fill(0, 0, 10, 10)];
+        assert_eq!(diff(&old, &new), Damage::Full);
+    }
+
+    #[test]
+    fn many_regions_are_merged() {
+        let old: Vec<DrawTarget> = (0..10).map(|i| fill(i * 20, 0, 10, 10)).collect();
+        let new: Vec<DrawTarget> = (0..10).map(|i| fill(i * 20, 20, 10, 10)).collect();
+        assert_eq!(diff(&old, &new), Damage::Regions(vec![Rect::new(0, 0, 190, 30)]));
+    }
+
+    #[test]
+    fn present_repaints_the_changed_command() {
+        let mut azusa = drawing();
+        let mut surface = Probe::new();
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), Some(Damage::Full));
+
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), None);
+
+        draw(&mut azusa, 50);
+        azusa.present(&mut surface);
+        assert_eq!(
+            surface.drawn.take(),
+            Some(Damage::Regions(vec![Rect::new(40, 0, 10, 10), Rect::new(40, 50, 10, 10)]))
+        );
+    }
+
+    #[test]
+    fn present_repaints_everything_when_the_surface_changes() {
+        let mut azusa = drawing();
+        let mut surface = Probe::new();
+        azusa.present(&mut surface);
+
+        surface.size = (200, 100);
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), Some(Damage::Full));
+
+        surface.scale = 2.0;
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), Some(Damage::Full));
+
+        azusa.invalidate();
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), Some(Damage::Full));
+        azusa.present(&mut surface);
+        assert_eq!(surface.drawn.take(), None);
+    }
+
+    #[test]
+    fn damaged_drawing_is_clipped_to_the_regions() {
+        let ctx = [DrawTarget::Clear(Color::White), fill(0, 0, 20, 20)];
+        let mut pixmap = Pixmap::new(20, 20);
+        pixmap.draw_damaged(&ctx, &[Rect::new(5, 5, 5, 5), Rect::new(15, 0, 5, 5)]);
+
+        let mut expected = Pixmap::new(20, 20);
+        expected.draw(&[fill(5, 5, 5, 5), fill(15, 0, 5, 5)]);
+        assert_eq!(pixmap.as_slice(), expected.as_slice());
+        assert_eq!(pixmap.clip(), None);
+    }
+}
@@ -0,0 +1,355 @@
+//! Minimal kernel mode setting with a dumb buffer, using the DRM ioctls directly
+
+use std::ffi::{c_ulong, c_void};
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+use std::ptr::null_mut;
+
+const fn iowr<T>(nr: c_ulong) -> c_ulong {
+    3 << 30 | (size_of::<T>() as c_ulong) << 16 | (b'd' as c_ulong) << 8 | nr
+}
+
+const DRM_IOCTL_MODE_GETRESOURCES: c_ulong = iowr::<CardRes>(0xA0);
+const DRM_IOCTL_MODE_GETCRTC: c_ulong = iowr::<Crtc>(0xA1);
+const DRM_IOCTL_MODE_SETCRTC: c_ulong = iowr::<Crtc>(0xA2);
+const DRM_IOCTL_MODE_GETENCODER: c_ulong = iowr::<Encoder>(0xA6);
+const DRM_IOCTL_MODE_GETCONNECTOR: c_ulong = iowr::<Connector>(0xA7);
+const DRM_IOCTL_MODE_ADDFB: c_ulong = iowr::<FbCmd>(0xAE);
+const DRM_IOCTL_MODE_RMFB: c_ulong = iowr::<u32>(0xAF);
+const DRM_IOCTL_MODE_CREATE_DUMB: c_ulong = iowr::<CreateDumb>(0xB2);
+const DRM_IOCTL_MODE_MAP_DUMB: c_ulong = iowr::<MapDumb>(0xB3);
+const DRM_IOCTL_MODE_DESTROY_DUMB: c_ulong = iowr::<u32>(0xB4);
+
+const DRM_MODE_CONNECTED: u32 = 1;
+
+/// struct drm_mode_card_res
+#[repr(C)]
+#[derive(Default)]
+struct CardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+/// struct drm_mode_modeinfo
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    mode_type: u32,
+    name: [u8; 32],
+}
+
+/// struct drm_mode_get_connector
+#[repr(C)]
+#[derive(Default)]
+struct Connector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+/// struct drm_mode_get_encoder
+#[repr(C)]
+#[derive(Default)]
+struct Encoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+/// struct drm_mode_crtc
+#[repr(C)]
+#[derive(Default)]
+struct Crtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: ModeInfo,
+}
+
+/// struct drm_mode_fb_cmd
+#[repr(C)]
+#[derive(Default)]
+struct FbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+/// struct drm_mode_create_dumb
+#[repr(C)]
+#[derive(Default)]
+struct CreateDumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64,
+}
+
+/// struct drm_mode_map_dumb
+#[repr(C)]
+#[derive(Default)]
+struct MapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+fn ioctl<T>(file: &File, request: c_ulong, arg: &mut T) -> io::Result<()> {
+    loop {
+        if unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+/// XRGB8888 dumb buffer that is scanned out on a display
+pub(crate) struct DumbBuffer {
+    file: File,
+    handle: u32,
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    stride: u32,
+    map: *mut c_void,
+    size: usize,
+
+    connector_id: u32,
+    /// CRTC state before the buffer was shown, restored when it is dropped
+    saved_crtc: Crtc,
+}
+
+impl DumbBuffer {
+    pub(crate) fn new(file: File) -> io::Result<Self> {
+        let mut res = CardRes::default();
+        ioctl(&file, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+        let mut crtcs = vec![0u32; res.count_crtcs as usize];
+        let mut connectors = vec![0u32; res.count_connectors as usize];
+        // Only the CRTCs and connectors are needed, the other lists are left empty
+        res = CardRes {
+            crtc_id_ptr: crtcs.as_mut_ptr() as u64,
+            count_crtcs: crtcs.len() as u32,
+            connector_id_ptr: connectors.as_mut_ptr() as u64,
+            count_connectors: connectors.len() as u32,
+            ..Default::default()
+        };
+        ioctl(&file, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+
+        let (connector_id, encoder_id, mode) = connectors
+            .iter()
+            .find_map(|id| connected_mode(&file, *id).transpose())
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, "No display is connected")))?;
+
+        let mut encoder = Encoder {
+            encoder_id,
+            ..Default::default()
+        };
+        if encoder_id != 0 {
+            ioctl(&file, DRM_IOCTL_MODE_GETENCODER, &mut encoder)?;
+        }
+        let crtc_id = match encoder.crtc_id {
+            0 => *crtcs
+                .first()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The device has no CRTC"))?,
+            id => id,
+        };
+        let mut saved_crtc = Crtc {
+            crtc_id,
+            ..Default::default()
+        };
+        ioctl(&file, DRM_IOCTL_MODE_GETCRTC, &mut saved_crtc)?;
+
+        let (width, height) = (mode.hdisplay as u32, mode.vdisplay as u32);
+        let mut create = CreateDumb {
+            width,
+            height,
+            bpp: 32,
+            ..Default::default()
+        };
+        ioctl(&file, DRM_IOCTL_MODE_CREATE_DUMB, &mut create)?;
+
+        // From here on, Drop releases whatever has been created
+        let mut buffer = Self {
+            file,
+            handle: create.handle,
+            fb_id: 0,
+            width,
+            height,
+            stride: create.pitch,
+            map: null_mut(),
+            size: create.size as usize,
+            connector_id,
+            saved_crtc,
+        };
+
+        let mut fb = FbCmd {
+            width,
+            height,
+            pitch: create.pitch,
+            bpp: 32,
+            depth: 24,
+            handle: create.handle,
+            ..Default::default()
+        };
+        ioctl(&buffer.file, DRM_IOCTL_MODE_ADDFB, &mut fb)?;
+        buffer.fb_id = fb.fb_id;
+
+        let mut map = MapDumb {
+            handle: create.handle,
+            ..Default::default()
+        };
+        ioctl(&buffer.file, DRM_IOCTL_MODE_MAP_DUMB, &mut map)?;
+        let address = unsafe {
+            libc::mmap(
+                null_mut(),
+                buffer.size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                buffer.file.as_raw_fd(),
+                map.offset as libc::off_t,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.map = address;
+
+        let mut connector_id = buffer.connector_id;
+        let mut crtc = Crtc {
+            set_connectors_ptr: &mut connector_id as *mut u32 as u64,
+            count_connectors: 1,
+            crtc_id,
+            fb_id: buffer.fb_id,
+            mode_valid: 1,
+            mode,
+            ..Default::default()
+        };
+        ioctl(&buffer.file, DRM_IOCTL_MODE_SETCRTC, &mut crtc)?;
+
+        Ok(buffer)
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub(crate) fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    /// The visible rows, without the padding the driver may add at the end of the buffer
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        let length = (self.stride as usize * self.height as usize).min(self.size);
+        unsafe { std::slice::from_raw_parts_mut(self.map as *mut u8, length) }
+    }
+}
+
+/// Returns the encoder and preferred mode of a connector if a display is connected to it
+fn connected_mode(file: &File, connector_id: u32) -> io::Result<Option<(u32, u32, ModeInfo)>> {
+    let mut connector = Connector {
+        connector_id,
+        ..Default::default()
+    };
+    // The first call probes the display and reports how many modes it has
+    ioctl(file, DRM_IOCTL_MODE_GETCONNECTOR, &mut connector)?;
+    if connector.connection != DRM_MODE_CONNECTED || connector.count_modes == 0 {
+        return Ok(None);
+    }
+
+    let mut modes = vec![ModeInfo::default(); connector.count_modes as usize];
+    connector = Connector {
+        connector_id,
+        modes_ptr: modes.as_mut_ptr() as u64,
+        count_modes: modes.len() as u32,
+        ..Default::default()
+    };
+    ioctl(file, DRM_IOCTL_MODE_GETCONNECTOR, &mut connector)?;
+    // The number of modes can change between the calls if the display is replugged
+    if connector.count_modes as usize > modes.len() {
+        return Ok(None);
+    }
+    modes.truncate(connector.count_modes as usize);
+
+    // Drivers list the preferred mode first
+    Ok(modes.first().map(|mode| (connector_id, connector.encoder_id, *mode)))
+}
+
+impl Drop for DumbBuffer {
+    fn drop(&mut self) {
+        let mut connector_id = self.connector_id;
+        if self.saved_crtc.mode_valid != 0 {
+            self.saved_crtc.set_connectors_ptr = &mut connector_id as *mut u32 as u64;
+            self.saved_crtc.count_connectors = 1;
+        }
+        let _ = ioctl(&self.file, DRM_IOCTL_MODE_SETCRTC, &mut self.saved_crtc);
+
+        unsafe {
+            if !self.map.is_null() {
+                libc::munmap(self.map, self.size);
+            }
+        }
+        if self.fb_id != 0 {
+            let _ = ioctl(&self.file, DRM_IOCTL_MODE_RMFB, &mut self.fb_id);
+        }
+        let _ = ioctl(&self.file, DRM_IOCTL_MODE_DESTROY_DUMB, &mut self.handle);
+    }
+}
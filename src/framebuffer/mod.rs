@@ -0,0 +1,344 @@
+mod drm;
+
+use crate::raster::Pixmap;
+use crate::{DrawTarget, Rect, Surface};
+
+use std::ffi::{c_char, c_ulong};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::MaybeUninit;
+use std::ops::Range;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+const FBIOGET_VSCREENINFO: c_ulong = 0x4600;
+const FBIOGET_FSCREENINFO: c_ulong = 0x4602;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// 16 bits per pixel, 5 bits of red in the high bits
+    Rgb565,
+    /// 32 bits per pixel, stored as B, G, R, X bytes
+    Xrgb8888,
+    /// 32 bits per pixel, stored as R, G, B, X bytes
+    Xbgr8888,
+    /// 24 bits per pixel, stored as B, G, R bytes
+    Rgb888,
+    /// 24 bits per pixel, stored as R, G, B bytes
+    Bgr888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Xrgb8888 | PixelFormat::Xbgr8888 => 4,
+            PixelFormat::Rgb888 | PixelFormat::Bgr888 => 3,
+        }
+    }
+
+    /// Format of a framebuffer with the bits per pixel and the bit offsets of red and blue
+    fn from_layout(bits_per_pixel: u32, red: u32, blue: u32) -> Option<Self> {
+        match (bits_per_pixel, red, blue) {
+            (16, 11, 0) => Some(PixelFormat::Rgb565),
+            (32, 16, 0) => Some(PixelFormat::Xrgb8888),
+            (32, 0, 16) => Some(PixelFormat::Xbgr8888),
+            (24, 16, 0) => Some(PixelFormat::Rgb888),
+            (24, 0, 16) => Some(PixelFormat::Bgr888),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+/// struct fb_var_screeninfo
+#[repr(C)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    rest: [u32; 20],
+}
+
+/// struct fb_fix_screeninfo
+#[repr(C)]
+struct FbFixScreeninfo {
+    id: [c_char; 16],
+    smem_start: c_ulong,
+    smem_len: u32,
+    fb_type: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+enum Target {
+    /// fbdev device or a regular file, written at an offset
+    File(File, u64),
+    /// Mapped DRM dumb buffer
+    Drm(drm::DumbBuffer),
+}
+
+/// Surface that draws directly to the screen without a window system,
+/// through a Linux framebuffer device or a DRM dumb buffer.
+/// Every frame is converted into a back buffer first and copied to the screen at once,
+/// so half drawn frames are never visible. There is no vsync, so tearing is still possible.
+pub struct FramebufferSurface {
+    target: Target,
+    format: PixelFormat,
+    stride: u32,
+
+    pixmap: Pixmap,
+    back: Vec<u8>,
+}
+
+impl FramebufferSurface {
+    /// Opens a framebuffer device like /dev/fb0 and queries its resolution and pixel format
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let (var, fix) = unsafe {
+            let mut var = MaybeUninit::<FbVarScreeninfo>::zeroed();
+            let mut fix = MaybeUninit::<FbFixScreeninfo>::zeroed();
+            if libc::ioctl(file.as_raw_fd(), FBIOGET_VSCREENINFO as _, var.as_mut_ptr()) < 0
+                || libc::ioctl(file.as_raw_fd(), FBIOGET_FSCREENINFO as _, fix.as_mut_ptr()) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            (var.assume_init(), fix.assume_init())
+        };
+
+        let format = match PixelFormat::from_layout(var.bits_per_pixel, var.red.offset, var.blue.offset) {
+            Some(format) => format,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "Unsupported pixel format: {} bits per pixel, red at bit {}, blue at bit {}",
+                        var.bits_per_pixel, var.red.offset, var.blue.offset
+                    ),
+                ))
+            }
+        };
+        // The visible area can be panned within the virtual resolution
+        let offset = var.yoffset as u64 * fix.line_length as u64 + var.xoffset as u64 * format.bytes_per_pixel() as u64;
+
+        Ok(Self::with_target(Target::File(file, offset), var.xres, var.yres, fix.line_length, format))
+    }
+
+    /// Uses a regular file as a framebuffer, e.g. to test without a screen.
+    /// Rows are stored without padding, the file is extended to the size of a frame if it is shorter.
+    pub fn from_file(path: impl AsRef<Path>, width: u32, height: u32, format: PixelFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let stride = width * format.bytes_per_pixel();
+        let size = stride as u64 * height as u64;
+        if file.metadata()?.len() < size {
+            file.set_len(size)?;
+        }
+
+        Ok(Self::with_target(Target::File(file, 0), width, height, stride, format))
+    }
+
+    /// Opens a DRM device like /dev/dri/card0 and shows a dumb buffer on the first connected display,
+    /// at the preferred mode of the display. The process has to be the DRM master.
+    pub fn open_drm(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let buffer = drm::DumbBuffer::new(file)?;
+        let (width, height, stride) = (buffer.width(), buffer.height(), buffer.stride());
+
+        Ok(Self::with_target(Target::Drm(buffer), width, height, stride, PixelFormat::Xrgb8888))
+    }
+
+    fn with_target(target: Target, width: u32, height: u32, stride: u32, format: PixelFormat) -> Self {
+        Self {
+            target,
+            format,
+            stride,
+            pixmap: Pixmap::new(width, height),
+            back: vec![0; stride as usize * height as usize],
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Converts rows of the pixmap into the back buffer
+    fn convert(&mut self, rows: Range<usize>) {
+        let width = self.pixmap.width() as usize;
+        let bytes = self.format.bytes_per_pixel() as usize;
+        let source = self.pixmap.as_slice().chunks_exact(width * 4);
+        let target = self.back.chunks_exact_mut(self.stride as usize);
+        for (row, line) in source.zip(target).skip(rows.start).take(rows.len()) {
+            for (source, target) in row.chunks_exact(4).zip(line[..width * bytes].chunks_exact_mut(bytes)) {
+                // There is nothing behind the screen, so transparent pixels become black
+                let alpha = source[3] as u32;
+                let [r, g, b] = [0, 1, 2].map(|i| ((source[i] as u32 * alpha + 127) / 255) as u8);
+                match self.format {
+                    PixelFormat::Rgb565 => {
+                        let pixel = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                        target.copy_from_slice(&pixel.to_le_bytes());
+                    }
+                    PixelFormat::Xrgb8888 => target.copy_from_slice(&[b, g, r, 255]),
+                    PixelFormat::Xbgr8888 => target.copy_from_slice(&[r, g, b, 255]),
+                    PixelFormat::Rgb888 => target.copy_from_slice(&[b, g, r]),
+                    PixelFormat::Bgr888 => target.copy_from_slice(&[r, g, b]),
+                }
+            }
+        }
+    }
+
+    /// Copies rows of the back buffer to the screen in one go
+    fn flip(&mut self, rows: Range<usize>) -> io::Result<()> {
+        let stride = self.stride as usize;
+        let bytes = rows.start * stride..rows.end * stride;
+        match &mut self.target {
+            Target::File(file, offset) => file.write_all_at(&self.back[bytes.clone()], *offset + bytes.start as u64),
+            Target::Drm(buffer) => {
+                buffer.as_mut_slice()[bytes.clone()].copy_from_slice(&self.back[bytes]);
+                Ok(())
+            }
+        }
+    }
+
+    fn update(&mut self, rows: Range<usize>) {
+        self.convert(rows.clone());
+        match self.flip(rows) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+            }
+        }
+    }
+}
+
+impl Surface for FramebufferSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.pixmap.draw(ctx);
+        self.update(0..self.pixmap.height() as usize);
+    }
+
+    /// Only the rows between the first and the last damaged region are written to the screen
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        self.pixmap.draw_damaged(ctx, damage);
+        let height = self.pixmap.height();
+        let top = damage.iter().map(|rect| rect.y).min().unwrap_or(0).min(height);
+        let bottom = damage.iter().map(|rect| rect.bottom()).max().unwrap_or(0).min(height);
+        if top < bottom {
+            self.update(top as usize..bottom as usize);
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.pixmap.width(), self.pixmap.height())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    use std::path::PathBuf;
+
+    /// File in the temporary directory that is removed afterwards
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("azusa-{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn fill(color: Color, x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, x, y, width, height)
+    }
+
+    /// A red pixel and a translucent silver one
+    fn frame(format: PixelFormat) -> Vec<u8> {
+        let file = TempFile::new(&format!("{:?}", format));
+        let mut surface = FramebufferSurface::from_file(&file.0, 2, 1, format).unwrap();
+        surface.draw(&[fill(Color::Red, 0, 0, 1, 1), fill(Color::Silver, 1, 0, 1, 1)]);
+        std::fs::read(&file.0).unwrap()
+    }
+
+    #[test]
+    fn rgb565() {
+        // Silver is 192 with an alpha of 192, so it is darkened to 145
+        assert_eq!(frame(PixelFormat::Rgb565), [0x00, 0xf8, 0x92, 0x94]);
+    }
+
+    #[test]
+    fn xrgb8888() {
+        assert_eq!(frame(PixelFormat::Xrgb8888), [0, 0, 255, 255, 145, 145, 145, 255]);
+    }
+
+    #[test]
+    fn xbgr8888() {
+        assert_eq!(frame(PixelFormat::Xbgr8888), [255, 0, 0, 255, 145, 145, 145, 255]);
+    }
+
+    #[test]
+    fn rgb888() {
+        assert_eq!(frame(PixelFormat::Rgb888), [0, 0, 255, 145, 145, 145]);
+    }
+
+    #[test]
+    fn bgr888() {
+        assert_eq!(frame(PixelFormat::Bgr888), [255, 0, 0, 145, 145, 145]);
+    }
+
+    #[test]
+    fn formats_of_the_channel_offsets() {
+        assert_eq!(PixelFormat::from_layout(32, 16, 0), Some(PixelFormat::Xrgb8888));
+        assert_eq!(PixelFormat::from_layout(32, 0, 16), Some(PixelFormat::Xbgr8888));
+        assert_eq!(PixelFormat::from_layout(24, 16, 0), Some(PixelFormat::Rgb888));
+        assert_eq!(PixelFormat::from_layout(24, 0, 16), Some(PixelFormat::Bgr888));
+        assert_eq!(PixelFormat::from_layout(16, 11, 0), Some(PixelFormat::Rgb565));
+        assert_eq!(PixelFormat::from_layout(16, 0, 11), None);
+        assert_eq!(PixelFormat::from_layout(8, 0, 0), None);
+    }
+
+    #[test]
+    fn only_damaged_rows_are_written() {
+        let file = TempFile::new("damage");
+        // Longer than a frame, the rest is left alone
+        std::fs::write(&file.0, [0xaa; 14]).unwrap();
+        let mut surface = FramebufferSurface::from_file(&file.0, 2, 2, PixelFormat::Rgb888).unwrap();
+        surface.draw_damaged(&[fill(Color::Red, 0, 0, 2, 2)], &[Rect::new(0, 1, 1, 1)]);
+
+        let expected = [[0xaa; 6].as_slice(), &[0, 0, 255, 0, 0, 0], &[0xaa; 2]].concat();
+        assert_eq!(std::fs::read(&file.0).unwrap(), expected);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::layer::{self, Layer};
+use crate::{DrawTarget, Rect, Vec4};
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::OnceLock;
+
+/// Width and height of the cells of the index in logical pixels
+const CELL_SIZE: u32 = 64;
+/// Commands covering more cells than this are kept in a single list instead of being added to every cell
+const MAX_CELLS: u64 = 256;
+
+/// Identifies a command of a context: the index of its layer in Azusa::layers
+/// and the index of the command in Layer::get_ctx
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CommandId {
+    pub layer: usize,
+    pub index: usize,
+}
+
+/// Command that can be hit, with the order it is drawn in
+struct Entry {
+    id: CommandId,
+    order: usize,
+    shape: Shape,
+}
+
+/// Pixels a command paints
+enum Shape {
+    Rect(Rect),
+    /// Outline of a rectangle, the border is inside of the rectangle like the rasterizer draws it
+    Outline(Rect, u32),
+}
+
+impl Shape {
+    fn new(command: &DrawTarget) -> Option<Shape> {
+        let shape = match *command {
+            DrawTarget::FillRectangle(_, _, x, y, width, height)
+            | DrawTarget::DrawText(_, _, x, y, width, height, _) => Shape::Rect(Rect::new(x, y, width, height)),
+            DrawTarget::DrawRectangle(_, 0, ..) => return None,
+            DrawTarget::DrawRectangle(_, thickness, x, y, width, height) => {
+                Shape::Outline(Rect::new(x, y, width, height), thickness)
+            }
+            DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => return None,
+        };
+        Some(shape).filter(|i| !i.bounds().is_empty())
+    }
+
+    fn bounds(&self) -> Rect {
+        match *self {
+            Shape::Rect(rect) | Shape::Outline(rect, _) => rect,
+        }
+    }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        match *self {
+            Shape::Rect(rect) => rect.contains(x, y),
+            Shape::Outline(rect, thickness) => {
+                let inner = Rect::new(
+                    rect.x.saturating_add(thickness),
+                    rect.y.saturating_add(thickness),
+                    rect.width.saturating_sub(thickness.saturating_mul(2)),
+                    rect.height.saturating_sub(thickness.saturating_mul(2)),
+                );
+                rect.contains(x, y) && !inner.contains(x, y)
+            }
+        }
+    }
+}
+
+/// Grid of the commands that are drawn, so a point only has to be tested against the commands near it
+pub(crate) struct Index {
+    entries: Vec<Entry>,
+    /// Entries touching each cell, in the order they are drawn
+    cells: HashMap<(u32, u32), Vec<usize>>,
+    /// Entries that are too large for the cells
+    large: Vec<usize>,
+}
+
+impl Index {
+    /// Indexes the visible layers in the order they are composited
+    fn new(layers: &[Layer]) -> Self {
+        let mut index = Self {
+            entries: vec![],
+            cells: HashMap::new(),
+            large: vec![],
+        };
+        let mut order = 0;
+        for (layer, i) in layer::visible(layers) {
+            // Translucent layers are blended as a group, like commands between BeginLayer and EndLayer
+            let mut depth = usize::from(i.alpha() != 255);
+            for (command_index, command) in i.get_ctx().iter().enumerate() {
+                match *command {
+                    DrawTarget::BeginLayer(_) => depth += 1,
+                    DrawTarget::EndLayer => depth = depth.saturating_sub(1),
+                    // Nothing drawn before an opaque clear can be seen, a clear in a group is blended over it
+                    DrawTarget::Clear(color) if depth == 0 && Vec4::from(color).3 == 255.0 => {
+                        index.entries.clear();
+                        index.cells.clear();
+                        index.large.clear();
+                    }
+                    _ => {}
+                }
+                if let Some(shape) = Shape::new(command) {
+                    let id = CommandId { layer, index: command_index };
+                    index.add(Entry { id, order, shape });
+                }
+                order += 1;
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, entry: Entry) {
+        let bounds = entry.shape.bounds();
+        let (left, top) = (bounds.x / CELL_SIZE, bounds.y / CELL_SIZE);
+        let (right, bottom) = ((bounds.right() - 1) / CELL_SIZE, (bounds.bottom() - 1) / CELL_SIZE);
+        let entry_index = self.entries.len();
+        self.entries.push(entry);
+
+        if (right - left + 1) as u64 * (bottom - top + 1) as u64 > MAX_CELLS {
+            self.large.push(entry_index);
+            return;
+        }
+        for y in top..=bottom {
+            for x in left..=right {
+                self.cells.entry((x, y)).or_default().push(entry_index);
+            }
+        }
+    }
+
+    /// Commands painting the pixel, the topmost first
+    pub(crate) fn hit_test(&self, x: u32, y: u32) -> Vec<CommandId> {
+        let cell = self.cells.get(&(x / CELL_SIZE, y / CELL_SIZE));
+        let mut hits: Vec<&Entry> = cell
+            .into_iter()
+            .flatten()
+            .chain(&self.large)
+            .map(|&i| &self.entries[i])
+            .filter(|i| i.shape.contains(x, y))
+            .collect();
+        hits.sort_by_key(|i| std::cmp::Reverse(i.order));
+        hits.into_iter().map(|i| i.id).collect()
+    }
+
+    /// Smallest rectangle covering everything that is drawn
+    pub(crate) fn bounds(&self) -> Option<Rect> {
+        self.entries.iter().map(|i| i.shape.bounds()).reduce(|a, b| a.union(&b))
+    }
+}
+
+/// Index of a context, built the first time it is queried and dropped when the context changes
+#[derive(Default)]
+pub(crate) struct Cache(OnceLock<Index>);
+
+impl Cache {
+    pub(crate) fn get(&self, layers: &[Layer]) -> &Index {
+        self.0.get_or_init(|| Index::new(layers))
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+// The cache is derived from the layers, so it does not take part in copies and comparisons of a context
+impl Clone for Cache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for Cache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Debug for Cache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cache")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Azusa, Color};
+
+    fn fill(azusa: &mut Azusa, x: u32, y: u32, width: u32, height: u32) -> CommandId {
+        azusa.move_to(x, y);
+        azusa.fill_rectangle(width, height);
+        let layer = azusa.layers().iter().position(|i| i.name() == "default").unwrap();
+        CommandId { layer, index: azusa.layers()[layer].get_ctx().len() - 1 }
+    }
+
+    fn id(layer: usize, index: usize) -> CommandId {
+        CommandId { layer, index }
+    }
+
+    #[test]
+    fn topmost_command_comes_first() {
+        let mut azusa = Azusa::new();
+        let bottom = fill(&mut azusa, 0, 0, 100, 100);
+        let top = fill(&mut azusa, 50, 50, 100, 100);
+        assert_eq!(azusa.hit_test(60, 60), [top, bottom]);
+        assert_eq!(azusa.hit_test(10, 10), [bottom]);
+        assert_eq!(azusa.hit_test(200, 200), []);
+
+        // Layers above come first, whatever order they were created in
+        azusa.layer("under").set_z_index(-1);
+        azusa.move_to(0, 0);
+        azusa.fill_rectangle(10, 10);
+        assert_eq!(azusa.hit_test(5, 5), [bottom, id(1, 0)]);
+    }
+
+    #[test]
+    fn outlines_are_hit_on_their_border() {
+        let mut azusa = Azusa::new();
+        azusa.move_to(10, 10);
+        azusa.draw_rectangle(2, 20, 20);
+        assert_eq!(azusa.hit_test(11, 20), [id(0, 0)]);
+        assert_eq!(azusa.hit_test(20, 20), []);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_hit() {
+        let mut azusa = Azusa::new();
+        let bottom = fill(&mut azusa, 0, 0, 10, 10);
+        azusa.layer("top");
+        azusa.fill_rectangle(10, 10);
+        assert_eq!(azusa.hit_test(5, 5), [id(1, 0), bottom]);
+        azusa.layer("top").set_visible(false);
+        assert_eq!(azusa.hit_test(5, 5), [bottom]);
+        azusa.layer("top").set_visible(true).set_opacity(0.0);
+        assert_eq!(azusa.hit_test(5, 5), [bottom]);
+    }
+
+    #[test]
+    fn cell_boundaries() {
+        let mut azusa = Azusa::new();
+        let left = fill(&mut azusa, 0, 0, CELL_SIZE, CELL_SIZE);
+        let right = fill(&mut azusa, CELL_SIZE, CELL_SIZE, 1, 1);
+        let large = fill(&mut azusa, 0, 0, CELL_SIZE * 20, CELL_SIZE * 20);
+        assert_eq!(azusa.hit_test(CELL_SIZE - 1, CELL_SIZE - 1), [large, left]);
+        assert_eq!(azusa.hit_test(CELL_SIZE, CELL_SIZE), [large, right]);
+        assert_eq!(azusa.hit_test(CELL_SIZE, CELL_SIZE - 1), [large]);
+        assert_eq!(azusa.hit_test(CELL_SIZE * 20 - 1, 0), [large]);
+        assert_eq!(azusa.hit_test(CELL_SIZE * 20, 0), []);
+    }
+
+    #[test]
+    fn opaque_clears_hide_what_is_below() {
+        let mut azusa = Azusa::new();
+        fill(&mut azusa, 0, 0, 10, 10);
+        azusa.layer("top");
+        azusa.set_source_color(Color::White);
+        azusa.clear();
+        azusa.fill_rectangle(5, 5);
+        assert_eq!(azusa.hit_test(2, 2), [id(1, 1)]);
+        assert_eq!(azusa.hit_test(7, 7), []);
+        assert_eq!(azusa.scene_bounds(), Some(Rect::new(0, 0, 5, 5)));
+    }
+
+    #[test]
+    fn translucent_clears_do_not_hide_what_is_below() {
+        let mut azusa = Azusa::new();
+        let bottom = fill(&mut azusa, 0, 0, 10, 10);
+        azusa.layer("silver");
+        azusa.set_source_color(Color::Silver);
+        azusa.clear();
+        assert_eq!(azusa.hit_test(5, 5), [bottom]);
+
+        // A clear in a translucent layer is blended over the layers below
+        azusa.layer("translucent").set_opacity(0.5);
+        azusa.set_source_color(Color::White);
+        azusa.clear();
+        assert_eq!(azusa.hit_test(5, 5), [bottom]);
+        azusa.layer("translucent").set_opacity(1.0);
+        assert_eq!(azusa.hit_test(5, 5), []);
+    }
+
+    #[test]
+    fn index_follows_changes() {
+        let mut azusa = Azusa::new();
+        let first = fill(&mut azusa, 0, 0, 10, 10);
+        assert_eq!(azusa.hit_test(15, 15), []);
+        let second = fill(&mut azusa, 10, 10, 10, 10);
+        assert_eq!(azusa.hit_test(15, 15), [second]);
+        assert_eq!(azusa.scene_bounds(), Some(Rect::new(0, 0, 20, 20)));
+
+        assert_eq!(azusa.optimize(5, 5).removed(), 1);
+        assert_eq!(azusa.hit_test(15, 15), []);
+        assert_eq!(azusa.hit_test(5, 5), [first]);
+        azusa.remove_layer("default");
+        assert_eq!(azusa.hit_test(5, 5), []);
+        assert_eq!(azusa.scene_bounds(), None);
+    }
+
+    #[test]
+    fn bounds_of_commands() {
+        let mut azusa = Azusa::new();
+        azusa.clear();
+        let fill = fill(&mut azusa, 1, 2, 3, 4);
+        azusa.layer("outline");
+        azusa.move_to(5, 6);
+        azusa.draw_rectangle(1, 7, 8);
+        assert_eq!(azusa.bounds_of(fill), Some(Rect::new(1, 2, 3, 4)));
+        assert_eq!(azusa.bounds_of(id(1, 0)), Some(Rect::new(5, 6, 7, 8)));
+        assert_eq!(azusa.bounds_of(id(0, 0)), None);
+        assert_eq!(azusa.bounds_of(id(0, 2)), None);
+        assert_eq!(azusa.bounds_of(id(2, 0)), None);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::DrawTarget;
+
+use std::fmt::{Debug, Formatter};
+use std::sync::OnceLock;
+
+/// Name of the layer a context draws to until another one is selected with Azusa::layer
+pub const DEFAULT_LAYER: &str = "default";
+
+/// Named list of commands of a context.
+/// Layers are drawn from the lowest z-index to the highest, layers with the same z-index in the order they were created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    name: String,
+    z_index: i32,
+    visible: bool,
+    opacity: f64,
+    pub(crate) ctx: Vec<DrawTarget>,
+}
+
+impl Layer {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            z_index: 0,
+            visible: true,
+            opacity: 1.0,
+            ctx: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn z_index(&self) -> i32 {
+        self.z_index
+    }
+
+    /// Layers with a higher z-index are drawn above the others, the default layer has 0
+    pub fn set_z_index(&mut self, z_index: i32) -> &mut Self {
+        self.z_index = z_index;
+        self
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Hidden layers keep their commands but are not drawn
+    pub fn set_visible(&mut self, visible: bool) -> &mut Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn opacity(&self) -> f64 {
+        self.opacity
+    }
+
+    /// Opacity of the whole layer from 0 to 1.
+    /// The layer is drawn first and blended afterwards, so overlapping shapes in it do not show through each other.
+    pub fn set_opacity(&mut self, opacity: f64) -> &mut Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Commands of the layer
+    pub fn get_ctx(&self) -> &[DrawTarget] {
+        &self.ctx
+    }
+
+    /// Removes the commands of the layer.
+    /// Unlike Azusa::clear, nothing is filled, so the layers below stay visible.
+    pub fn clear(&mut self) {
+        self.ctx.clear();
+    }
+
+    /// Opacity as the alpha of DrawTarget::BeginLayer
+    pub(crate) fn alpha(&self) -> u8 {
+        (self.opacity * 255.0).round() as u8
+    }
+}
+
+/// Flattens the visible layers into a single command list.
+/// Translucent layers are wrapped into BeginLayer and EndLayer, so surfaces blend them as a whole.
+/// A single opaque layer is borrowed, other lists are flattened into the cache the first time after the layers changed.
+pub(crate) fn composite<'a>(layers: &'a [Layer], cache: &'a Flattened) -> &'a [DrawTarget] {
+    let visible = visible(layers);
+    match visible.as_slice() {
+        [] => &[],
+        [(_, layer)] if layer.alpha() == 255 => &layer.ctx,
+        _ => cache.0.get_or_init(|| {
+            let mut ctx = Vec::with_capacity(visible.iter().map(|(_, i)| i.ctx.len() + 2).sum());
+            for (_, layer) in &visible {
+                match layer.alpha() {
+                    255 => ctx.extend_from_slice(&layer.ctx),
+                    alpha => {
+                        ctx.push(DrawTarget::BeginLayer(alpha));
+                        ctx.extend_from_slice(&layer.ctx);
+                        ctx.push(DrawTarget::EndLayer);
+                    }
+                }
+            }
+            ctx
+        }),
+    }
+}
+
+/// Commands of several layers flattened by composite, dropped when a layer changes
+#[derive(Default)]
+pub(crate) struct Flattened(OnceLock<Vec<DrawTarget>>);
+
+impl Flattened {
+    pub(crate) fn invalidate(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+// Like hit::Cache, the list is derived from the layers and does not take part in copies and comparisons
+impl Clone for Flattened {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for Flattened {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Debug for Flattened {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Flattened")
+    }
+}
+
+/// Layers that are drawn with their index, in the order they are drawn
+pub(crate) fn visible(layers: &[Layer]) -> Vec<(usize, &Layer)> {
+    let mut visible: Vec<(usize, &Layer)> = layers
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| i.visible && i.alpha() > 0 && !i.ctx.is_empty())
+        .collect();
+    // Stable, so layers with the same z-index stay in the order they were created
+    visible.sort_by_key(|(_, i)| i.z_index);
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Azusa, Color, DrawTarget};
+
+    fn fill(color: Color) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, 0, 0, 10, 10)
+    }
+
+    fn draw(azusa: &mut Azusa, layer: &str, color: Color) {
+        azusa.layer(layer);
+        azusa.set_source_color(color);
+        azusa.set_border_color(color);
+        azusa.fill_rectangle(10, 10);
+    }
+
+    #[test]
+    fn layers_are_drawn_by_z_index() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "top", Color::Red);
+        draw(&mut azusa, "bottom", Color::Blue);
+        draw(&mut azusa, "default", Color::Lime);
+        azusa.layer("top").set_z_index(1);
+        azusa.layer("bottom").set_z_index(-1);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Blue), fill(Color::Lime), fill(Color::Red)]);
+    }
+
+    #[test]
+    fn layers_with_the_same_z_index_keep_their_order() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "a", Color::Red);
+        draw(&mut azusa, "b", Color::Blue);
+        draw(&mut azusa, "c", Color::Lime);
+        azusa.layer("a").set_z_index(2);
+        azusa.layer("b").set_z_index(2);
+        azusa.layer("c").set_z_index(2);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red), fill(Color::Blue), fill(Color::Lime)]);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_drawn() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "default", Color::Red);
+        draw(&mut azusa, "hidden", Color::Blue);
+        azusa.layer("hidden").set_visible(false);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red)]);
+        azusa.get_layer_mut("hidden").unwrap().set_opacity(0.0).set_visible(true);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red)]);
+    }
+
+    #[test]
+    fn translucent_layers_are_wrapped() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "default", Color::Red);
+        draw(&mut azusa, "overlay", Color::Blue);
+        azusa.layer("overlay").set_opacity(0.5);
+        assert_eq!(
+            azusa.get_ctx(),
+            [fill(Color::Red), DrawTarget::BeginLayer(128), fill(Color::Blue), DrawTarget::EndLayer]
+        );
+
+        azusa.layer("default").set_visible(false);
+        assert_eq!(azusa.get_ctx(), [DrawTarget::BeginLayer(128), fill(Color::Blue), DrawTarget::EndLayer]);
+    }
+
+    #[test]
+    fn single_opaque_layer_is_borrowed() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "default", Color::Red);
+        draw(&mut azusa, "empty", Color::Red);
+        azusa.layer("empty").clear();
+        let layer = azusa.get_layer("default").unwrap().get_ctx();
+        assert_eq!(azusa.get_ctx().as_ptr(), layer.as_ptr());
+    }
+
+    #[test]
+    fn flattened_commands_follow_changes() {
+        let mut azusa = Azusa::new();
+        draw(&mut azusa, "default", Color::Red);
+        draw(&mut azusa, "top", Color::Blue);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red), fill(Color::Blue)]);
+        // Flattened once until something changes
+        assert_eq!(azusa.get_ctx().as_ptr(), azusa.get_ctx().as_ptr());
+
+        draw(&mut azusa, "top", Color::Lime);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red), fill(Color::Blue), fill(Color::Lime)]);
+        azusa.get_layer_mut("top").unwrap().set_z_index(-1);
+        assert_eq!(azusa.get_ctx(), [fill(Color::Blue), fill(Color::Lime), fill(Color::Red)]);
+        azusa.remove_layer("top");
+        assert_eq!(azusa.get_ctx(), [fill(Color::Red)]);
+    }
+}
@@ -1,9 +1,18 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(any(feature = "png", feature = "window"))]
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
+#[cfg(feature = "png")]
 use std::fs::File;
+#[cfg(feature = "png")]
 use std::io::BufWriter;
+use std::io::{self, Read, Write};
+
+use damage::Damage;
+use hit::CommandId;
+use layer::{Layer, DEFAULT_LAYER};
 
 #[cfg(feature = "window")]
 pub mod window;
@@ -11,7 +20,33 @@ pub mod window;
 #[cfg(feature = "web")]
 pub mod web;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+mod codec;
+pub mod compose;
+pub mod damage;
+pub mod hit;
+pub mod layer;
+pub mod optimize;
+
+#[cfg(feature = "pdf")]
+pub mod pdf;
+
+#[cfg(any(feature = "png", feature = "gif"))]
+pub mod animation;
+
+#[cfg(all(feature = "framebuffer", target_os = "linux"))]
+pub mod framebuffer;
+
+pub mod raster;
+pub mod recording;
+pub mod scene;
+pub mod stream;
+pub mod svg;
+pub mod terminal;
+pub mod text;
+pub mod video;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Color {
     White,
     Olive,
@@ -54,7 +89,10 @@ impl From<Color> for Vec4 {
     }
 }
 
-#[derive(Clone,PartialEq,Debug)]
+/// UTF-16 string, serialized as a regular string with the serde feature
+#[derive(Clone,PartialEq,Eq,Hash,Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", from = "String"))]
 pub struct UString {
     data: Vec<u16>
 }
@@ -65,6 +103,20 @@ impl UString {
             data: string.encode_utf16().chain(std::iter::once(0)).collect::<Vec<u16>>()
         }
     }
+
+    pub(crate) fn from_utf16(units: &[u16]) -> Self {
+        Self {
+            data: units.iter().copied().chain(std::iter::once(0)).collect()
+        }
+    }
+
+    /// UTF-16 code units without the terminating NUL
+    pub fn as_utf16(&self) -> &[u16] {
+        match self.data.split_last() {
+            Some((0, rest)) => rest,
+            _ => &self.data,
+        }
+    }
 }
 
 impl From<String> for UString {
@@ -73,44 +125,242 @@ impl From<String> for UString {
     }
 }
 
+impl From<UString> for String {
+    /// Unpaired surrogates are replaced with U+FFFD
+    fn from(value: UString) -> Self {
+        String::from_utf16_lossy(value.as_utf16())
+    }
+}
+
 impl Display for UString {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}",String::from_utf16(self.data.as_slice()).unwrap())
     }
 }
 
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FontInfo(pub(crate) u32,pub(crate) bool,pub(crate) bool);
 
 impl FontInfo {
     pub fn new(px:u32,is_italic: bool,is_under_line: bool) -> Self {
-        Self {
-            0:px,
-            1: is_italic,
-            2: is_under_line
-        }
+        Self(px, is_italic, is_under_line)
     }
 }
 
 #[derive(Copy, Clone, Debug)]
 struct Vec4(f64, f64, f64, f64);
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DrawTarget {
     /// Clear(Color)
     Clear(Color),
-    /// FillRectangle(Color,BorderColor,x,y,width,height
+    /// FillRectangle(Color,BorderColor,x,y,width,height)
     FillRectangle(Color, Color, u32, u32, u32, u32),
-    /// DrawRectangle(Color,x,y,width,height,thickness)
+    /// DrawRectangle(Color,thickness,x,y,width,height).
+    /// Earlier versions documented the thickness after the size and Azusa::draw_rectangle stored it after y,
+    /// commands built by hand in that order have to move the thickness to the second field.
     DrawRectangle(Color, u32, u32, u32, u32, u32),
-    /// DrawText(Color,x,y,width,height,Text)
-    DrawText(Color,FontInfo,u32,u32,u32,u32,UString)
+    /// DrawText(Color,FontInfo,x,y,width,height,Text)
+    DrawText(Color,FontInfo,u32,u32,u32,u32,UString),
+    /// BeginLayer(opacity). The commands up to the matching EndLayer are drawn into a transparent layer,
+    /// which is blended over the surface with the opacity (255 is opaque) at the EndLayer.
+    BeginLayer(u8),
+    /// EndLayer
+    EndLayer,
+}
+
+impl DrawTarget {
+    /// Converts a command from logical pixels to the physical pixels of a surface with the given scale factor.
+    /// Edges are rounded separately, so adjacent rectangles stay adjacent.
+    pub fn scale(&self, factor: f64) -> Self {
+        let edge = |v: u32| (v as f64 * factor).round() as u32;
+        let rect = |x: u32, y: u32, width: u32, height: u32| {
+            (
+                edge(x),
+                edge(y),
+                edge(x.saturating_add(width)) - edge(x),
+                edge(y.saturating_add(height)) - edge(y),
+            )
+        };
+        // Thin lines must not disappear
+        let line = |v: u32| if v == 0 { 0 } else { edge(v).max(1) };
+
+        match self {
+            DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => self.clone(),
+            DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                let (x, y, width, height) = rect(*x, *y, *width, *height);
+                DrawTarget::FillRectangle(*color, *border_color, x, y, width, height)
+            }
+            DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                let (x, y, width, height) = rect(*x, *y, *width, *height);
+                DrawTarget::DrawRectangle(*color, line(*thickness), x, y, width, height)
+            }
+            DrawTarget::DrawText(color, info, x, y, width, height, string) => {
+                let (x, y, width, height) = rect(*x, *y, *width, *height);
+                let info = FontInfo(line(info.0), info.1, info.2);
+                DrawTarget::DrawText(*color, info, x, y, width, height, string.clone())
+            }
+        }
+    }
+
+    /// Moves a command by x, y. Commands without a position, like Clear, stay the same.
+    pub fn translate(&self, x: u32, y: u32) -> Self {
+        let mut command = self.clone();
+        match &mut command {
+            DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+            DrawTarget::FillRectangle(_, _, cx, cy, _, _)
+            | DrawTarget::DrawRectangle(_, _, cx, cy, _, _)
+            | DrawTarget::DrawText(_, _, cx, cy, _, _, _) => {
+                *cx = cx.saturating_add(x);
+                *cy = cy.saturating_add(y);
+            }
+        }
+        command
+    }
+
+    /// Area a command draws to, None for Clear which covers the whole surface
+    /// and for the start and end of layers, which can blend anything below them
+    pub fn bounds(&self) -> Option<Rect> {
+        match *self {
+            DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => None,
+            DrawTarget::FillRectangle(_, _, x, y, width, height)
+            | DrawTarget::DrawRectangle(_, _, x, y, width, height)
+            | DrawTarget::DrawText(_, _, x, y, width, height, _) => Some(Rect::new(x, y, width, height)),
+        }
+    }
+
+    /// Whether a command can change pixels inside of a rectangle
+    pub fn intersects(&self, rect: &Rect) -> bool {
+        self.bounds().is_none_or(|bounds| bounds.intersects(rect))
+    }
+}
+
+/// Rectangle in pixels
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Whether the pixel at x, y is inside of the rectangle
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// Whether the rectangles share at least one pixel
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Pixels covered by both rectangles
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let (right, bottom) = (self.right().min(other.right()), self.bottom().min(other.bottom()));
+        if x >= right || y >= bottom {
+            return None;
+        }
+        Some(Rect::new(x, y, right - x, bottom - y))
+    }
+
+    /// Smallest rectangle covering both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let (right, bottom) = (self.right().max(other.right()), self.bottom().max(other.bottom()));
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Converts to physical pixels. Edges are rounded outwards,
+    /// so the rectangle covers every pixel a command scaled with DrawTarget::scale touches.
+    pub fn scale(&self, factor: f64) -> Rect {
+        let x = (self.x as f64 * factor).floor() as u32;
+        let y = (self.y as f64 * factor).floor() as u32;
+        let right = (self.right() as f64 * factor).ceil() as u32;
+        let bottom = (self.bottom() as f64 * factor).ceil() as u32;
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Where a context is drawn inside of another one: it is scaled first and then moved by x, y
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub x: u32,
+    pub y: u32,
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::translate(0, 0)
+    }
+}
+
+impl Transform {
+    /// Moves a context without scaling it
+    pub fn translate(x: u32, y: u32) -> Self {
+        Self { x, y, scale: 1.0 }
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Applies the transform to a command, see DrawTarget::scale and DrawTarget::translate
+    pub fn apply(&self, command: &DrawTarget) -> DrawTarget {
+        if self.scale == 1.0 {
+            return command.translate(self.x, self.y);
+        }
+        command.scale(self.scale).translate(self.x, self.y)
+    }
+}
+
+/// Converts a context to physical pixels, see DrawTarget::scale.
+/// The context is only copied if it has to be scaled.
+#[cfg(any(feature = "png", feature = "window"))]
+pub(crate) fn scale_ctx(ctx: &[DrawTarget], factor: f64) -> Cow<'_, [DrawTarget]> {
+    if factor == 1.0 {
+        return Cow::Borrowed(ctx);
+    }
+    Cow::Owned(ctx.iter().map(|i| i.scale(factor)).collect())
 }
 
 pub trait Surface {
-    fn draw(&mut self, ctx: Vec<DrawTarget>);
-    /// Get surface size
+    /// Draws a context. The commands are borrowed, so drawing every frame does not copy them
+    fn draw(&mut self, ctx: &[DrawTarget]);
+    /// Repaints the given regions in logical pixels, the rest of the surface keeps showing the previous frame.
+    /// Like draw, the regions are not erased first. Surfaces that cannot keep their contents draw everything.
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], _damage: &[Rect]) {
+        self.draw(ctx);
+    }
+    /// Get surface size in logical pixels
     fn get_client_size(&self) -> (u32, u32);
+    /// Number of physical pixels per logical pixel.
+    /// Contexts are drawn in logical pixels, surfaces with a scale factor render them at their physical resolution.
+    fn scale_factor(&self) -> f64 {
+        1.0
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -126,6 +376,8 @@ pub struct ImageSurface<'a> {
     height: f64,
     name: &'a str,
     image_type: ImageType,
+    scale: f64,
+    threads: usize,
 }
 
 impl<'a> ImageSurface<'a> {
@@ -135,6 +387,8 @@ impl<'a> ImageSurface<'a> {
             height,
             name,
             image_type,
+            scale: 1.0,
+            threads: 1,
         }
     }
 
@@ -142,128 +396,200 @@ impl<'a> ImageSurface<'a> {
         self.width = width;
         self.height = height;
     }
-}
 
-impl Surface for ImageSurface<'_> {
-    fn draw(&mut self, ctx: Vec<DrawTarget>) {
+    /// Renders the image at a higher resolution, e.g. 2.0 for a PNG for 2x displays.
+    /// The size stays in logical pixels.
+    pub fn set_scale_factor(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    /// Rasterizes large images on several threads, 0 uses one thread per CPU.
+    /// The image is the same with any number of threads.
+    pub fn set_thread_count(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    /// Writes a context to the image file like draw, but returns the error instead of logging it
+    #[cfg_attr(not(feature = "png"), allow(unused_variables))]
+    pub fn save(&self, ctx: &[DrawTarget]) -> io::Result<()> {
         match self.image_type {
             #[cfg(feature = "png")]
             ImageType::Png => {
-                // Initialize png encoder
+                let width = (self.width * self.scale).round() as u32;
+                let height = (self.height * self.scale).round() as u32;
 
-                let mut png = immo::png::Png::new(self.width as u32, self.height as u32);
+                // Rasterized by the same code as the software window backends
+                let mut pixmap = raster::Pixmap::new(width, height);
+                raster::TiledRasterizer::new(self.threads).draw(&mut pixmap, &scale_ctx(ctx, self.scale));
 
                 let path = format!("{}.png", self.name);
-                let file = File::create(path).unwrap();
+                let file = File::create(path)?;
                 let w = &mut BufWriter::new(file);
 
-                let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32); // Width is 2 pixels and height is 1.
+                let mut encoder = png::Encoder::new(w, width, height);
                 encoder.set_color(png::ColorType::Rgba);
                 encoder.set_depth(png::BitDepth::Eight);
-
-                let mut writer = encoder.write_header().unwrap();
-
-                for i in ctx {
-                    match i {
-                        DrawTarget::Clear(color) => {
-                            let color = Vec4::from(color);
-                            png.clear((color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8));
-                        }
-                        DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
-                            let color = Vec4::from(color);
-                            let border_color = Vec4::from(border_color);
-
-                            match png.draw_rectangle(
-                                x,
-                                y,
-                                width,
-                                height,
-                                1,
-                                (
-                                    border_color.0 as u8,
-                                    border_color.1 as u8,
-                                    border_color.2 as u8,
-                                    border_color.3 as u8,
-                                ),
-                            ) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!("{}", e);
-                                }
-                            }
-
-                            match png.fill_rectangle(
-                                x + 1,
-                                y + 1,
-                                width - 2,
-                                height - 2,
-                                (color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8),
-                            ) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!("{}", e);
-                                }
-                            }
-                        }
-
-                        DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
-                            let color = Vec4::from(color);
-                            match png.draw_rectangle(
-                                x,
-                                y,
-                                width,
-                                height,
-                                thickness,
-                                (color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8),
-                            ) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!("{}", e);
-                                }
-                            }
-                        }
-                        DrawTarget::DrawText(color,info,x,y,width,height,string) => {
-
-                        }
-                    }
+                if self.scale != 1.0 {
+                    // Viewers show the image at its logical size, 96 DPI per logical pixel
+                    let pixels_per_meter = (96.0 * self.scale / 0.0254).round() as u32;
+                    encoder.set_pixel_dims(Some(png::PixelDimensions {
+                        xppu: pixels_per_meter,
+                        yppu: pixels_per_meter,
+                        unit: png::Unit::Meter,
+                    }));
                 }
 
-                writer.write_image_data(png.as_slice()).unwrap();
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(pixmap.as_slice())?;
+                writer.finish()?;
+                Ok(())
             }
-            ImageType::None => {}
+            ImageType::None => Ok(()),
+        }
+    }
+}
+
+impl Surface for ImageSurface<'_> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        if let Err(e) = self.save(ctx) {
+            error!("Cannot write {}.png: {}", self.name, e);
         }
     }
 
     fn get_client_size(&self) -> (u32, u32) {
         (self.width as u32, self.height as u32)
     }
+
+    fn scale_factor(&self) -> f64 {
+        self.scale
+    }
 }
 
+/// Magic number of recordings written by Azusa::save_to
+const FORMAT_MAGIC: &[u8; 4] = b"AZSA";
+/// Incremented when the encoding of the commands changes.
+/// Version 2 added layers, recordings of older versions can still be read.
+const FORMAT_VERSION: u16 = 2;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Azusa {
-    ctx: Vec<DrawTarget>,
+    layers: Vec<Layer>,
+    /// Index of the layer that is drawn to
+    current: usize,
     ctx_color: Color,
     ctx_border_color: Color,
 
     ctx_x: u32,
     ctx_y: u32,
+
+    presented: Option<Presented>,
+    hit_index: hit::Cache,
+    flattened: layer::Flattened,
+}
+
+/// What Azusa::present showed last
+#[derive(Clone, Debug, PartialEq)]
+struct Presented {
+    summaries: Vec<damage::Summary>,
+    /// Summaries of the frame before, reused as the buffer of the next one
+    next: Vec<damage::Summary>,
+    size: (u32, u32),
+    scale: f64,
+}
+
+impl Default for Azusa {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Azusa {
     pub fn new() -> Self {
         info!("Azusa context has been created");
         Self {
-            ctx: vec![],
+            layers: vec![Layer::new(DEFAULT_LAYER)],
+            current: 0,
             ctx_color: Color::Black,
             ctx_border_color: Color::Black,
             ctx_x: 0,
             ctx_y: 0,
+            presented: None,
+            hit_index: hit::Cache::default(),
+            flattened: layer::Flattened::default(),
         }
     }
 
-    /// Retrieves the contents of a context
+    /// Creates a context holding commands that were recorded elsewhere
+    pub(crate) fn from_ctx(ctx: Vec<DrawTarget>) -> Self {
+        let mut azusa = Self::new();
+        azusa.layers[0].ctx = ctx;
+        azusa
+    }
+
+    /// Retrieves the contents of a context, the visible layers flattened in the order they are drawn
     pub fn get_ctx(&self) -> &[DrawTarget] {
-        &self.ctx
+        layer::composite(&self.layers, &self.flattened)
+    }
+
+    /// Selects the layer the following drawings go to, creating it if it does not exist.
+    /// Drawings go to the layer named "default" until another one is selected.
+    pub fn layer(&mut self, name: &str) -> &mut Layer {
+        self.current = match self.layers.iter().position(|i| i.name() == name) {
+            Some(index) => index,
+            None => {
+                self.layers.push(Layer::new(name));
+                self.layers.len() - 1
+            }
+        };
+        self.changed();
+        &mut self.layers[self.current]
+    }
+
+    /// Gets a layer without selecting it
+    pub fn get_layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|i| i.name() == name)
+    }
+
+    /// Gets a layer to change it without selecting it
+    pub fn get_layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.changed();
+        self.layers.iter_mut().find(|i| i.name() == name)
+    }
+
+    /// Layers in the order they were created
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Removes a layer and its commands. The default layer cannot be removed, it is cleared instead.
+    /// If the removed layer was selected, drawings go to the default layer again.
+    pub fn remove_layer(&mut self, name: &str) -> bool {
+        let Some(index) = self.layers.iter().position(|i| i.name() == name) else {
+            return false;
+        };
+        self.changed();
+        if name == DEFAULT_LAYER {
+            self.layers[index].clear();
+            return true;
+        }
+        let current = self.layers[self.current].name().to_string();
+        self.layers.remove(index);
+        self.current = self.layers.iter().position(|i| i.name() == current).unwrap_or_else(|| {
+            self.layers.iter().position(|i| i.name() == DEFAULT_LAYER).unwrap_or(0)
+        });
+        true
+    }
+
+    /// Drops what was derived from the layers, they are about to change
+    fn changed(&mut self) {
+        self.hit_index.invalidate();
+        self.flattened.invalidate();
+    }
+
+    /// Commands of the selected layer
+    fn ctx_mut(&mut self) -> &mut Vec<DrawTarget> {
+        self.changed();
+        &mut self.layers[self.current].ctx
     }
 
     /// Specifies the color to use for the fill
@@ -276,12 +602,16 @@ impl Azusa {
         self.ctx_border_color = color;
     }
 
-    /// Fills a surface with a specific color and clears the contents of the context.
+    /// Fills a surface with a specific color and clears the contents of the selected layer.
     pub fn clear(&mut self) {
-        self.ctx.clear();
-        self.ctx.push(DrawTarget::Clear(self.ctx_color));
+        let color = self.ctx_color;
+        let ctx = self.ctx_mut();
+        ctx.clear();
+        ctx.push(DrawTarget::Clear(color));
     }
 
+    /// Moves the position of the next drawing.
+    /// Coordinates and sizes are in logical pixels, surfaces scale them by their scale factor.
     pub fn move_to(&mut self, x: u32, y: u32) {
         self.ctx_x = x;
         self.ctx_y = y;
@@ -289,35 +619,289 @@ impl Azusa {
 
     /// Reserves the context to fill rectangle
     pub fn fill_rectangle(&mut self, width: u32, height: u32) {
-        self.ctx.push(DrawTarget::FillRectangle(
+        let command = DrawTarget::FillRectangle(
             self.ctx_color,
             self.ctx_border_color,
             self.ctx_x,
             self.ctx_y,
             width,
             height,
-        ));
+        );
+        self.ctx_mut().push(command);
     }
 
     /// Reserves the context to draw rectangle
     pub fn draw_rectangle(&mut self, thickness: u32, width: u32, height: u32) {
-        self.ctx.push(DrawTarget::DrawRectangle(
+        let command = DrawTarget::DrawRectangle(
             self.ctx_color,
+            thickness,
             self.ctx_x,
             self.ctx_y,
-            thickness,
             width,
             height,
-        ));
+        );
+        self.ctx_mut().push(command);
     }
 
     /// Reserves the context to write text
     pub fn draw_text(&mut self,width:u32,height:u32,string: UString,info: FontInfo) {
-        self.ctx.push(DrawTarget::DrawText(self.ctx_color,info,self.ctx_x,self.ctx_y,width,height,string));
+        let command = DrawTarget::DrawText(self.ctx_color,info,self.ctx_x,self.ctx_y,width,height,string);
+        self.ctx_mut().push(command);
+    }
+
+    /// Reserves the context to draw a scene, flattening only the parts of it that changed since it was drawn last
+    pub fn draw_scene(&mut self, scene: &mut scene::Scene) {
+        self.ctx_mut().extend_from_slice(scene.commands());
+    }
+
+    /// Area a command draws to in logical pixels, None for commands without bounds like Clear.
+    /// Borders are drawn inside of the bounds.
+    pub fn bounds_of(&self, id: CommandId) -> Option<Rect> {
+        self.layers.get(id.layer)?.get_ctx().get(id.index)?.bounds()
+    }
+
+    /// Smallest rectangle covering everything that is drawn, None if nothing is.
+    /// Clears cover the whole surface, so they are not part of it.
+    pub fn scene_bounds(&self) -> Option<Rect> {
+        self.hit_index.get(&self.layers).bounds()
+    }
+
+    /// Commands that paint the pixel at x, y in logical pixels, the topmost first.
+    /// The pixel is tested against what the commands paint, so the inside of a DrawRectangle is not hit,
+    /// and commands of hidden layers or covered by a later opaque Clear outside of a translucent layer are left out.
+    /// Positions of a physical pointer have to be divided by Surface::scale_factor first.
+    /// The commands are indexed in a grid the first time after the context changed.
+    pub fn hit_test(&self, x: u32, y: u32) -> Vec<CommandId> {
+        self.hit_index.get(&self.layers).hit_test(x, y)
+    }
+
+    /// Removes commands that do not change what a surface of the given size in logical pixels shows:
+    /// commands outside of it, commands covered by later opaque commands of the same layer
+    /// and clears that are painted over, and merges adjacent fills of the same color.
+    /// Every layer is optimized on its own, so hiding or moving layers later still shows the layers below.
+    /// A larger surface may miss what was outside of this one.
+    pub fn optimize(&mut self, width: u32, height: u32) -> optimize::Report {
+        self.changed();
+        let mut report = optimize::Report::default();
+        for layer in &mut self.layers {
+            report += optimize::optimize(&mut layer.ctx, (width, height));
+        }
+        report
+    }
+
+    /// Reserves the context to draw another context, e.g. an icon that is drawn many times.
+    /// The visible layers of the other context are drawn into the selected layer.
+    /// Its clears are left out, they would cover the whole surface instead of the area of the context.
+    pub fn draw_context(&mut self, other: &Azusa, transform: Transform) {
+        let ctx = other.get_ctx();
+        let commands = ctx
+            .iter()
+            .filter(|i| !matches!(i, DrawTarget::Clear(_)))
+            .map(|i| transform.apply(i));
+        self.ctx_mut().extend(commands);
+    }
+
+    /// Writes the commands of the context in a compact binary format that can be read with load_from.
+    /// The format is versioned, so recordings stay readable by later versions.
+    pub fn save_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let payload = codec::encode(self.get_ctx());
+        let length = u32::try_from(payload.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The context is too large"))?;
+        writer.write_all(FORMAT_MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&length.to_le_bytes())?;
+        writer.write_all(&payload)
+    }
+
+    /// Reads a context written by save_to.
+    /// Only the recording is read, so several contexts can be read from the same stream.
+    pub fn load_from(mut reader: impl Read) -> io::Result<Self> {
+        let mut header = [0; 10];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != FORMAT_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an Azusa recording"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported recording version {}", version),
+            ));
+        }
+        let length = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+
+        let mut payload = vec![];
+        reader.take(length as u64).read_to_end(&mut payload)?;
+        if payload.len() != length as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The recording is truncated"));
+        }
+        codec::decode(&payload).map(Self::from_ctx)
     }
 
     /// Writes to the surface passed as argument
     pub fn draw<T: Surface>(&self, surface: &mut T) {
-        surface.draw(self.ctx.to_vec());
+        surface.draw(self.get_ctx());
+    }
+
+    /// Writes to the surface passed as argument, repainting only what changed since the last call.
+    /// A context remembers a single surface, use draw for the others.
+    pub fn present<T: Surface>(&mut self, surface: &mut T) {
+        let size = surface.get_client_size();
+        let scale = surface.scale_factor();
+        let ctx = layer::composite(&self.layers, &self.flattened);
+        // Only summaries of the commands are kept, copying the commands would copy every text of every frame
+        let mut presented = self.presented.take();
+        let mut summaries = presented.as_mut().map(|i| std::mem::take(&mut i.next)).unwrap_or_default();
+        damage::summarize(ctx, &mut summaries);
+        let damage = match &presented {
+            Some(presented) if presented.size == size && presented.scale == scale => {
+                damage::diff_summaries(&presented.summaries, &summaries)
+            }
+            _ => Damage::Full,
+        };
+
+        match damage {
+            Damage::Regions(regions) if regions.is_empty() => {}
+            Damage::Regions(regions) => surface.draw_damaged(ctx, &regions),
+            Damage::Full => surface.draw(ctx),
+        }
+
+        self.presented = Some(Presented {
+            next: presented.map(|i| i.summaries).unwrap_or_default(),
+            summaries,
+            size,
+            scale,
+        });
+    }
+
+    /// Makes the next present repaint everything, e.g. after the window system erased the window
+    pub fn invalidate(&mut self) {
+        self.presented = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_scale_to_physical_pixels() {
+        let fill = DrawTarget::FillRectangle(Color::Red, Color::Blue, 1, 2, 3, 4);
+        assert_eq!(fill.scale(2.0), DrawTarget::FillRectangle(Color::Red, Color::Blue, 2, 4, 6, 8));
+
+        let text = DrawTarget::DrawText(Color::Black, FontInfo::new(12, true, false), 0, 0, 10, 10, UString::new("a"));
+        let scaled = DrawTarget::DrawText(Color::Black, FontInfo::new(18, true, false), 0, 0, 15, 15, UString::new("a"));
+        assert_eq!(text.scale(1.5), scaled);
+        assert_eq!(DrawTarget::Clear(Color::Red).scale(3.0), DrawTarget::Clear(Color::Red));
+    }
+
+    #[test]
+    fn scaled_rectangles_stay_adjacent() {
+        // 1.5 and 3.0 are rounded separately, the left one gets 2 pixels and the right one 1
+        let left = DrawTarget::FillRectangle(Color::Red, Color::Red, 0, 0, 1, 1).scale(1.5);
+        let right = DrawTarget::FillRectangle(Color::Red, Color::Red, 1, 0, 1, 1).scale(1.5);
+        assert_eq!(left.bounds(), Some(Rect::new(0, 0, 2, 2)));
+        assert_eq!(right.bounds(), Some(Rect::new(2, 0, 1, 2)));
+    }
+
+    #[test]
+    fn thin_lines_do_not_disappear() {
+        let outline = DrawTarget::DrawRectangle(Color::Red, 1, 0, 0, 10, 10);
+        assert_eq!(outline.scale(0.25), DrawTarget::DrawRectangle(Color::Red, 1, 0, 0, 3, 3));
+        let none = DrawTarget::DrawRectangle(Color::Red, 0, 0, 0, 10, 10);
+        assert_eq!(none.scale(2.0), DrawTarget::DrawRectangle(Color::Red, 0, 0, 0, 20, 20));
+    }
+
+    #[test]
+    fn scaled_rect_covers_the_scaled_command() {
+        for factor in [0.5, 1.25, 1.5, 2.0, 2.75] {
+            for (x, width) in [(0, 1), (1, 1), (3, 7), (5, 2)] {
+                let rect = Rect::new(x, x + 1, width, width + 1);
+                let command = DrawTarget::FillRectangle(Color::Red, Color::Red, x, x + 1, width, width + 1).scale(factor);
+                let bounds = command.bounds().unwrap();
+                assert_eq!(rect.scale(factor).union(&bounds), rect.scale(factor), "{:?} at {}", rect, factor);
+            }
+        }
+        assert_eq!(Rect::new(1, 1, 1, 1).scale(1.5), Rect::new(1, 1, 2, 2));
+    }
+
+    /// Remembers where the commands it was given are stored
+    #[derive(Default)]
+    struct Probe {
+        drawn: Vec<(*const DrawTarget, usize)>,
+    }
+
+    impl Surface for Probe {
+        fn draw(&mut self, ctx: &[DrawTarget]) {
+            self.drawn.push((ctx.as_ptr(), ctx.len()));
+        }
+
+        fn get_client_size(&self) -> (u32, u32) {
+            (100, 100)
+        }
+    }
+
+    #[test]
+    fn draw_lends_the_commands() {
+        let mut azusa = Azusa::new();
+        azusa.draw_text(50, 10, UString::new("not copied"), FontInfo::new(12, false, false));
+        azusa.fill_rectangle(10, 10);
+
+        let mut probe = Probe::default();
+        azusa.draw(&mut probe);
+        azusa.draw(&mut probe);
+        // The commands of the only layer, not a copy of them
+        let stored = (azusa.layers()[0].get_ctx().as_ptr(), 2);
+        assert_eq!(probe.drawn, [stored, stored]);
+    }
+
+    #[test]
+    fn flattened_layers_are_lent_until_they_change() {
+        let mut azusa = Azusa::new();
+        azusa.fill_rectangle(10, 10);
+        azusa.layer("overlay").set_opacity(0.5);
+        azusa.fill_rectangle(10, 10);
+
+        let mut probe = Probe::default();
+        azusa.draw(&mut probe);
+        azusa.draw(&mut probe);
+        assert_eq!(probe.drawn[0], probe.drawn[1]);
+        assert_eq!(probe.drawn[0], (azusa.get_ctx().as_ptr(), 4));
+
+        azusa.fill_rectangle(5, 5);
+        azusa.draw(&mut probe);
+        assert_eq!(probe.drawn[2].1, 5);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn contexts_are_only_copied_when_scaled() {
+        let ctx = [DrawTarget::Clear(Color::Red)];
+        assert!(matches!(scale_ctx(&ctx, 1.0), Cow::Borrowed(_)));
+        assert!(matches!(scale_ctx(&ctx, 2.0), Cow::Owned(_)));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn image_is_rendered_at_the_scale_factor() {
+        let name = std::env::temp_dir().join(format!("azusa-{}-scaled", std::process::id()));
+        let name = name.to_str().unwrap();
+        let mut surface = ImageSurface::new(3.0, 2.0, name, ImageType::Png);
+        surface.set_scale_factor(2.0);
+        assert_eq!(surface.get_client_size(), (3, 2));
+        surface.save(&[DrawTarget::FillRectangle(Color::Red, Color::Red, 1, 0, 1, 1)]).unwrap();
+
+        let path = format!("{}.png", name);
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut reader = png::Decoder::new(data.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (6, 4));
+        // 192 DPI
+        assert_eq!(info.pixel_dims.unwrap().xppu, 7559);
+
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        let red: Vec<_> = pixels.chunks_exact(4).enumerate().filter(|(_, i)| i[0] == 255).map(|(i, _)| (i % 6, i / 6)).collect();
+        assert_eq!(red, [(2, 0), (3, 0), (2, 1), (3, 1)]);
     }
 }
@@ -0,0 +1,280 @@
+use crate::{Color, DrawTarget, Rect, Vec4};
+
+use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
+
+/// Commands removed by Azusa::optimize
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Commands outside of the surface or with nothing to paint
+    pub off_surface: usize,
+    /// Commands completely covered by later opaque commands
+    pub occluded: usize,
+    /// Fills that were merged into the fill before them
+    pub merged: usize,
+    /// Clears painted over by a later clear or a fill of the whole surface
+    pub clears: usize,
+}
+
+impl Report {
+    /// Number of commands that were removed
+    pub fn removed(&self) -> usize {
+        self.off_surface + self.occluded + self.merged + self.clears
+    }
+}
+
+impl AddAssign for Report {
+    fn add_assign(&mut self, other: Self) {
+        self.off_surface += other.off_surface;
+        self.occluded += other.occluded;
+        self.merged += other.merged;
+        self.clears += other.clears;
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} commands removed: {} off the surface, {} occluded, {} merged, {} redundant clears",
+            self.removed(),
+            self.off_surface,
+            self.occluded,
+            self.merged,
+            self.clears
+        )
+    }
+}
+
+/// Removes the commands of a list that do not change what a surface of the given size in logical pixels shows.
+/// The pixels stay the same as long as the surface is not larger.
+pub(crate) fn optimize(ctx: &mut Vec<DrawTarget>, (width, height): (u32, u32)) -> Report {
+    let mut report = Report::default();
+    let mut keep = vec![true; ctx.len()];
+
+    // Later commands are drawn over earlier ones, so go backwards and remember what is covered
+    let mut occluders: Vec<Rect> = vec![];
+    // Lengths of occluders when the layers that are being walked through were entered
+    let mut layers: Vec<usize> = vec![];
+    let surface = Rect::new(0, 0, width, height);
+    for (index, command) in ctx.iter().enumerate().rev() {
+        match *command {
+            // Commands inside of a layer are blended with its opacity, so they only cover commands in the same layer
+            DrawTarget::EndLayer => layers.push(occluders.len()),
+            DrawTarget::BeginLayer(_) => match layers.pop() {
+                Some(len) => occluders.truncate(len),
+                // Surfaces end the layers left open, so everything after it is in the layer
+                None => occluders.clear(),
+            },
+            DrawTarget::Clear(_) => {
+                if occluders.iter().any(|i| contains(i, &surface)) {
+                    keep[index] = false;
+                    report.clears += 1;
+                } else {
+                    occluders.push(surface);
+                }
+            }
+            _ => {
+                let Some(bounds) = command.bounds() else {
+                    continue;
+                };
+                if bounds.x >= width || bounds.y >= height || paints_nothing(command) {
+                    keep[index] = false;
+                    report.off_surface += 1;
+                    continue;
+                }
+                // Only the part on the surface has to be covered
+                let visible = bounds.intersection(&surface).unwrap_or(bounds);
+                if occluders.iter().any(|i| contains(i, &visible)) {
+                    keep[index] = false;
+                    report.occluded += 1;
+                    continue;
+                }
+                if covers(command) {
+                    // Rectangles of the same layer inside of the new one are not needed anymore
+                    let mut i = layers.last().copied().unwrap_or(0);
+                    while i < occluders.len() {
+                        if contains(&visible, &occluders[i]) {
+                            occluders.swap_remove(i);
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    occluders.push(visible);
+                }
+            }
+        }
+    }
+
+    let mut index = 0;
+    ctx.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+
+    // Merge runs of solid fills, nothing is drawn between them that could be covered differently
+    let mut merged: Vec<DrawTarget> = Vec::with_capacity(ctx.len());
+    for command in ctx.drain(..) {
+        if let Some(last) = merged.last_mut() {
+            if let Some(union) = merge(last, &command) {
+                *last = union;
+                report.merged += 1;
+                continue;
+            }
+        }
+        merged.push(command);
+    }
+    *ctx = merged;
+
+    report
+}
+
+fn is_opaque(color: Color) -> bool {
+    Vec4::from(color).3 == 255.0
+}
+
+/// Whether the rectangle a covers all of b
+fn contains(a: &Rect, b: &Rect) -> bool {
+    a.x <= b.x && a.y <= b.y && a.right() >= b.right() && a.bottom() >= b.bottom()
+}
+
+/// Whether a rectangle command does not change any pixel
+fn paints_nothing(command: &DrawTarget) -> bool {
+    match *command {
+        DrawTarget::FillRectangle(_, _, _, _, width, height) => width == 0 || height == 0,
+        DrawTarget::DrawRectangle(_, thickness, _, _, width, height) => thickness == 0 || width == 0 || height == 0,
+        _ => false,
+    }
+}
+
+/// Whether a command paints every pixel of its bounds with an opaque color
+fn covers(command: &DrawTarget) -> bool {
+    match *command {
+        DrawTarget::FillRectangle(color, border_color, ..) => is_opaque(color) && is_opaque(border_color),
+        // The border fills the whole rectangle when it is at least half as thick
+        DrawTarget::DrawRectangle(color, thickness, _, _, width, height) => {
+            is_opaque(color) && (thickness.saturating_mul(2) >= width || thickness.saturating_mul(2) >= height)
+        }
+        _ => false,
+    }
+}
+
+/// Fill covering both fills, if they have a single color and their union is a rectangle.
+/// Overlapping fills are only merged if they are opaque, a translucent color would be blended twice.
+fn merge(a: &DrawTarget, b: &DrawTarget) -> Option<DrawTarget> {
+    let (&DrawTarget::FillRectangle(color, border_color, ax, ay, aw, ah), &DrawTarget::FillRectangle(b_color, b_border_color, bx, by, bw, bh)) = (a, b) else {
+        return None;
+    };
+    if color != border_color || b_color != color || b_border_color != color {
+        return None;
+    }
+    let (a, b) = (Rect::new(ax, ay, aw, ah), Rect::new(bx, by, bw, bh));
+    let union = a.union(&b);
+    let side_by_side = a.y == b.y && a.height == b.height && a.x <= b.right() && b.x <= a.right();
+    let stacked = a.x == b.x && a.width == b.width && a.y <= b.bottom() && b.y <= a.bottom();
+    if !(side_by_side || stacked) || (a.intersects(&b) && !is_opaque(color)) {
+        return None;
+    }
+    Some(DrawTarget::FillRectangle(color, color, union.x, union.y, union.width, union.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::Pixmap;
+    use crate::Surface;
+
+    fn fill(color: Color, x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, x, y, width, height)
+    }
+
+    fn pixels(ctx: &[DrawTarget]) -> Vec<u8> {
+        let mut pixmap = Pixmap::new(100, 100);
+        pixmap.draw(ctx);
+        pixmap.as_slice().to_vec()
+    }
+
+    #[test]
+    fn every_kind_of_removal() {
+        let ctx = vec![
+            DrawTarget::Clear(Color::White),
+            fill(Color::Red, 0, 0, 10, 10),
+            DrawTarget::Clear(Color::Black),
+            fill(Color::Red, 200, 0, 10, 10),
+            DrawTarget::DrawRectangle(Color::Blue, 0, 0, 0, 10, 10),
+            fill(Color::Lime, 10, 10, 10, 10),
+            fill(Color::Lime, 20, 10, 10, 10),
+            fill(Color::Silver, 50, 50, 10, 10),
+            fill(Color::Blue, 45, 45, 20, 20),
+            // Blended with the layer, so it does not cover what is below
+            fill(Color::Maroon, 70, 70, 5, 5),
+            DrawTarget::BeginLayer(128),
+            fill(Color::Red, 70, 70, 5, 5),
+            fill(Color::Red, 0, 0, 5, 5),
+            DrawTarget::EndLayer,
+            fill(Color::Navy, 0, 0, 6, 6),
+        ];
+        let mut optimized = ctx.clone();
+        let report = optimize(&mut optimized, (100, 100));
+
+        let expected = Report {
+            off_surface: 2,
+            occluded: 3,
+            merged: 1,
+            clears: 1,
+        };
+        assert_eq!(report, expected);
+        assert_eq!(optimized.len(), ctx.len() - report.removed());
+        assert_eq!(optimized[1], fill(Color::Lime, 10, 10, 20, 10));
+        assert!(pixels(&optimized) == pixels(&ctx));
+    }
+
+    #[test]
+    fn layers_left_open_do_not_cover_what_is_below() {
+        let ctx = vec![fill(Color::Red, 0, 0, 10, 10), DrawTarget::BeginLayer(128), DrawTarget::Clear(Color::Blue)];
+        let mut optimized = ctx.clone();
+        assert_eq!(optimize(&mut optimized, (100, 100)), Report::default());
+        assert!(pixels(&optimized) == pixels(&ctx));
+    }
+
+    #[test]
+    fn translucent_fills_are_not_merged_over_each_other() {
+        let mut ctx = vec![fill(Color::Silver, 0, 0, 10, 10), fill(Color::Silver, 5, 0, 10, 10)];
+        assert_eq!(optimize(&mut ctx, (100, 100)), Report::default());
+        let mut ctx = vec![fill(Color::Silver, 0, 0, 10, 10), fill(Color::Silver, 10, 0, 10, 10)];
+        assert_eq!(optimize(&mut ctx, (100, 100)).merged, 1);
+    }
+
+    #[test]
+    fn random_contexts_keep_their_pixels() {
+        const COLORS: [Color; 4] = [Color::Red, Color::Silver, Color::Blue, Color::Lime];
+        let mut seed = 0x9E37_79B9u32;
+        let mut next = move |max: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % max
+        };
+
+        for _ in 0..50 {
+            let mut ctx = vec![];
+            for _ in 0..40 {
+                let (x, y, width, height) = (next(120), next(120), next(60), next(60));
+                let color = COLORS[next(4) as usize];
+                ctx.push(match next(8) {
+                    0 => DrawTarget::Clear(color),
+                    1 => DrawTarget::BeginLayer(next(256) as u8),
+                    2 => DrawTarget::EndLayer,
+                    3 => DrawTarget::DrawRectangle(color, next(30), x, y, width, height),
+                    4 => DrawTarget::FillRectangle(color, COLORS[next(4) as usize], x, y, width, height),
+                    // Rows of fills that can be merged
+                    _ => fill(color, x / 20 * 20, y / 20 * 20, 20, 20),
+                });
+            }
+            let mut optimized = ctx.clone();
+            let report = optimize(&mut optimized, (100, 100));
+            assert_eq!(optimized.len(), ctx.len() - report.removed());
+            assert!(pixels(&optimized) == pixels(&ctx), "{:?}", ctx);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ttf_parser::{name_id, Face, GlyphId};
+
+/// TrueType font embedded into a PDF as a CIDFontType2
+pub(crate) struct TrueTypeFont {
+    data: Vec<u8>,
+    /// Glyphs used so far, with the character they were mapped from
+    used: BTreeMap<u16, char>,
+}
+
+impl TrueTypeFont {
+    pub(crate) fn new(data: Vec<u8>) -> std::io::Result<Self> {
+        if let Err(e) = Face::parse(&data, 0) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+        }
+
+        Ok(Self {
+            data,
+            used: BTreeMap::new(),
+        })
+    }
+
+    fn face(&self) -> Face<'_> {
+        // The data was validated in new()
+        Face::parse(&self.data, 0).unwrap()
+    }
+
+    /// Maps the text to glyph ids, remembering them for the subset
+    pub(crate) fn encode(&mut self, text: &str) -> Vec<u16> {
+        let face = Face::parse(&self.data, 0).unwrap();
+        let mut glyphs = vec![];
+        for c in text.chars() {
+            let id = face.glyph_index(c).map(|id| id.0).unwrap_or(0);
+            self.used.entry(id).or_insert(c);
+            glyphs.push(id);
+        }
+        glyphs
+    }
+
+    pub(crate) fn is_used(&self) -> bool {
+        !self.used.is_empty()
+    }
+
+    /// Scales a font unit value to the 1000 unit glyph space of PDF
+    fn scale(&self, face: &Face, value: f64) -> i64 {
+        (value * 1000.0 / face.units_per_em() as f64).round() as i64
+    }
+
+    /// Ascent as a fraction of the font size
+    pub(crate) fn ascent(&self) -> f64 {
+        let face = self.face();
+        face.ascender() as f64 / face.units_per_em() as f64
+    }
+
+    pub(crate) fn base_font(&self) -> String {
+        let face = self.face();
+        let name = face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .unwrap_or_else(|| String::from("AzusaFont"));
+        let name: String = name
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%#".contains(*c))
+            .collect();
+
+        // Subset fonts are tagged with six upper case letters
+        format!("AZUSAA+{}", name)
+    }
+
+    /// Widths array for the CIDFont dictionary
+    pub(crate) fn widths(&self) -> String {
+        let face = self.face();
+        let mut widths = String::from("[");
+        for id in self.used.keys() {
+            let advance = face.glyph_hor_advance(GlyphId(*id)).unwrap_or(0);
+            widths.push_str(&format!("{} [{}] ", id, self.scale(&face, advance as f64)));
+        }
+        widths.push(']');
+        widths
+    }
+
+    /// Entries for the FontDescriptor dictionary
+    pub(crate) fn descriptor(&self) -> String {
+        let face = self.face();
+        let bbox = face.global_bounding_box();
+        format!(
+            "/Flags 32 /FontBBox [{} {} {} {}] /ItalicAngle {} /Ascent {} /Descent {} /CapHeight {} /StemV 80",
+            self.scale(&face, bbox.x_min as f64),
+            self.scale(&face, bbox.y_min as f64),
+            self.scale(&face, bbox.x_max as f64),
+            self.scale(&face, bbox.y_max as f64),
+            face.italic_angle(),
+            self.scale(&face, face.ascender() as f64),
+            self.scale(&face, face.descender() as f64),
+            self.scale(&face, face.capital_height().unwrap_or(face.ascender()) as f64),
+        )
+    }
+
+    /// CMap that lets readers extract the text again
+    pub(crate) fn to_unicode(&self) -> String {
+        let mut cmap = String::from(
+            "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+             /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+             /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+             1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+        );
+
+        let used = self.used.iter().collect::<Vec<_>>();
+        // bfchar sections are limited to 100 entries
+        for chunk in used.chunks(100) {
+            cmap.push_str(&format!("{} beginbfchar\n", chunk.len()));
+            for (id, c) in chunk {
+                let mut units = [0u16; 2];
+                let hex: String = c
+                    .encode_utf16(&mut units)
+                    .iter()
+                    .map(|unit| format!("{:04X}", unit))
+                    .collect();
+                cmap.push_str(&format!("<{:04X}> <{}>\n", id, hex));
+            }
+            cmap.push_str("endbfchar\n");
+        }
+
+        cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+        cmap
+    }
+
+    /// Font program containing only the glyphs that were used
+    pub(crate) fn subset(&self) -> Vec<u8> {
+        let glyphs = self.used.keys().copied().collect::<BTreeSet<u16>>();
+        match subset(&self.data, &glyphs) {
+            Some(data) => data,
+            None => {
+                warn!("Could not subset the font, embedding it as a whole");
+                self.data.clone()
+            }
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Tables needed to render glyphs of a CIDFontType2
+const TABLES: [&[u8; 4]; 9] = [
+    b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep",
+];
+
+/// Builds a font where every glyph that is not in `glyphs` is empty.
+/// Glyph ids are retained so the content streams can use them as CIDs.
+fn subset(data: &[u8], glyphs: &BTreeSet<u16>) -> Option<Vec<u8>> {
+    let num_tables = read_u16(data, 4)? as usize;
+    let mut tables = BTreeMap::new();
+    for i in 0..num_tables {
+        let record = 12 + i * 16;
+        let tag: [u8; 4] = data.get(record..record + 4)?.try_into().ok()?;
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        tables.insert(tag, data.get(offset..offset + length)?);
+    }
+
+    let head = *tables.get(b"head")?;
+    let long_loca = read_u16(head, 50)? != 0;
+    let num_glyphs = read_u16(tables.get(b"maxp")?, 4)? as usize;
+    let loca = *tables.get(b"loca")?;
+    let glyf = *tables.get(b"glyf")?;
+
+    let glyph_range = |id: usize| -> Option<(usize, usize)> {
+        if long_loca {
+            Some((read_u32(loca, id * 4)? as usize, read_u32(loca, id * 4 + 4)? as usize))
+        } else {
+            Some((
+                read_u16(loca, id * 2)? as usize * 2,
+                read_u16(loca, id * 2 + 2)? as usize * 2,
+            ))
+        }
+    };
+
+    // Composite glyphs reference other glyphs which have to be kept as well
+    let mut keep = BTreeSet::from([0u16]);
+    let mut pending = glyphs.iter().copied().collect::<Vec<_>>();
+    while let Some(id) = pending.pop() {
+        if (id as usize) >= num_glyphs || !keep.insert(id) {
+            continue;
+        }
+        let (start, end) = glyph_range(id as usize)?;
+        let glyph = glyf.get(start..end)?;
+        if glyph.len() < 10 || (read_u16(glyph, 0)? as i16) >= 0 {
+            continue;
+        }
+
+        let mut offset = 10;
+        loop {
+            let flags = read_u16(glyph, offset)?;
+            let component = read_u16(glyph, offset + 2)?;
+            if !keep.contains(&component) {
+                pending.push(component);
+            }
+
+            offset += 4;
+            offset += if flags & 0x0001 != 0 { 4 } else { 2 };
+            if flags & 0x0008 != 0 {
+                offset += 2;
+            } else if flags & 0x0040 != 0 {
+                offset += 4;
+            } else if flags & 0x0080 != 0 {
+                offset += 8;
+            }
+
+            if flags & 0x0020 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut new_glyf = vec![];
+    let mut new_loca = vec![];
+    for id in 0..num_glyphs {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if keep.contains(&(id as u16)) {
+            let (start, end) = glyph_range(id)?;
+            new_glyf.extend_from_slice(glyf.get(start..end)?);
+            while new_glyf.len() % 4 != 0 {
+                new_glyf.push(0);
+            }
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    let mut new_head = head.to_vec();
+    // checkSumAdjustment is recalculated below, loca is always written in the long format
+    new_head.get_mut(8..12)?.copy_from_slice(&[0; 4]);
+    new_head.get_mut(50..52)?.copy_from_slice(&1u16.to_be_bytes());
+
+    let mut output_tables: Vec<([u8; 4], Vec<u8>)> = vec![];
+    for tag in TABLES {
+        let table = match tag {
+            b"glyf" => std::mem::take(&mut new_glyf),
+            b"loca" => std::mem::take(&mut new_loca),
+            b"head" => std::mem::take(&mut new_head),
+            _ => match tables.get(tag) {
+                Some(table) => table.to_vec(),
+                None => continue,
+            },
+        };
+        output_tables.push((*tag, table));
+    }
+
+    let count = output_tables.len() as u16;
+    let entry_selector = 15 - count.leading_zeros() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+
+    let mut font = vec![];
+    font.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    font.extend_from_slice(&count.to_be_bytes());
+    font.extend_from_slice(&search_range.to_be_bytes());
+    font.extend_from_slice(&entry_selector.to_be_bytes());
+    font.extend_from_slice(&(count * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + 16 * output_tables.len();
+    let mut head_offset = 0;
+    for (tag, table) in &output_tables {
+        if tag == b"head" {
+            head_offset = offset;
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(table).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+
+    for (_, table) in &output_tables {
+        font.extend_from_slice(table);
+        while font.len() % 4 != 0 {
+            font.push(0);
+        }
+    }
+
+    let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+    font.get_mut(head_offset + 8..head_offset + 12)?
+        .copy_from_slice(&adjustment.to_be_bytes());
+
+    Some(font)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Tables of a font, by tag
+    fn tables(data: &[u8]) -> BTreeMap<[u8; 4], &[u8]> {
+        (0..read_u16(data, 4).unwrap() as usize)
+            .map(|i| {
+                let record = 12 + i * 16;
+                let offset = read_u32(data, record + 8).unwrap() as usize;
+                let length = read_u32(data, record + 12).unwrap() as usize;
+                (data[record..record + 4].try_into().unwrap(), &data[offset..offset + length])
+            })
+            .collect()
+    }
+
+    /// Tiny font with glyphs for a to d after .notdef. Glyph 4 (d) is a composite of glyph 3 (c).
+    pub(crate) fn test_font() -> Vec<u8> {
+        // Glyphs without contours, only the header with the bounding box, padded to 4 bytes
+        let simple = |width: i16| {
+            let mut glyph = vec![];
+            for value in [0, 0, 0, width, 700, 0] {
+                glyph.extend_from_slice(&value.to_be_bytes());
+            }
+            glyph
+        };
+        // One component with byte offsets of 0
+        let mut composite = vec![];
+        for value in [-1i16, 0, 0, 500, 700, 0x0002, 3] {
+            composite.extend_from_slice(&value.to_be_bytes());
+        }
+        composite.extend_from_slice(&[0, 0, 0, 0]);
+        let glyphs = [simple(500), simple(400), simple(600), simple(500), composite];
+
+        let mut glyf = vec![];
+        let mut loca = vec![];
+        let mut hmtx = vec![];
+        for glyph in &glyphs {
+            loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+            glyf.extend_from_slice(glyph);
+            hmtx.extend_from_slice(&[0x01, 0xF4, 0, 0]);
+        }
+        loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+
+        let mut head = vec![0; 54];
+        head[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        head[12..16].copy_from_slice(&0x5F0F_3CF5u32.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        head[40..42].copy_from_slice(&600u16.to_be_bytes());
+        head[42..44].copy_from_slice(&700u16.to_be_bytes());
+        head[50..52].copy_from_slice(&1u16.to_be_bytes());
+
+        let mut hhea = vec![0; 36];
+        hhea[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&(glyphs.len() as u16).to_be_bytes());
+
+        let mut maxp = 0x0000_5000u32.to_be_bytes().to_vec();
+        maxp.extend_from_slice(&(glyphs.len() as u16).to_be_bytes());
+
+        // Format 4 subtable mapping a to d to glyphs 1 to 4
+        let mut cmap = vec![];
+        let header = [0u16, 1, 3, 1, 0, 12];
+        let subtable = [4u16, 32, 0, 4, 4, 1, 0, 'd' as u16, 0xFFFF, 0, 'a' as u16, 0xFFFF];
+        for value in header.into_iter().chain(subtable) {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+        for value in [1u16.wrapping_sub('a' as u16), 1, 0, 0] {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let tables: [(&[u8; 4], Vec<u8>); 7] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+        let mut font = vec![];
+        for value in [0x0001u16, 0x0000, tables.len() as u16, 64, 2, 48] {
+            font.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in &tables {
+            font.extend_from_slice(*tag);
+            font.extend_from_slice(&checksum(table).to_be_bytes());
+            font.extend_from_slice(&(offset as u32).to_be_bytes());
+            font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += (table.len() + 3) & !3;
+        }
+        for (_, table) in &tables {
+            font.extend_from_slice(table);
+            while font.len() % 4 != 0 {
+                font.push(0);
+            }
+        }
+        font
+    }
+
+    /// Glyphs of a font that have an outline
+    fn glyphs_with_data(data: &[u8]) -> Vec<u16> {
+        let tables = tables(data);
+        let loca = tables[b"loca"];
+        let count = read_u16(tables[b"maxp"], 4).unwrap();
+        (0..count)
+            .filter(|i| read_u32(loca, *i as usize * 4 + 4) > read_u32(loca, *i as usize * 4))
+            .collect()
+    }
+
+    #[test]
+    fn test_font_is_valid() {
+        let data = test_font();
+        let face = Face::parse(&data, 0).unwrap();
+        assert_eq!(face.number_of_glyphs(), 5);
+        assert_eq!(face.glyph_index('b'), Some(GlyphId(2)));
+        assert_eq!(glyphs_with_data(&data), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn subset_keeps_the_used_glyphs_and_notdef() {
+        let mut font = TrueTypeFont::new(test_font()).unwrap();
+        assert!(!font.is_used());
+        assert_eq!(font.encode("abba"), [1, 2, 2, 1]);
+        assert!(font.is_used());
+
+        let subset = font.subset();
+        // Glyph ids stay the same, the other glyphs are empty
+        assert_eq!(glyphs_with_data(&subset), [0, 1, 2]);
+        let face = Face::parse(&subset, 0).unwrap();
+        assert_eq!(face.number_of_glyphs(), 5);
+        assert_eq!(checksum(&subset), 0xB1B0_AFBA);
+        assert_eq!(tables(&subset).keys().collect::<Vec<_>>(), [b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp"]);
+    }
+
+    #[test]
+    fn subset_keeps_components_of_composite_glyphs() {
+        let mut font = TrueTypeFont::new(test_font()).unwrap();
+        // Characters without a glyph are drawn with .notdef
+        assert_eq!(font.encode("dz"), [4, 0]);
+        assert_eq!(glyphs_with_data(&font.subset()), [0, 3, 4]);
+        assert_eq!(font.widths(), "[0 [500] 4 [500] ]");
+    }
+}
@@ -0,0 +1,598 @@
+mod font;
+
+use crate::raster::{outline, Pixmap};
+use crate::{Color, DrawTarget, FontInfo, Rect, Surface, UString, Vec4};
+use font::TrueTypeFont;
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Ascent of the built-in Helvetica font as a fraction of the font size
+const HELVETICA_ASCENT: f64 = 0.718;
+
+struct Page {
+    width: f64,
+    height: f64,
+    content: String,
+}
+
+/// Layer drawn as a transparency group, so its opacity applies to it as a whole
+struct Group {
+    width: f64,
+    height: f64,
+    content: String,
+}
+
+/// Image drawn with PdfSurface::draw_image
+struct Image {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+    /// Soft mask, None if every pixel is opaque
+    alpha: Option<Vec<u8>>,
+}
+
+/// Surface that writes vector PDF documents.
+/// One pixel of the context is one point (1/72 inch) on the page.
+pub struct PdfSurface {
+    width: f64,
+    height: f64,
+
+    pages: Vec<Page>,
+    content: String,
+    groups: Vec<Group>,
+    images: Vec<Image>,
+    /// Content of the streams the open layers are drawn over, and their opacities
+    layers: Vec<(String, u8)>,
+
+    font: Option<TrueTypeFont>,
+    alphas: BTreeSet<u8>,
+    helvetica: bool,
+}
+
+impl PdfSurface {
+    pub fn new(width: f64, height: f64) -> Self {
+        Self {
+            width,
+            height,
+            pages: vec![],
+            content: String::new(),
+            groups: vec![],
+            images: vec![],
+            layers: vec![],
+            font: None,
+            alphas: BTreeSet::new(),
+            helvetica: false,
+        }
+    }
+
+    /// Changes the size of the current and the following pages
+    pub fn resize(&mut self, width: f64, height: f64) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Uses a TrueType font for DrawText.
+    /// Only the glyphs that are actually drawn are embedded into the document.
+    /// Without a font, the standard Helvetica font is used, which can only show Latin-1 characters.
+    pub fn set_font(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        self.font = Some(TrueTypeFont::new(data)?);
+        Ok(())
+    }
+
+    /// Draws an image, e.g. a chart rasterized into a Pixmap, scaled to width x height at x, y of the page.
+    /// It is embedded uncompressed, with a soft mask if it has translucent pixels.
+    pub fn draw_image(&mut self, image: &Pixmap, x: u32, y: u32, width: u32, height: u32) {
+        if image.width() == 0 || image.height() == 0 {
+            return;
+        }
+        let pixels = image.as_slice();
+        let rgb = pixels.chunks_exact(4).flat_map(|i| [i[0], i[1], i[2]]).collect();
+        let alpha = pixels.chunks_exact(4).any(|i| i[3] != 255).then(|| pixels.chunks_exact(4).map(|i| i[3]).collect());
+        self.images.push(Image {
+            width: image.width(),
+            height: image.height(),
+            rgb,
+            alpha,
+        });
+
+        // Images fill the unit square, and the page has its origin at the bottom left
+        let bottom = self.height - y as f64 - height as f64;
+        let _ = writeln!(
+            self.content,
+            "q {} 0 0 {} {} {} cm /I{} Do Q",
+            width,
+            height,
+            x,
+            bottom,
+            self.images.len() - 1
+        );
+    }
+
+    /// Finishes the current page, following draws go to a new page
+    pub fn show_page(&mut self) {
+        let content = std::mem::take(&mut self.content);
+        self.pages.push(Page {
+            width: self.width,
+            height: self.height,
+            content,
+        });
+    }
+
+    /// Number of pages, including the one that is currently being drawn
+    pub fn page_count(&self) -> usize {
+        self.pages.len() + usize::from(!self.content.is_empty() || self.pages.is_empty())
+    }
+
+    /// Writes the document to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(file);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+
+    /// Writes the document
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let mut pages = self.pages.iter().collect::<Vec<_>>();
+        let current = Page {
+            width: self.width,
+            height: self.height,
+            content: self.content.clone(),
+        };
+        if !self.content.is_empty() || pages.is_empty() {
+            pages.push(&current);
+        }
+
+        let mut writer = ObjectWriter::new();
+        // 1: Catalog, 2: Pages, 3: Resources
+        writer.reserve(3);
+
+        let mut fonts = String::new();
+        if self.helvetica {
+            let id = writer.add(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                    .as_bytes(),
+            );
+            let oblique = writer.add(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Oblique /Encoding /WinAnsiEncoding >>"
+                    .as_bytes(),
+            );
+            let _ = write!(fonts, "/F1 {} 0 R /F2 {} 0 R ", id, oblique);
+        }
+        if let Some(font) = self.font.as_ref().filter(|font| font.is_used()) {
+            let program = font.subset();
+            let file = writer.add_stream(&format!("/Length1 {} ", program.len()), &program);
+            let descriptor = writer.add(
+                format!(
+                    "<< /Type /FontDescriptor /FontName /{} {} /FontFile2 {} 0 R >>",
+                    font.base_font(),
+                    font.descriptor(),
+                    file
+                )
+                .as_bytes(),
+            );
+            let cid = writer.add(
+                format!(
+                    "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /{} \
+                     /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> \
+                     /FontDescriptor {} 0 R /W {} /CIDToGIDMap /Identity >>",
+                    font.base_font(),
+                    descriptor,
+                    font.widths()
+                )
+                .as_bytes(),
+            );
+            let to_unicode = writer.add_stream("", font.to_unicode().as_bytes());
+            let id = writer.add(
+                format!(
+                    "<< /Type /Font /Subtype /Type0 /BaseFont /{} /Encoding /Identity-H \
+                     /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>",
+                    font.base_font(),
+                    cid,
+                    to_unicode
+                )
+                .as_bytes(),
+            );
+            let _ = write!(fonts, "/F3 {} 0 R ", id);
+        }
+
+        let mut states = String::new();
+        for alpha in &self.alphas {
+            let id = writer.add(
+                format!("<< /Type /ExtGState /ca {0} /CA {0} >>", *alpha as f64 / 255.0).as_bytes(),
+            );
+            let _ = write!(states, "/GS{} {} 0 R ", alpha, id);
+        }
+
+        let mut groups = String::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            let id = writer.add_stream(
+                &format!(
+                    "/Type /XObject /Subtype /Form /BBox [0 0 {} {}] /Group << /S /Transparency >> /Resources 3 0 R ",
+                    group.width, group.height
+                ),
+                group.content.as_bytes(),
+            );
+            let _ = write!(groups, "/L{} {} 0 R ", i, id);
+        }
+
+        for (i, image) in self.images.iter().enumerate() {
+            let size = format!("/Width {} /Height {} /BitsPerComponent 8 ", image.width, image.height);
+            let mask = match &image.alpha {
+                Some(alpha) => {
+                    let id = writer.add_stream(&format!("/Type /XObject /Subtype /Image {}/ColorSpace /DeviceGray ", size), alpha);
+                    format!("/SMask {} 0 R ", id)
+                }
+                None => String::new(),
+            };
+            let id = writer.add_stream(
+                &format!("/Type /XObject /Subtype /Image {}/ColorSpace /DeviceRGB {}", size, mask),
+                &image.rgb,
+            );
+            let _ = write!(groups, "/I{} {} 0 R ", i, id);
+        }
+
+        let mut kids = String::new();
+        for page in &pages {
+            let content = writer.add_stream("", page.content.as_bytes());
+            let id = writer.add(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources 3 0 R /Contents {} 0 R >>",
+                    page.width, page.height, content
+                )
+                .as_bytes(),
+            );
+            let _ = write!(kids, "{} 0 R ", id);
+        }
+
+        writer.set(1, b"<< /Type /Catalog /Pages 2 0 R >>");
+        writer.set(
+            2,
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, pages.len()).as_bytes(),
+        );
+        writer.set(
+            3,
+            format!("<< /Font << {} >> /ExtGState << {} >> /XObject << {} >> >>", fonts, states, groups).as_bytes(),
+        );
+
+        writer.finish(w)
+    }
+
+    fn set_fill_color(&mut self, color: Vec4) {
+        let _ = writeln!(
+            self.content,
+            "{} {} {} rg",
+            color.0 / 255.0,
+            color.1 / 255.0,
+            color.2 / 255.0
+        );
+        self.set_alpha(color.3);
+    }
+
+    fn set_alpha(&mut self, alpha: f64) {
+        let alpha = alpha as u8;
+        self.alphas.insert(alpha);
+        let _ = writeln!(self.content, "/GS{} gs", alpha);
+    }
+
+    /// Following drawings go to a new transparency group
+    fn begin_layer(&mut self, opacity: u8) {
+        let content = std::mem::take(&mut self.content);
+        self.layers.push((content, opacity));
+    }
+
+    /// Finishes the innermost transparency group and draws it with its opacity
+    fn end_layer(&mut self) {
+        let Some((content, opacity)) = self.layers.pop() else {
+            return;
+        };
+        let group = std::mem::replace(&mut self.content, content);
+        self.groups.push(Group {
+            width: self.width,
+            height: self.height,
+            content: group,
+        });
+        let _ = writeln!(self.content, "q");
+        self.set_alpha(opacity as f64);
+        let _ = writeln!(self.content, "/L{} Do Q", self.groups.len() - 1);
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let _ = writeln!(self.content, "{} {} {} {} re f", x, y, width, height);
+    }
+
+    /// Fills the sides of an outline inside of the rectangle, the same rectangles the rasterizer paints.
+    /// A stroke would spill outside of rectangles thinner than the border, and a zero width is a hairline in PDF.
+    fn fill_outline(&mut self, color: Color, thickness: u32, rect: Rect) {
+        if thickness == 0 || rect.is_empty() {
+            return;
+        }
+        self.set_fill_color(Vec4::from(color));
+        let mut sides = String::new();
+        outline(thickness, rect, |side| {
+            let _ = write!(sides, "{} {} {} {} re ", side.x, side.y, side.width, side.height);
+        });
+        // The sides do not overlap, so they are filled as a single path
+        let _ = writeln!(self.content, "{}f", sides);
+    }
+
+    fn draw_text(&mut self, color: Color, info: FontInfo, rect: (u32, u32, u32, u32), string: &UString) {
+        let (x, y, width, height) = rect;
+        let text = String::from_utf16_lossy(string.as_utf16());
+        let size = info.0 as f64;
+
+        let (font, ascent, encoded) = match self.font.as_mut() {
+            Some(font) => {
+                let glyphs = font.encode(&text);
+                let hex: String = glyphs.iter().map(|id| format!("{:04X}", id)).collect();
+                ("/F3", font.ascent(), format!("<{}>", hex))
+            }
+            None => {
+                self.helvetica = true;
+                let font = if info.1 { "/F2" } else { "/F1" };
+                (font, HELVETICA_ASCENT, format!("({})", win_ansi(&text)))
+            }
+        };
+        // Oblique Helvetica is a real font, TrueType fonts are slanted
+        let slant = if info.1 && font == "/F3" { 0.2 } else { 0.0 };
+        let baseline = y as f64 + size * ascent;
+
+        let _ = writeln!(self.content, "q");
+        // Text is clipped to the rectangle like in the window backends
+        let _ = writeln!(self.content, "{} {} {} {} re W n", x, y, width, height);
+        self.set_fill_color(Vec4::from(color));
+        let _ = writeln!(
+            self.content,
+            "BT {} {} Tf 1 0 {} -1 {} {} Tm {} Tj ET",
+            font, size, slant, x, baseline, encoded
+        );
+        if info.2 {
+            let thickness = (size / 14.0).max(1.0);
+            self.fill_rect(x as f64, baseline + thickness, width as f64, thickness);
+        }
+        let _ = writeln!(self.content, "Q");
+    }
+}
+
+impl Surface for PdfSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        // PDF has its origin at the bottom left
+        let _ = writeln!(self.content, "q 1 0 0 -1 0 {} cm", self.height);
+        for i in ctx {
+            match *i {
+                DrawTarget::Clear(color) => {
+                    self.set_fill_color(Vec4::from(color));
+                    self.fill_rect(0.0, 0.0, self.width, self.height);
+                }
+                DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                    // Only a 1px ring is the border, so a translucent fill does not show it through
+                    self.fill_outline(border_color, 1, Rect::new(x, y, width, height));
+                    if width > 2 && height > 2 {
+                        self.set_fill_color(Vec4::from(color));
+                        self.fill_rect(x as f64 + 1.0, y as f64 + 1.0, width as f64 - 2.0, height as f64 - 2.0);
+                    }
+                }
+                DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                    self.fill_outline(color, thickness, Rect::new(x, y, width, height));
+                }
+                DrawTarget::DrawText(color, info, x, y, width, height, ref string) => {
+                    self.draw_text(color, info, (x, y, width, height), string);
+                }
+                DrawTarget::BeginLayer(opacity) => self.begin_layer(opacity),
+                DrawTarget::EndLayer => self.end_layer(),
+            }
+        }
+        while !self.layers.is_empty() {
+            self.end_layer();
+        }
+        let _ = writeln!(self.content, "Q");
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+}
+
+/// Encodes text as a PDF string in WinAnsiEncoding
+fn win_ansi(text: &str) -> String {
+    let mut encoded = String::new();
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                encoded.push('\\');
+                encoded.push(c);
+            }
+            ' '..='~' => encoded.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(encoded, "\\{:03o}", c as u32);
+            }
+            _ => encoded.push('?'),
+        }
+    }
+    encoded
+}
+
+/// Collects numbered objects and writes them with a cross-reference table
+struct ObjectWriter {
+    objects: Vec<Vec<u8>>,
+}
+
+impl ObjectWriter {
+    fn new() -> Self {
+        Self { objects: vec![] }
+    }
+
+    /// Reserves object numbers that are filled in later with set()
+    fn reserve(&mut self, count: usize) {
+        self.objects.resize(self.objects.len() + count, vec![]);
+    }
+
+    fn set(&mut self, id: usize, object: &[u8]) {
+        self.objects[id - 1] = object.to_vec();
+    }
+
+    fn add(&mut self, object: &[u8]) -> usize {
+        self.objects.push(object.to_vec());
+        self.objects.len()
+    }
+
+    fn add_stream(&mut self, dictionary: &str, data: &[u8]) -> usize {
+        let mut object = format!("<< {}/Length {} >>\nstream\n", dictionary, data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        self.add(&object)
+    }
+
+    fn finish<W: Write>(self, w: &mut W) -> std::io::Result<()> {
+        let mut offset = 0;
+        let mut offsets = vec![];
+
+        let header = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n";
+        w.write_all(header)?;
+        offset += header.len();
+
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(offset);
+            let start = format!("{} 0 obj\n", i + 1);
+            w.write_all(start.as_bytes())?;
+            w.write_all(object)?;
+            w.write_all(b"\nendobj\n")?;
+            offset += start.len() + object.len() + 8;
+        }
+
+        write!(w, "xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1)?;
+        for offset in offsets {
+            writeln!(w, "{:010} 00000 n ", offset)?;
+        }
+        write!(
+            w,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.objects.len() + 1,
+            offset
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+        data.windows(needle.len()).position(|i| i == needle)
+    }
+
+    /// Checks the cross-reference table and returns the number of objects
+    fn check_xref(pdf: &[u8]) -> usize {
+        let tail = std::str::from_utf8(&pdf[pdf.len() - 64..]).unwrap();
+        let startxref: usize = tail.split("startxref\n").nth(1).unwrap().lines().next().unwrap().parse().unwrap();
+        let xref = std::str::from_utf8(&pdf[startxref..]).unwrap();
+        let mut lines = xref.lines();
+        assert_eq!(lines.next(), Some("xref"));
+        let count: usize = lines.next().unwrap().strip_prefix("0 ").unwrap().parse().unwrap();
+        assert_eq!(lines.next(), Some("0000000000 65535 f "));
+        for id in 1..count {
+            let entry = lines.next().unwrap();
+            assert!(entry.ends_with(" 00000 n "), "{}", entry);
+            let offset: usize = entry[..10].parse().unwrap();
+            let object = format!("{} 0 obj\n", id);
+            assert_eq!(&pdf[offset..offset + object.len()], object.as_bytes(), "object {}", id);
+        }
+        assert_eq!(lines.next(), Some("trailer"));
+        assert_eq!(lines.next(), Some(format!("<< /Size {} /Root 1 0 R >>", count).as_str()));
+        count - 1
+    }
+
+    fn write(surface: &PdfSurface) -> Vec<u8> {
+        let mut pdf = vec![];
+        surface.write_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    #[test]
+    fn empty_document_has_one_page() {
+        let surface = PdfSurface::new(100.0, 50.0);
+        assert_eq!(surface.page_count(), 1);
+        let pdf = write(&surface);
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        // Catalog, pages, resources, the content and the page
+        assert_eq!(check_xref(&pdf), 5);
+        assert!(find(&pdf, b"/Count 1").is_some());
+        assert!(find(&pdf, b"/MediaBox [0 0 100 50]").is_some());
+    }
+
+    #[test]
+    fn xref_points_at_every_object() {
+        let mut surface = PdfSurface::new(100.0, 100.0);
+        surface.set_font(font::tests::test_font()).unwrap();
+        surface.draw(&[
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Black, 10, 10, 30, 20),
+            DrawTarget::BeginLayer(128),
+            DrawTarget::DrawText(Color::Black, FontInfo::new(12, false, false), 0, 0, 100, 20, UString::new("abc")),
+            DrawTarget::EndLayer,
+        ]);
+        surface.show_page();
+        surface.resize(50.0, 50.0);
+        surface.draw(&[DrawTarget::DrawRectangle(Color::Silver, 2, 0, 0, 50, 50)]);
+        assert_eq!(surface.page_count(), 2);
+
+        let pdf = write(&surface);
+        // Catalog, pages, resources, 5 font objects, 3 opacities including the opaque one,
+        // the layer and 2 pages with their contents
+        assert_eq!(check_xref(&pdf), 16);
+        assert!(find(&pdf, b"/Count 2").is_some());
+        assert!(find(&pdf, b"/FontFile2").is_some());
+        assert!(find(&pdf, b"<0001> <0061>").is_some());
+        assert!(find(&pdf, b"/Helvetica").is_none());
+    }
+
+    #[test]
+    fn rectangles_paint_the_same_areas_as_the_rasterizer() {
+        let mut surface = PdfSurface::new(100.0, 100.0);
+        surface.draw(&[
+            DrawTarget::FillRectangle(Color::Silver, Color::Red, 10, 10, 30, 20),
+            DrawTarget::DrawRectangle(Color::Blue, 2, 0, 0, 20, 10),
+            // Borders that fill the rectangle and borders without a thickness
+            DrawTarget::DrawRectangle(Color::Blue, 3, 50, 50, 5, 20),
+            DrawTarget::DrawRectangle(Color::Lime, 0, 0, 0, 20, 10),
+        ]);
+        let content = &surface.content;
+        assert!(content.contains("10 10 30 1 re 10 29 30 1 re 10 11 1 18 re 39 11 1 18 re f\n"), "{}", content);
+        assert!(content.contains("11 11 28 18 re f\n"));
+        assert!(!content.contains("10 10 30 20 re"));
+        assert!(content.contains("0 0 20 2 re 0 8 20 2 re 0 2 2 6 re 18 2 2 6 re f\n"));
+        assert!(content.contains("50 50 5 20 re f\n"));
+        assert!(!content.contains(" w "));
+        assert!(!content.contains("0 1 0 rg"));
+    }
+
+    #[test]
+    fn images_are_embedded() {
+        let mut image = Pixmap::new(2, 1);
+        image.draw(&[
+            DrawTarget::FillRectangle(Color::Red, Color::Red, 0, 0, 1, 1),
+            DrawTarget::FillRectangle(Color::Silver, Color::Silver, 1, 0, 1, 1),
+        ]);
+        let mut opaque = Pixmap::new(1, 1);
+        opaque.draw(&[DrawTarget::Clear(Color::Blue)]);
+
+        let mut surface = PdfSurface::new(100.0, 50.0);
+        surface.draw_image(&image, 10, 5, 20, 10);
+        surface.draw_image(&opaque, 0, 0, 1, 1);
+        let pdf = write(&surface);
+        // Catalog, pages, resources, the image with its mask, the opaque image and the page with its content
+        assert_eq!(check_xref(&pdf), 8);
+
+        let rgb = b"/Type /XObject /Subtype /Image /Width 2 /Height 1 /BitsPerComponent 8 /ColorSpace /DeviceRGB /SMask 4 0 R /Length 6 >>\nstream\n\xff\x00\x00\xc0\xc0\xc0\n";
+        assert!(find(&pdf, rgb).is_some());
+        assert!(find(&pdf, b"/ColorSpace /DeviceGray /Length 2 >>\nstream\n\xff\xc0\n").is_some());
+        assert!(find(&pdf, b"/ColorSpace /DeviceRGB /Length 3 >>").is_some());
+        assert!(find(&pdf, b"q 20 0 0 10 10 35 cm /I0 Do Q\nq 1 0 0 1 0 49 cm /I1 Do Q\n").is_some());
+        assert!(find(&pdf, b"/XObject << /I0 5 0 R /I1 6 0 R ").is_some());
+    }
+}
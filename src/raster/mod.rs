@@ -0,0 +1,364 @@
+mod simd;
+mod tiled;
+
+pub use tiled::TiledRasterizer;
+
+use crate::{Color, DrawTarget, Rect, Surface, Vec4};
+
+use std::sync::Once;
+
+/// RGBA buffer that the software surfaces rasterize into
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pixmap {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    clip: Option<Rect>,
+    /// Pixels below the layers that are being drawn, the innermost last
+    layers: Vec<Layer>,
+}
+
+/// Pixels a layer is blended over when it ends
+#[derive(Clone, Debug, PartialEq)]
+struct Layer {
+    backdrop: Vec<u8>,
+    opacity: u8,
+}
+
+impl Pixmap {
+    /// Creates a transparent pixmap
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+            clip: None,
+            layers: vec![],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Changes the size of the pixmap. The contents become transparent.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.data.clear();
+        self.data.resize(width as usize * height as usize * 4, 0);
+        self.layers.clear();
+    }
+
+    /// Pixels in RGBA order, row by row
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.data.as_mut_slice()
+    }
+
+    /// Gets the RGBA value of a pixel
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let index = (y as usize * self.width as usize + x as usize) * 4;
+        Some([
+            self.data[index],
+            self.data[index + 1],
+            self.data[index + 2],
+            self.data[index + 3],
+        ])
+    }
+
+    /// Restricts drawing to a rectangle, None draws to the whole pixmap
+    pub fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
+    }
+
+    pub fn clip(&self) -> Option<Rect> {
+        self.clip
+    }
+
+    /// Fills the whole pixmap, or the clip rectangle, without blending
+    pub fn clear(&mut self, color: Color) {
+        self.paint(Rect::new(0, 0, self.width, self.height), rgba(color), Paint::Copy);
+    }
+
+    /// Blends a rectangle over the pixmap. Parts outside the pixmap or the clip rectangle are ignored.
+    pub fn fill_rectangle(&mut self, color: Color, x: u32, y: u32, width: u32, height: u32) {
+        self.paint(Rect::new(x, y, width, height), rgba(color), Paint::Blend);
+    }
+
+    /// Fills a rectangle with a 1 pixel border, like DrawTarget::FillRectangle
+    pub fn fill_bordered_rectangle(
+        &mut self,
+        color: Color,
+        border_color: Color,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) {
+        let command = DrawTarget::FillRectangle(color, border_color, x, y, width, height);
+        self.draw_command(&command);
+    }
+
+    /// Outlines a rectangle, the border is drawn inside of the rectangle
+    pub fn draw_rectangle(
+        &mut self,
+        color: Color,
+        thickness: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) {
+        let command = DrawTarget::DrawRectangle(color, thickness, x, y, width, height);
+        self.draw_command(&command);
+    }
+
+    /// Rasterizes a single command
+    pub fn draw_command(&mut self, command: &DrawTarget) {
+        match *command {
+            DrawTarget::BeginLayer(opacity) => self.begin_layer(opacity),
+            DrawTarget::EndLayer => self.end_layer(),
+            _ => spans(command, (self.width, self.height), |rect, color, paint| self.paint(rect, color, paint)),
+        }
+    }
+
+    /// Following drawings go to a transparent layer, which is blended over the pixmap by end_layer
+    pub fn begin_layer(&mut self, opacity: u8) {
+        begin_layer(&mut self.data, &mut self.layers, opacity);
+    }
+
+    /// Blends the innermost layer over the pixels below it, inside of the clip rectangle.
+    /// Does nothing if no layer was begun.
+    pub fn end_layer(&mut self) {
+        let area = Rect::new(0, 0, self.width, self.height);
+        let rect = match self.clip {
+            Some(clip) => area.intersection(&clip),
+            None => Some(area),
+        };
+        end_layer(&mut self.data, &mut self.layers, area, rect);
+    }
+
+    /// Ends the layers that were begun but not ended
+    fn end_layers(&mut self) {
+        while !self.layers.is_empty() {
+            self.end_layer();
+        }
+    }
+
+    /// Paints a rectangle, clipped to the pixmap and the clip rectangle
+    fn paint(&mut self, rect: Rect, color: [u8; 4], paint: Paint) {
+        let rect = match self.clip {
+            Some(clip) => rect.intersection(&clip),
+            None => Some(rect),
+        };
+        if let Some(rect) = rect {
+            paint_rect(&mut self.data, Rect::new(0, 0, self.width, self.height), rect, color, paint);
+        }
+    }
+
+    /// Blends another pixmap over this one with its top left corner at x, y.
+    /// Parts outside of this pixmap or the clip rectangle are ignored.
+    pub fn blit(&mut self, source: &Pixmap, x: u32, y: u32) {
+        let mut rect = Rect::new(x, y, source.width, source.height);
+        if let Some(clip) = self.clip {
+            rect = match rect.intersection(&clip) {
+                Some(rect) => rect,
+                None => return,
+            };
+        }
+        let Some(rect) = rect.intersection(&Rect::new(0, 0, self.width, self.height)) else {
+            return;
+        };
+
+        let blit = simd::kernels().blit;
+        let length = rect.width as usize * 4;
+        for row in rect.y..rect.bottom() {
+            let start = (row as usize * self.width as usize + rect.x as usize) * 4;
+            let from = ((row - y) as usize * source.width as usize + (rect.x - x) as usize) * 4;
+            blit(&mut self.data[start..start + length], &source.data[from..from + length]);
+        }
+    }
+}
+
+/// How a span is combined with the pixels below it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Paint {
+    /// The pixels are replaced
+    Copy,
+    /// The color is blended over the pixels
+    Blend,
+}
+
+/// Splits a command into the rectangles it paints, in order.
+/// Every rasterizer paints commands this way, so their output is the same.
+fn spans(command: &DrawTarget, (width, height): (u32, u32), mut f: impl FnMut(Rect, [u8; 4], Paint)) {
+    match *command {
+        DrawTarget::Clear(color) => f(Rect::new(0, 0, width, height), rgba(color), Paint::Copy),
+        DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+            outline(1, Rect::new(x, y, width, height), |rect| f(rect, rgba(border_color), Paint::Blend));
+            if width > 2 && height > 2 {
+                f(Rect::new(x.saturating_add(1), y.saturating_add(1), width - 2, height - 2), rgba(color), Paint::Blend);
+            }
+        }
+        DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+            outline(thickness, Rect::new(x, y, width, height), |rect| f(rect, rgba(color), Paint::Blend));
+        }
+        DrawTarget::DrawText(..) => skip_text(),
+        // Layers are handled by the rasterizers, they do not paint anything themselves
+        DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+    }
+}
+
+/// There is no font rasterizer yet, so text is left out of rasterized images.
+/// Warns the first time, instead of silently showing an image without its text.
+pub(crate) fn skip_text() {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| warn!("Text is not rasterized yet, DrawText commands are left out of rasterized images"));
+}
+
+/// Splits the outline of a rectangle into its sides, the border is inside of the rectangle
+pub(crate) fn outline(thickness: u32, rect: Rect, mut f: impl FnMut(Rect)) {
+    let Rect { x, y, width, height } = rect;
+    if thickness.saturating_mul(2) >= width || thickness.saturating_mul(2) >= height {
+        f(rect);
+        return;
+    }
+
+    // Saturating, the sides of rectangles near the end of the coordinate space are off the surface anyway
+    let inner = height - thickness * 2;
+    f(Rect::new(x, y, width, thickness));
+    f(Rect::new(x, y.saturating_add(height - thickness), width, thickness));
+    f(Rect::new(x, y.saturating_add(thickness), thickness, inner));
+    f(Rect::new(x.saturating_add(width - thickness), y.saturating_add(thickness), thickness, inner));
+}
+
+/// Paints a rectangle into pixels that cover the area, parts outside of the area are ignored
+fn paint_rect(data: &mut [u8], area: Rect, rect: Rect, color: [u8; 4], paint: Paint) {
+    let Some(rect) = rect.intersection(&area) else {
+        return;
+    };
+
+    let stride = area.width as usize * 4;
+    for row in rect.y..rect.bottom() {
+        let start = (row - area.y) as usize * stride + (rect.x - area.x) as usize * 4;
+        let span = &mut data[start..start + rect.width as usize * 4];
+        match paint {
+            Paint::Copy => (simd::kernels().fill)(span, color),
+            Paint::Blend => (simd::kernels().blend)(span, color),
+        }
+    }
+}
+
+/// Starts a layer: the pixels are kept as its backdrop and the layer starts transparent
+fn begin_layer(data: &mut Vec<u8>, layers: &mut Vec<Layer>, opacity: u8) {
+    let layer = vec![0; data.len()];
+    layers.push(Layer {
+        backdrop: std::mem::replace(data, layer),
+        opacity,
+    });
+}
+
+/// Blends the innermost layer over its backdrop inside of rect, the pixels cover the area
+fn end_layer(data: &mut Vec<u8>, layers: &mut Vec<Layer>, area: Rect, rect: Option<Rect>) {
+    let Some(Layer { backdrop, opacity }) = layers.pop() else {
+        return;
+    };
+    let layer = std::mem::replace(data, backdrop);
+    let Some(rect) = rect.and_then(|rect| rect.intersection(&area)) else {
+        return;
+    };
+
+    let blit = simd::kernels().blit;
+    let stride = area.width as usize * 4;
+    let mut row = vec![];
+    for y in rect.y..rect.bottom() {
+        let start = (y - area.y) as usize * stride + (rect.x - area.x) as usize * 4;
+        let span = start..start + rect.width as usize * 4;
+        if opacity == 255 {
+            blit(&mut data[span.clone()], &layer[span]);
+            continue;
+        }
+        // The opacity of the layer scales the alpha of its pixels
+        row.clear();
+        row.extend_from_slice(&layer[span.clone()]);
+        for pixel in row.chunks_exact_mut(4) {
+            pixel[3] = ((pixel[3] as u32 * opacity as u32 + 127) / 255) as u8;
+        }
+        blit(&mut data[span], &row);
+    }
+}
+
+impl Surface for Pixmap {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        for i in ctx {
+            self.draw_command(i);
+        }
+        self.end_layers();
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        let clip = self.clip;
+        for rect in damage {
+            let rect = match clip {
+                Some(clip) => rect.intersection(&clip),
+                None => Some(*rect),
+            };
+            let Some(rect) = rect else {
+                continue;
+            };
+            self.clip = Some(rect);
+            // Commands outside of the region would be clipped away completely
+            for i in ctx.iter().filter(|i| i.intersects(&rect)) {
+                self.draw_command(i);
+            }
+            self.end_layers();
+        }
+        self.clip = clip;
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+fn rgba(color: Color) -> [u8; 4] {
+    let color = Vec4::from(color);
+    [color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn commands_near_the_end_of_the_coordinates_are_ignored() {
+        let max = u32::MAX;
+        let mut pixmap = Pixmap::new(10, 10);
+        pixmap.draw(&[
+            DrawTarget::FillRectangle(Color::Red, Color::Blue, max, 0, 3, 3),
+            DrawTarget::FillRectangle(Color::Red, Color::Blue, 0, max - 1, 5, 5),
+            DrawTarget::DrawRectangle(Color::Red, 2, 0, max - 5, 100, 100),
+            DrawTarget::DrawRectangle(Color::Red, 1, max - 2, max - 2, max, max),
+        ]);
+        assert!(pixmap.as_slice().iter().all(|i| *i == 0));
+        TiledRasterizer::new(2).with_tile_size(4).draw(&mut pixmap, &[DrawTarget::FillRectangle(Color::Red, Color::Blue, max, 0, 3, 3)]);
+        assert!(pixmap.as_slice().iter().all(|i| *i == 0));
+
+        // Sides that end past the coordinates are still drawn where they are on the pixmap
+        pixmap.draw(&[DrawTarget::DrawRectangle(Color::Red, 1, 9, 9, max, max)]);
+        assert_eq!(pixmap.pixel(9, 9), Some([255, 0, 0, 255]));
+        assert_eq!(pixmap.pixel(8, 9), Some([0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,596 @@
+//! Span kernels of the software rasterizer.
+//! The SIMD kernels take a fast path for runs of opaque destination pixels,
+//! which is where source-over blending reduces to a division by 255 that can be done exactly with integers.
+//! On x86_64, other runs, e.g. inside of a layer, which starts transparent, divide by the blended alpha in f32,
+//! which is exact for these numbers. Every kernel gives the same pixels as the scalar code.
+//!
+//! Limitation: the NEON kernels only vectorize blends over opaque pixels,
+//! blending over translucent pixels runs at scalar speed on aarch64.
+
+use std::sync::OnceLock;
+
+/// Kernels working on spans of RGBA pixels
+#[derive(Clone, Copy)]
+pub(crate) struct Kernels {
+    /// Replaces every pixel with a color
+    pub fill: fn(&mut [u8], [u8; 4]),
+    /// Blends a color over every pixel
+    pub blend: fn(&mut [u8], [u8; 4]),
+    /// Blends the pixels of the second span over the first one
+    pub blit: fn(&mut [u8], &[u8]),
+}
+
+/// Kernels for the CPU the program runs on, detected the first time they are used
+pub(crate) fn kernels() -> &'static Kernels {
+    static KERNELS: OnceLock<Kernels> = OnceLock::new();
+    KERNELS.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return x86::AVX2;
+            }
+            // SSE2 is part of x86_64
+            return x86::SSE2;
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return neon::NEON;
+            }
+        }
+        #[allow(unreachable_code)]
+        SCALAR
+    })
+}
+
+pub(crate) const SCALAR: Kernels = Kernels {
+    fill: fill_scalar,
+    blend: blend_scalar,
+    blit: blit_scalar,
+};
+
+fn fill_scalar(span: &mut [u8], color: [u8; 4]) {
+    for pixel in span.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&color);
+    }
+}
+
+fn blend_scalar(span: &mut [u8], color: [u8; 4]) {
+    match color[3] {
+        255 => fill_scalar(span, color),
+        0 => {}
+        _ => {
+            for pixel in span.chunks_exact_mut(4) {
+                blend_pixel(pixel, color);
+            }
+        }
+    }
+}
+
+fn blit_scalar(span: &mut [u8], source: &[u8]) {
+    for (pixel, color) in span.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+        blend_pixel(pixel, [color[0], color[1], color[2], color[3]]);
+    }
+}
+
+/// Source-over blending of a color onto a single pixel
+fn blend_pixel(pixel: &mut [u8], color: [u8; 4]) {
+    if color[3] == 255 {
+        pixel.copy_from_slice(&color);
+        return;
+    }
+
+    let alpha = color[3] as u32;
+    let inverse = 255 - alpha;
+    // Pixels are not premultiplied, so the destination is weighted by its own alpha
+    let behind = (pixel[3] as u32 * inverse + 127) / 255;
+    let total = alpha + behind;
+    if total == 0 {
+        return;
+    }
+    for c in 0..3 {
+        pixel[c] = ((color[c] as u32 * alpha + pixel[c] as u32 * behind + total / 2) / total) as u8;
+    }
+    pixel[3] = total as u8;
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    //! Over an opaque pixel, blending is (color * alpha + pixel * (255 - alpha) + 127) / 255 and the pixel stays opaque.
+    //! For x <= 65152, x / 255 equals ((x + 1) * 257) >> 16, which is a single mulhi.
+    //! Over other pixels, the channels are divided by the blended alpha like in blend_pixel. The numerators are below 2^24
+    //! and the quotients at most 255.5, so the rounding error of an f32 division never reaches the next integer.
+
+    use super::{blend_scalar, blit_scalar, fill_scalar, Kernels};
+    use std::arch::x86_64::*;
+
+    pub(super) const SSE2: Kernels = Kernels {
+        fill: |span, color| unsafe { fill_sse2(span, color) },
+        blend: |span, color| unsafe { blend_sse2(span, color) },
+        blit: |span, source| unsafe { blit_sse2(span, source) },
+    };
+
+    pub(super) const AVX2: Kernels = Kernels {
+        fill: |span, color| unsafe { fill_avx2(span, color) },
+        blend: |span, color| unsafe { blend_avx2(span, color) },
+        blit: |span, source| unsafe { blit_avx2(span, source) },
+    };
+
+    const ALPHA: i32 = 0xff000000u32 as i32;
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn fill_sse2(span: &mut [u8], color: [u8; 4]) {
+        let value = _mm_set1_epi32(i32::from_le_bytes(color));
+        let mut chunks = span.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            _mm_storeu_si128(chunk.as_mut_ptr().cast(), value);
+        }
+        fill_scalar(chunks.into_remainder(), color);
+    }
+
+    /// Blends 4 pixels over opaque pixels. The color and its alpha are 16 bit lanes, two pixels per register.
+    #[target_feature(enable = "sse2")]
+    unsafe fn blend_opaque_sse2(pixels: __m128i, color: (__m128i, __m128i), alpha: (__m128i, __m128i)) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let one = _mm_set1_epi16(128);
+        let divisor = _mm_set1_epi16(257);
+        let max = _mm_set1_epi16(255);
+        let half = |pixels: __m128i, color: __m128i, alpha: __m128i| {
+            let product = _mm_add_epi16(_mm_mullo_epi16(color, alpha), _mm_mullo_epi16(pixels, _mm_sub_epi16(max, alpha)));
+            _mm_mulhi_epu16(_mm_add_epi16(product, one), divisor)
+        };
+        let low = half(_mm_unpacklo_epi8(pixels, zero), color.0, alpha.0);
+        let high = half(_mm_unpackhi_epi8(pixels, zero), color.1, alpha.1);
+        _mm_or_si128(_mm_packus_epi16(low, high), _mm_set1_epi32(ALPHA))
+    }
+
+    /// Blends 4 pixels over pixels with any alpha, see blend_pixel
+    #[target_feature(enable = "sse2")]
+    unsafe fn blend_any_sse2(pixels: __m128i, colors: __m128i) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let one = _mm_set1_epi16(128);
+        let divisor = _mm_set1_epi16(257);
+        let max = _mm_set1_epi16(255);
+        let alpha_lanes = _mm_set_epi16(-1, 0, 0, 0, -1, 0, 0, 0);
+        let broadcast = |v: __m128i| _mm_shufflehi_epi16::<0xff>(_mm_shufflelo_epi16::<0xff>(v));
+        // Two pixels in 16 bit lanes
+        let half = |pixels: __m128i, colors: __m128i| {
+            let alpha = broadcast(colors);
+            let below = broadcast(pixels);
+            let behind = _mm_mulhi_epu16(_mm_add_epi16(_mm_mullo_epi16(below, _mm_sub_epi16(max, alpha)), one), divisor);
+            let total = _mm_add_epi16(alpha, behind);
+            let rounding = _mm_srli_epi16::<1>(total);
+            // One pixel in 32 bit lanes
+            let divide = |colors: __m128i, pixels: __m128i, weights: __m128i, rounding: __m128i, total: __m128i| {
+                let numerator = _mm_add_epi32(_mm_madd_epi16(_mm_unpacklo_epi16(colors, pixels), weights), rounding);
+                _mm_cvttps_epi32(_mm_div_ps(_mm_cvtepi32_ps(numerator), _mm_cvtepi32_ps(total)))
+            };
+            let low = divide(
+                colors,
+                pixels,
+                _mm_unpacklo_epi16(alpha, behind),
+                _mm_unpacklo_epi16(rounding, zero),
+                _mm_unpacklo_epi16(total, zero),
+            );
+            let high = divide(
+                _mm_unpackhi_epi64(colors, zero),
+                _mm_unpackhi_epi64(pixels, zero),
+                _mm_unpackhi_epi16(alpha, behind),
+                _mm_unpackhi_epi16(rounding, zero),
+                _mm_unpackhi_epi16(total, zero),
+            );
+            // The alpha is the blended alpha, and nothing is blended where it is 0
+            let result = _mm_packs_epi32(low, high);
+            let result = _mm_or_si128(_mm_andnot_si128(alpha_lanes, result), _mm_and_si128(alpha_lanes, total));
+            let empty = _mm_cmpeq_epi16(total, zero);
+            _mm_or_si128(_mm_andnot_si128(empty, result), _mm_and_si128(empty, pixels))
+        };
+        let low = half(_mm_unpacklo_epi8(pixels, zero), _mm_unpacklo_epi8(colors, zero));
+        let high = half(_mm_unpackhi_epi8(pixels, zero), _mm_unpackhi_epi8(colors, zero));
+        _mm_packus_epi16(low, high)
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn is_opaque_sse2(pixels: __m128i) -> bool {
+        let mask = _mm_set1_epi32(ALPHA);
+        _mm_movemask_epi8(_mm_cmpeq_epi32(_mm_and_si128(pixels, mask), mask)) == 0xffff
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn blend_sse2(span: &mut [u8], color: [u8; 4]) {
+        match color[3] {
+            255 => return fill_sse2(span, color),
+            0 => return,
+            _ => {}
+        }
+        let [r, g, b, a] = color.map(|c| c as i16);
+        let source = _mm_set_epi16(a, b, g, r, a, b, g, r);
+        let alpha = _mm_set1_epi16(a);
+        let colors = _mm_set1_epi32(i32::from_le_bytes(color));
+
+        let mut chunks = span.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let pixels = _mm_loadu_si128(chunk.as_ptr().cast());
+            let result = if is_opaque_sse2(pixels) {
+                blend_opaque_sse2(pixels, (source, source), (alpha, alpha))
+            } else {
+                blend_any_sse2(pixels, colors)
+            };
+            _mm_storeu_si128(chunk.as_mut_ptr().cast(), result);
+        }
+        blend_scalar(chunks.into_remainder(), color);
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn blit_sse2(span: &mut [u8], source: &[u8]) {
+        let zero = _mm_setzero_si128();
+        let mut chunks = span.chunks_exact_mut(16);
+        let mut sources = source.chunks_exact(16);
+        for (chunk, source) in (&mut chunks).zip(&mut sources) {
+            let pixels = _mm_loadu_si128(chunk.as_ptr().cast());
+            let colors = _mm_loadu_si128(source.as_ptr().cast());
+            if !is_opaque_sse2(pixels) {
+                _mm_storeu_si128(chunk.as_mut_ptr().cast(), blend_any_sse2(pixels, colors));
+                continue;
+            }
+            let low = _mm_unpacklo_epi8(colors, zero);
+            let high = _mm_unpackhi_epi8(colors, zero);
+            // Every channel of a pixel is weighted by the alpha of the pixel
+            let broadcast = |v: __m128i| _mm_shufflehi_epi16::<0xff>(_mm_shufflelo_epi16::<0xff>(v));
+            let result = blend_opaque_sse2(pixels, (low, high), (broadcast(low), broadcast(high)));
+            _mm_storeu_si128(chunk.as_mut_ptr().cast(), result);
+        }
+        blit_scalar(chunks.into_remainder(), sources.remainder());
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn fill_avx2(span: &mut [u8], color: [u8; 4]) {
+        let value = _mm256_set1_epi32(i32::from_le_bytes(color));
+        let mut chunks = span.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            _mm256_storeu_si256(chunk.as_mut_ptr().cast(), value);
+        }
+        fill_sse2(chunks.into_remainder(), color);
+    }
+
+    /// Blends 8 pixels over opaque pixels, like blend_opaque_sse2 for each 128 bit lane
+    #[target_feature(enable = "avx2")]
+    unsafe fn blend_opaque_avx2(pixels: __m256i, color: (__m256i, __m256i), alpha: (__m256i, __m256i)) -> __m256i {
+        let zero = _mm256_setzero_si256();
+        let one = _mm256_set1_epi16(128);
+        let divisor = _mm256_set1_epi16(257);
+        let max = _mm256_set1_epi16(255);
+        let half = |pixels: __m256i, color: __m256i, alpha: __m256i| {
+            let product = _mm256_add_epi16(
+                _mm256_mullo_epi16(color, alpha),
+                _mm256_mullo_epi16(pixels, _mm256_sub_epi16(max, alpha)),
+            );
+            _mm256_mulhi_epu16(_mm256_add_epi16(product, one), divisor)
+        };
+        let low = half(_mm256_unpacklo_epi8(pixels, zero), color.0, alpha.0);
+        let high = half(_mm256_unpackhi_epi8(pixels, zero), color.1, alpha.1);
+        _mm256_or_si256(_mm256_packus_epi16(low, high), _mm256_set1_epi32(ALPHA))
+    }
+
+    /// Blends 8 pixels over pixels with any alpha, like blend_any_sse2 for each 128 bit lane
+    #[target_feature(enable = "avx2")]
+    unsafe fn blend_any_avx2(pixels: __m256i, colors: __m256i) -> __m256i {
+        let zero = _mm256_setzero_si256();
+        let one = _mm256_set1_epi16(128);
+        let divisor = _mm256_set1_epi16(257);
+        let max = _mm256_set1_epi16(255);
+        let alpha_lanes = _mm256_set_epi16(-1, 0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0);
+        let broadcast = |v: __m256i| _mm256_shufflehi_epi16::<0xff>(_mm256_shufflelo_epi16::<0xff>(v));
+        let half = |pixels: __m256i, colors: __m256i| {
+            let alpha = broadcast(colors);
+            let below = broadcast(pixels);
+            let behind = _mm256_mulhi_epu16(
+                _mm256_add_epi16(_mm256_mullo_epi16(below, _mm256_sub_epi16(max, alpha)), one),
+                divisor,
+            );
+            let total = _mm256_add_epi16(alpha, behind);
+            let rounding = _mm256_srli_epi16::<1>(total);
+            let divide = |colors: __m256i, pixels: __m256i, weights: __m256i, rounding: __m256i, total: __m256i| {
+                let numerator =
+                    _mm256_add_epi32(_mm256_madd_epi16(_mm256_unpacklo_epi16(colors, pixels), weights), rounding);
+                _mm256_cvttps_epi32(_mm256_div_ps(_mm256_cvtepi32_ps(numerator), _mm256_cvtepi32_ps(total)))
+            };
+            let low = divide(
+                colors,
+                pixels,
+                _mm256_unpacklo_epi16(alpha, behind),
+                _mm256_unpacklo_epi16(rounding, zero),
+                _mm256_unpacklo_epi16(total, zero),
+            );
+            let high = divide(
+                _mm256_unpackhi_epi64(colors, zero),
+                _mm256_unpackhi_epi64(pixels, zero),
+                _mm256_unpackhi_epi16(alpha, behind),
+                _mm256_unpackhi_epi16(rounding, zero),
+                _mm256_unpackhi_epi16(total, zero),
+            );
+            let result = _mm256_packs_epi32(low, high);
+            let result =
+                _mm256_or_si256(_mm256_andnot_si256(alpha_lanes, result), _mm256_and_si256(alpha_lanes, total));
+            let empty = _mm256_cmpeq_epi16(total, zero);
+            _mm256_or_si256(_mm256_andnot_si256(empty, result), _mm256_and_si256(empty, pixels))
+        };
+        let low = half(_mm256_unpacklo_epi8(pixels, zero), _mm256_unpacklo_epi8(colors, zero));
+        let high = half(_mm256_unpackhi_epi8(pixels, zero), _mm256_unpackhi_epi8(colors, zero));
+        _mm256_packus_epi16(low, high)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn is_opaque_avx2(pixels: __m256i) -> bool {
+        let mask = _mm256_set1_epi32(ALPHA);
+        _mm256_movemask_epi8(_mm256_cmpeq_epi32(_mm256_and_si256(pixels, mask), mask)) == -1
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn blend_avx2(span: &mut [u8], color: [u8; 4]) {
+        match color[3] {
+            255 => return fill_avx2(span, color),
+            0 => return,
+            _ => {}
+        }
+        let [r, g, b, a] = color.map(|c| c as i16);
+        let source = _mm256_set_epi16(a, b, g, r, a, b, g, r, a, b, g, r, a, b, g, r);
+        let alpha = _mm256_set1_epi16(a);
+        let colors = _mm256_set1_epi32(i32::from_le_bytes(color));
+
+        let mut chunks = span.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let pixels = _mm256_loadu_si256(chunk.as_ptr().cast());
+            let result = if is_opaque_avx2(pixels) {
+                blend_opaque_avx2(pixels, (source, source), (alpha, alpha))
+            } else {
+                blend_any_avx2(pixels, colors)
+            };
+            _mm256_storeu_si256(chunk.as_mut_ptr().cast(), result);
+        }
+        blend_sse2(chunks.into_remainder(), color);
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn blit_avx2(span: &mut [u8], source: &[u8]) {
+        let zero = _mm256_setzero_si256();
+        // Copies the alpha of each pixel to its 4 channels
+        let broadcast = _mm256_setr_epi8(
+            6, 7, 6, 7, 6, 7, 6, 7, 14, 15, 14, 15, 14, 15, 14, 15, 6, 7, 6, 7, 6, 7, 6, 7, 14, 15, 14, 15, 14, 15, 14, 15,
+        );
+        let mut chunks = span.chunks_exact_mut(32);
+        let mut sources = source.chunks_exact(32);
+        for (chunk, source) in (&mut chunks).zip(&mut sources) {
+            let pixels = _mm256_loadu_si256(chunk.as_ptr().cast());
+            let colors = _mm256_loadu_si256(source.as_ptr().cast());
+            if !is_opaque_avx2(pixels) {
+                _mm256_storeu_si256(chunk.as_mut_ptr().cast(), blend_any_avx2(pixels, colors));
+                continue;
+            }
+            let low = _mm256_unpacklo_epi8(colors, zero);
+            let high = _mm256_unpackhi_epi8(colors, zero);
+            let alpha = (_mm256_shuffle_epi8(low, broadcast), _mm256_shuffle_epi8(high, broadcast));
+            let result = blend_opaque_avx2(pixels, (low, high), alpha);
+            _mm256_storeu_si256(chunk.as_mut_ptr().cast(), result);
+        }
+        blit_sse2(chunks.into_remainder(), sources.remainder());
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    //! Over an opaque pixel, blending is (color * alpha + pixel * (255 - alpha) + 127) / 255 and the pixel stays opaque.
+    //! For x <= 65152, x / 255 equals (x + 1 + ((x + 1) >> 8)) >> 8.
+
+    use super::{blend_pixel, blend_scalar, blit_scalar, fill_scalar, Kernels};
+    use std::arch::aarch64::*;
+
+    pub(super) const NEON: Kernels = Kernels {
+        fill: |span, color| unsafe { fill_neon(span, color) },
+        blend: |span, color| unsafe { blend_neon(span, color) },
+        blit: |span, source| unsafe { blit_neon(span, source) },
+    };
+
+    const ALPHA: u32 = 0xff000000;
+
+    #[target_feature(enable = "neon")]
+    unsafe fn fill_neon(span: &mut [u8], color: [u8; 4]) {
+        let value = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_le_bytes(color)));
+        let mut chunks = span.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            vst1q_u8(chunk.as_mut_ptr(), value);
+        }
+        fill_scalar(chunks.into_remainder(), color);
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn is_opaque_neon(pixels: uint8x16_t) -> bool {
+        let mask = vdupq_n_u32(ALPHA);
+        vminvq_u32(vceqq_u32(vandq_u32(vreinterpretq_u32_u8(pixels), mask), mask)) == u32::MAX
+    }
+
+    /// Blends 4 pixels over opaque pixels, the alpha of every channel is given by alpha
+    #[target_feature(enable = "neon")]
+    unsafe fn blend_opaque_neon(pixels: uint8x16_t, color: uint8x16_t, alpha: uint8x16_t) -> uint8x16_t {
+        let inverse = vmvnq_u8(alpha);
+        let half = |pixels: uint8x8_t, color: uint8x8_t, alpha: uint8x8_t, inverse: uint8x8_t| {
+            let sum = vmlal_u8(vmull_u8(color, alpha), pixels, inverse);
+            let x = vaddq_u16(sum, vdupq_n_u16(128));
+            vshrn_n_u16::<8>(vsraq_n_u16::<8>(x, x))
+        };
+        let low = half(vget_low_u8(pixels), vget_low_u8(color), vget_low_u8(alpha), vget_low_u8(inverse));
+        let high = half(vget_high_u8(pixels), vget_high_u8(color), vget_high_u8(alpha), vget_high_u8(inverse));
+        vorrq_u8(vcombine_u8(low, high), vreinterpretq_u8_u32(vdupq_n_u32(ALPHA)))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn blend_neon(span: &mut [u8], color: [u8; 4]) {
+        match color[3] {
+            255 => return fill_neon(span, color),
+            0 => return,
+            _ => {}
+        }
+        let source = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_le_bytes(color)));
+        let alpha = vdupq_n_u8(color[3]);
+
+        let mut chunks = span.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let pixels = vld1q_u8(chunk.as_ptr());
+            if !is_opaque_neon(pixels) {
+                for pixel in chunk.chunks_exact_mut(4) {
+                    blend_pixel(pixel, color);
+                }
+                continue;
+            }
+            vst1q_u8(chunk.as_mut_ptr(), blend_opaque_neon(pixels, source, alpha));
+        }
+        blend_scalar(chunks.into_remainder(), color);
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn blit_neon(span: &mut [u8], source: &[u8]) {
+        // Copies the alpha of each pixel to its 4 channels
+        let broadcast = vld1q_u8([3, 3, 3, 3, 7, 7, 7, 7, 11, 11, 11, 11, 15, 15, 15, 15].as_ptr());
+        let mut chunks = span.chunks_exact_mut(16);
+        let mut sources = source.chunks_exact(16);
+        for (chunk, source) in (&mut chunks).zip(&mut sources) {
+            let pixels = vld1q_u8(chunk.as_ptr());
+            if !is_opaque_neon(pixels) {
+                blit_scalar(chunk, source);
+                continue;
+            }
+            let colors = vld1q_u8(source.as_ptr());
+            let alpha = vqtbl1q_u8(colors, broadcast);
+            vst1q_u8(chunk.as_mut_ptr(), blend_opaque_neon(pixels, colors, alpha));
+        }
+        blit_scalar(chunks.into_remainder(), sources.remainder());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kernels that can run on this CPU
+    fn available() -> Vec<(&'static str, Kernels)> {
+        let mut kernels = vec![("detected", *kernels())];
+        #[cfg(target_arch = "x86_64")]
+        {
+            kernels.push(("sse2", x86::SSE2));
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(("avx2", x86::AVX2));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                kernels.push(("neon", neon::NEON));
+            }
+        }
+        kernels
+    }
+
+    /// Pixels with random colors, either an opaque run, mostly opaque pixels,
+    /// or translucent pixels like the ones of a layer, including transparent ones
+    fn pixels(seed: &mut u64, count: usize) -> Vec<u8> {
+        let mut next = || {
+            *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (*seed >> 33) as u8
+        };
+        let mode = next() % 3;
+        (0..count)
+            .flat_map(|_| {
+                let alpha = match mode {
+                    0 => 255,
+                    1 if next() % 4 != 0 => 255,
+                    _ if next() % 4 == 0 => 0,
+                    _ => next(),
+                };
+                [next(), next(), next(), alpha]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fill_matches_scalar() {
+        for (name, kernels) in available() {
+            for count in 0..40 {
+                let mut expected = vec![7; count * 4];
+                let mut actual = expected.clone();
+                fill_scalar(&mut expected, [1, 2, 3, 4]);
+                (kernels.fill)(&mut actual, [1, 2, 3, 4]);
+                assert_eq!(expected, actual, "{} with {} pixels", name, count);
+            }
+        }
+    }
+
+    #[test]
+    fn blend_matches_scalar() {
+        let mut seed = 1;
+        for (name, kernels) in available() {
+            for alpha in 0..=255u8 {
+                let count = alpha as usize % 37 + 1;
+                let span = pixels(&mut seed, count);
+                let color = [alpha.wrapping_mul(7), alpha.wrapping_mul(13), 255 - alpha, alpha];
+                let mut expected = span.clone();
+                let mut actual = span.clone();
+                blend_scalar(&mut expected, color);
+                (kernels.blend)(&mut actual, color);
+                assert_eq!(expected, actual, "{} with {:?} over {:?}", name, color, span);
+            }
+        }
+    }
+
+    #[test]
+    fn blit_matches_scalar() {
+        let mut seed = 2;
+        for (name, kernels) in available() {
+            for count in 0..200 {
+                let span = pixels(&mut seed, count % 41);
+                let source = pixels(&mut seed, count % 41);
+                let mut expected = span.clone();
+                let mut actual = span.clone();
+                blit_scalar(&mut expected, &source);
+                (kernels.blit)(&mut actual, &source);
+                assert_eq!(expected, actual, "{} with {:?} over {:?}", name, source, span);
+            }
+        }
+    }
+
+    #[test]
+    fn opaque_blending_is_exact() {
+        // Every color and alpha over every channel of an opaque pixel
+        for (name, kernels) in available() {
+            for alpha in 0..=255u8 {
+                let source: Vec<u8> = (0..=255u8).flat_map(|c| [c, c, c, alpha]).collect();
+                // Each channel covers a third of the values below the color
+                for below in 0..86u8 {
+                    let span: Vec<u8> = (0..256).flat_map(|_| [below, below + 85, below + 170, 255]).collect();
+                    let mut expected = span.clone();
+                    let mut actual = span;
+                    blit_scalar(&mut expected, &source);
+                    (kernels.blit)(&mut actual, &source);
+                    assert_eq!(expected, actual, "{} with alpha {} over {}", name, alpha, below);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn translucent_blending_is_exact() {
+        // Every color and alpha over every fifth alpha of the pixel below
+        for (name, kernels) in available() {
+            for alpha in 0..=255u8 {
+                let source: Vec<u8> = (0..=255u8).flat_map(|c| [c, 255 - c, c / 2, alpha]).collect();
+                for below in (0..=255u8).step_by(5) {
+                    let span: Vec<u8> = (0..=255u8).flat_map(|c| [255 - c, c / 3, c, below]).collect();
+                    let mut expected = span.clone();
+                    let mut actual = span;
+                    blit_scalar(&mut expected, &source);
+                    (kernels.blit)(&mut actual, &source);
+                    assert_eq!(expected, actual, "{} with alpha {} over alpha {}", name, alpha, below);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::raster::{begin_layer, end_layer, paint_rect, spans, Pixmap};
+use crate::{DrawTarget, Rect, Surface};
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Rasterizes into a Pixmap on several threads.
+/// The pixmap is split into tiles, every command is binned into the tiles its bounds touch
+/// and the tiles are rasterized in parallel. The pixels are the same as the ones of Pixmap::draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TiledRasterizer {
+    threads: usize,
+    tile_size: u32,
+}
+
+/// Part of the pixmap and the indices of the commands that touch it
+struct Tile {
+    rect: Rect,
+    commands: Vec<usize>,
+}
+
+impl TiledRasterizer {
+    /// Creates a rasterizer using the given number of threads, 0 uses one thread per CPU
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            tile_size: 256,
+        }
+    }
+
+    /// Sets the width and height of the tiles, 256 pixels by default
+    pub fn with_tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    /// Number of threads that are used
+    pub fn threads(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1),
+            threads => threads,
+        }
+    }
+
+    pub fn draw(&self, pixmap: &mut Pixmap, ctx: &[DrawTarget]) {
+        let threads = self.threads();
+        if threads <= 1 {
+            pixmap.draw(ctx);
+            return;
+        }
+
+        let size = (pixmap.width(), pixmap.height());
+        let mut area = Rect::new(0, 0, size.0, size.1);
+        if let Some(clip) = pixmap.clip() {
+            match area.intersection(&clip) {
+                Some(rect) => area = rect,
+                None => return,
+            }
+        }
+        if area.is_empty() {
+            return;
+        }
+
+        let tiles = self.bin(area, ctx);
+        let next = AtomicUsize::new(0);
+        let source: &Pixmap = pixmap;
+        let rendered: Vec<(usize, Vec<u8>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.min(tiles.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut rendered = vec![];
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(tile) = tiles.get(index) else {
+                                break;
+                            };
+                            rendered.push((index, render(source, tile, ctx, size)));
+                        }
+                        rendered
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+
+        let stride = size.0 as usize * 4;
+        let data = pixmap.as_mut_slice();
+        for (index, pixels) in rendered {
+            let rect = tiles[index].rect;
+            let row_length = rect.width as usize * 4;
+            for (row, line) in (rect.y..rect.bottom()).zip(pixels.chunks_exact(row_length)) {
+                let start = row as usize * stride + rect.x as usize * 4;
+                data[start..start + row_length].copy_from_slice(line);
+            }
+        }
+    }
+
+    /// Splits the area into tiles, tiles no command touches are left out
+    fn bin(&self, area: Rect, ctx: &[DrawTarget]) -> Vec<Tile> {
+        let tile_size = self.tile_size;
+        let columns = area.width.div_ceil(tile_size);
+        let rows = area.height.div_ceil(tile_size);
+        let mut tiles: Vec<Tile> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let (x, y) = (area.x + column * tile_size, area.y + row * tile_size);
+                let width = tile_size.min(area.right() - x);
+                let height = tile_size.min(area.bottom() - y);
+                Tile {
+                    rect: Rect::new(x, y, width, height),
+                    commands: vec![],
+                }
+            })
+            .collect();
+
+        for (index, command) in ctx.iter().enumerate() {
+            let bounds = match command.bounds() {
+                Some(bounds) => bounds.intersection(&area),
+                // Clear covers every tile
+                None => Some(area),
+            };
+            let Some(bounds) = bounds else {
+                continue;
+            };
+            let first_column = (bounds.x - area.x) / tile_size;
+            let last_column = (bounds.right() - 1 - area.x) / tile_size;
+            let first_row = (bounds.y - area.y) / tile_size;
+            let last_row = (bounds.bottom() - 1 - area.y) / tile_size;
+            for row in first_row..=last_row {
+                for column in first_column..=last_column {
+                    tiles[(row * columns + column) as usize].commands.push(index);
+                }
+            }
+        }
+
+        tiles.retain(|tile| !tile.commands.is_empty());
+        tiles
+    }
+}
+
+/// Rasterizes the commands of a tile over a copy of its pixels
+fn render(pixmap: &Pixmap, tile: &Tile, ctx: &[DrawTarget], size: (u32, u32)) -> Vec<u8> {
+    let rect = tile.rect;
+    let stride = pixmap.width() as usize * 4;
+    let row_length = rect.width as usize * 4;
+    let mut data = Vec::with_capacity(row_length * rect.height as usize);
+    for row in rect.y..rect.bottom() {
+        let start = row as usize * stride + rect.x as usize * 4;
+        data.extend_from_slice(&pixmap.as_slice()[start..start + row_length]);
+    }
+
+    let mut layers = vec![];
+    for &index in &tile.commands {
+        match ctx[index] {
+            DrawTarget::BeginLayer(opacity) => begin_layer(&mut data, &mut layers, opacity),
+            DrawTarget::EndLayer => end_layer(&mut data, &mut layers, rect, Some(rect)),
+            ref command => spans(command, size, |span, color, paint| paint_rect(&mut data, rect, span, color, paint)),
+        }
+    }
+    while !layers.is_empty() {
+        end_layer(&mut data, &mut layers, rect, Some(rect));
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, FontInfo, UString};
+
+    const COLORS: [Color; 4] = [Color::Red, Color::Silver, Color::Blue, Color::Lime];
+
+    /// Overlapping fills and outlines, nested translucent groups and text, many of them crossing tile edges
+    fn commands() -> Vec<DrawTarget> {
+        let mut seed = 0x2545_F491u32;
+        let mut next = move |max: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % max
+        };
+
+        let mut ctx = vec![DrawTarget::Clear(Color::Silver)];
+        for i in 0..60 {
+            let (x, y, width, height) = (next(70), next(50), next(40) + 1, next(30) + 1);
+            let color = COLORS[next(4) as usize];
+            let command = match i % 6 {
+                0 => DrawTarget::BeginLayer(next(256) as u8),
+                1 => DrawTarget::EndLayer,
+                2 => DrawTarget::DrawRectangle(color, next(5), x, y, width, height),
+                3 => DrawTarget::DrawText(color, FontInfo::new(12, false, false), x, y, width, height, UString::new("tile")),
+                _ => DrawTarget::FillRectangle(color, COLORS[next(4) as usize], x, y, width, height),
+            };
+            ctx.push(command);
+        }
+        // Left open, the rasterizers end it
+        ctx.push(DrawTarget::BeginLayer(100));
+        ctx.push(DrawTarget::FillRectangle(Color::Silver, Color::Silver, 5, 5, 60, 40));
+        ctx
+    }
+
+    fn single_threaded(clip: Option<Rect>) -> Pixmap {
+        let mut pixmap = Pixmap::new(80, 60);
+        pixmap.set_clip(clip);
+        pixmap.draw(&commands());
+        pixmap
+    }
+
+    #[test]
+    fn same_pixels_with_any_thread_count() {
+        let expected = single_threaded(None);
+        for threads in [1, 2, 3, 8] {
+            for tile_size in [7, 16, 256] {
+                let mut pixmap = Pixmap::new(80, 60);
+                TiledRasterizer::new(threads).with_tile_size(tile_size).draw(&mut pixmap, &commands());
+                assert!(pixmap.as_slice() == expected.as_slice(), "{} threads, {} pixel tiles", threads, tile_size);
+            }
+        }
+    }
+
+    #[test]
+    fn same_pixels_with_a_clip() {
+        let clip = Rect::new(13, 9, 50, 33);
+        let expected = single_threaded(Some(clip));
+        for threads in [2, 5] {
+            let mut pixmap = Pixmap::new(80, 60);
+            pixmap.set_clip(Some(clip));
+            TiledRasterizer::new(threads).with_tile_size(10).draw(&mut pixmap, &commands());
+            assert!(pixmap.as_slice() == expected.as_slice(), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn draws_over_existing_pixels() {
+        let mut expected = Pixmap::new(40, 40);
+        expected.draw(&[DrawTarget::FillRectangle(Color::Red, Color::Blue, 0, 0, 30, 30)]);
+        let mut pixmap = expected.clone();
+        let ctx = [DrawTarget::BeginLayer(128), DrawTarget::FillRectangle(Color::Silver, Color::Lime, 10, 10, 30, 30)];
+        expected.draw(&ctx);
+        TiledRasterizer::new(4).with_tile_size(8).draw(&mut pixmap, &ctx);
+        assert!(pixmap.as_slice() == expected.as_slice());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::raster::Pixmap;
+use crate::{Azusa, DrawTarget, Surface};
+
+use std::collections::HashMap;
+
+/// Surface that keeps the commands drawn to it instead of showing them.
+/// The recording can be drawn into other contexts with Azusa::draw_context,
+/// or stamped onto pixmaps, which rasterizes it once for every scale.
+#[derive(Clone, Debug)]
+pub struct RecordingSurface {
+    width: u32,
+    height: u32,
+    ctx: Vec<DrawTarget>,
+    /// Rasterized recordings by the bits of their scale
+    images: HashMap<u64, Pixmap>,
+}
+
+impl RecordingSurface {
+    /// Creates a recording of the given size in logical pixels, which is also the size of its images
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            ctx: vec![],
+            images: HashMap::new(),
+        }
+    }
+
+    /// Commands that were drawn last
+    pub fn get_ctx(&self) -> &[DrawTarget] {
+        &self.ctx
+    }
+
+    /// Creates a context holding the recorded commands
+    pub fn to_context(&self) -> Azusa {
+        Azusa::from_ctx(self.ctx.clone())
+    }
+
+    /// The recording rasterized at a scale factor. It is only rasterized the first time,
+    /// until something else is drawn to the surface.
+    pub fn image(&mut self, scale: f64) -> &Pixmap {
+        let (width, height, ctx) = (self.width, self.height, &self.ctx);
+        self.images.entry(scale.to_bits()).or_insert_with(|| {
+            let edge = |v: u32| (v as f64 * scale).round() as u32;
+            let mut pixmap = Pixmap::new(edge(width), edge(height));
+            let ctx: Vec<DrawTarget> = ctx.iter().map(|i| i.scale(scale)).collect();
+            pixmap.draw(&ctx);
+            pixmap
+        })
+    }
+
+    /// Blends the recording rasterized at a scale factor over a pixmap with its top left corner at x, y in physical pixels
+    pub fn stamp(&mut self, target: &mut Pixmap, x: u32, y: u32, scale: f64) {
+        target.blit(self.image(scale), x, y);
+    }
+}
+
+impl Surface for RecordingSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.ctx.clear();
+        self.ctx.extend_from_slice(ctx);
+        self.images.clear();
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Transform};
+
+    fn icon() -> RecordingSurface {
+        let mut recording = RecordingSurface::new(4, 3);
+        recording.draw(&[
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Blue, 1, 0, 3, 3),
+        ]);
+        recording
+    }
+
+    #[test]
+    fn images_are_cached_by_scale() {
+        let mut recording = icon();
+        let image = recording.image(2.0) as *const Pixmap;
+        assert_eq!(recording.image(2.0) as *const Pixmap, image);
+        assert_eq!(recording.images.len(), 1);
+
+        let scaled = recording.image(1.5);
+        assert_eq!((scaled.width(), scaled.height()), (6, 5));
+        assert_eq!(recording.images.len(), 2);
+        assert_eq!((recording.image(2.0).width(), recording.image(2.0).height()), (8, 6));
+        assert_eq!(recording.images.len(), 2);
+
+        // Drawing again drops the images
+        recording.draw(&[DrawTarget::Clear(Color::Lime)]);
+        assert!(recording.images.is_empty());
+        assert_eq!(recording.image(2.0).pixel(0, 0), Some([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn stamp_draws_the_scaled_recording() {
+        let mut recording = icon();
+        let mut stamped = Pixmap::new(20, 20);
+        stamped.draw(&[DrawTarget::Clear(Color::Black)]);
+        let mut expected = stamped.clone();
+        recording.stamp(&mut stamped, 5, 7, 2.0);
+        // The cached image is opaque, so stamping it again changes nothing
+        recording.stamp(&mut stamped, 5, 7, 2.0);
+
+        let transform = Transform::translate(5, 7).with_scale(2.0);
+        let ctx: Vec<DrawTarget> = recording.get_ctx().iter().map(|i| transform.apply(i)).collect();
+        // The clear of the recording only covers its own image
+        expected.draw(&[DrawTarget::FillRectangle(Color::White, Color::White, 5, 7, 8, 6)]);
+        expected.draw(&ctx[1..]);
+        assert!(stamped.as_slice() == expected.as_slice());
+    }
+
+    #[test]
+    fn recordings_are_drawn_into_contexts_without_their_clears() {
+        let recording = icon();
+        let mut azusa = Azusa::new();
+        azusa.draw_context(&recording.to_context(), Transform::translate(10, 20).with_scale(2.0));
+        assert_eq!(azusa.get_ctx(), [DrawTarget::FillRectangle(Color::Red, Color::Blue, 12, 20, 6, 6)]);
+    }
+}
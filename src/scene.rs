@@ -0,0 +1,417 @@
+use crate::{Color, DrawTarget, FontInfo, UString};
+
+use std::collections::HashMap;
+
+/// Handle of a node in a Scene. It stays the same until the node is removed and is never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u64);
+
+/// Retained drawing: a tree of groups and shapes that can be changed after they were added.
+/// The tree is flattened into commands only when it is drawn, and groups whose subtree did not change
+/// reuse the commands they were flattened to last time.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    nodes: HashMap<NodeId, Node>,
+    root: NodeId,
+    next_id: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    parent: Option<NodeId>,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    /// A single command, in the coordinates of its group
+    Shape(DrawTarget),
+    /// Children drawn in order, moved by the position of the group
+    Group {
+        x: u32,
+        y: u32,
+        children: Vec<NodeId>,
+        /// Flattened children, None after something in the subtree changed
+        cache: Option<Vec<DrawTarget>>,
+    },
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    /// Creates a scene with only the root group
+    pub fn new() -> Self {
+        let root = NodeId(0);
+        let mut nodes = HashMap::new();
+        nodes.insert(root, Node::group(None, 0, 0));
+        Self {
+            nodes,
+            root,
+            next_id: 1,
+        }
+    }
+
+    /// Group everything else is added to. It cannot be removed.
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Whether a node is in the scene
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    /// Number of nodes, including the root
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether only the root is left
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    /// Adds a command on top of the other children of a group.
+    /// Returns None if the parent is not a group of this scene.
+    pub fn add(&mut self, parent: NodeId, command: DrawTarget) -> Option<NodeId> {
+        self.insert(parent, Node {
+            parent: Some(parent),
+            kind: Kind::Shape(command),
+        })
+    }
+
+    /// Adds an empty group at x, y of its parent on top of the other children
+    pub fn add_group(&mut self, parent: NodeId, x: u32, y: u32) -> Option<NodeId> {
+        self.insert(parent, Node::group(Some(parent), x, y))
+    }
+
+    /// Command of a shape, None for groups
+    pub fn command(&self, id: NodeId) -> Option<&DrawTarget> {
+        match &self.nodes.get(&id)?.kind {
+            Kind::Shape(command) => Some(command),
+            Kind::Group { .. } => None,
+        }
+    }
+
+    /// Children of a group from bottom to top, None for shapes
+    pub fn children(&self, id: NodeId) -> Option<&[NodeId]> {
+        match &self.nodes.get(&id)?.kind {
+            Kind::Shape(_) => None,
+            Kind::Group { children, .. } => Some(children),
+        }
+    }
+
+    /// Group a node belongs to, None for the root
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes.get(&id)?.parent
+    }
+
+    /// Replaces the command of a shape. Returns false if the node is not a shape.
+    pub fn update(&mut self, id: NodeId, command: DrawTarget) -> bool {
+        self.update_shape(id, |i| *i = command)
+    }
+
+    /// Moves a node to x, y of its group. Clear and layers have no position, so moving them changes nothing.
+    pub fn set_position(&mut self, id: NodeId, x: u32, y: u32) -> bool {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            warn!("{:?} is not in the scene", id);
+            return false;
+        };
+        match &mut node.kind {
+            Kind::Shape(command) => match command {
+                DrawTarget::Clear(_) | DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+                DrawTarget::FillRectangle(_, _, cx, cy, _, _)
+                | DrawTarget::DrawRectangle(_, _, cx, cy, _, _)
+                | DrawTarget::DrawText(_, _, cx, cy, _, _, _) => {
+                    (*cx, *cy) = (x, y);
+                }
+            },
+            // The cache of the group itself is in its own coordinates and stays valid
+            Kind::Group { x: gx, y: gy, .. } => {
+                (*gx, *gy) = (x, y);
+            }
+        }
+        let parent = node.parent;
+        self.invalidate(parent);
+        true
+    }
+
+    /// Changes the color of a shape, the border color of filled rectangles stays the same
+    pub fn set_color(&mut self, id: NodeId, color: Color) -> bool {
+        self.update_shape(id, |command| match command {
+            DrawTarget::Clear(c)
+            | DrawTarget::FillRectangle(c, _, _, _, _, _)
+            | DrawTarget::DrawRectangle(c, _, _, _, _, _)
+            | DrawTarget::DrawText(c, _, _, _, _, _, _) => *c = color,
+            DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+        })
+    }
+
+    /// Changes the text of a text shape. Returns false for other nodes.
+    pub fn set_text(&mut self, id: NodeId, string: UString) -> bool {
+        self.update_text(id, |_, text| *text = string)
+    }
+
+    /// Changes the font of a text shape. Returns false for other nodes.
+    pub fn set_font(&mut self, id: NodeId, info: FontInfo) -> bool {
+        self.update_text(id, |font, _| *font = info)
+    }
+
+    /// Moves a node to a position among the children of its group, 0 is the bottom.
+    /// Positions past the end move it to the top.
+    pub fn reorder(&mut self, id: NodeId, index: usize) -> bool {
+        let Some(parent) = self.parent(id) else {
+            warn!("{:?} cannot be reordered", id);
+            return false;
+        };
+        if let Some(Kind::Group { children, .. }) = self.nodes.get_mut(&parent).map(|i| &mut i.kind) {
+            children.retain(|i| *i != id);
+            children.insert(index.min(children.len()), id);
+        }
+        self.invalidate(Some(parent));
+        true
+    }
+
+    /// Draws a node above the other children of its group
+    pub fn raise_to_top(&mut self, id: NodeId) -> bool {
+        self.reorder(id, usize::MAX)
+    }
+
+    /// Draws a node below the other children of its group
+    pub fn lower_to_bottom(&mut self, id: NodeId) -> bool {
+        self.reorder(id, 0)
+    }
+
+    /// Removes a node and, for groups, everything in it
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let Some(parent) = self.parent(id) else {
+            warn!("{:?} cannot be removed", id);
+            return false;
+        };
+        if let Some(Kind::Group { children, .. }) = self.nodes.get_mut(&parent).map(|i| &mut i.kind) {
+            children.retain(|i| *i != id);
+        }
+        self.invalidate(Some(parent));
+
+        let mut removed = vec![id];
+        while let Some(id) = removed.pop() {
+            if let Some(Node {
+                kind: Kind::Group { children, .. },
+                ..
+            }) = self.nodes.remove(&id)
+            {
+                removed.extend(children);
+            }
+        }
+        true
+    }
+
+    /// Removes everything but the root
+    pub fn clear(&mut self) {
+        self.nodes.retain(|id, _| *id == self.root);
+        if let Some(Kind::Group { children, cache, .. }) = self.nodes.get_mut(&self.root).map(|i| &mut i.kind) {
+            children.clear();
+            *cache = None;
+        }
+    }
+
+    /// Flattens the scene into commands, reusing the commands of unchanged groups
+    pub fn commands(&mut self) -> &[DrawTarget] {
+        self.flatten(self.root);
+        match &self.nodes[&self.root].kind {
+            Kind::Group { cache: Some(cache), .. } => cache,
+            _ => unreachable!(),
+        }
+    }
+
+    fn insert(&mut self, parent: NodeId, node: Node) -> Option<NodeId> {
+        let Some(Kind::Group { children, .. }) = self.nodes.get_mut(&parent).map(|i| &mut i.kind) else {
+            warn!("{:?} is not a group of the scene", parent);
+            return None;
+        };
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        children.push(id);
+        self.nodes.insert(id, node);
+        self.invalidate(Some(parent));
+        Some(id)
+    }
+
+    fn update_shape(&mut self, id: NodeId, f: impl FnOnce(&mut DrawTarget)) -> bool {
+        let Some(Node {
+            parent,
+            kind: Kind::Shape(command),
+        }) = self.nodes.get_mut(&id)
+        else {
+            warn!("{:?} is not a shape of the scene", id);
+            return false;
+        };
+        f(command);
+        let parent = *parent;
+        self.invalidate(parent);
+        true
+    }
+
+    fn update_text(&mut self, id: NodeId, f: impl FnOnce(&mut FontInfo, &mut UString)) -> bool {
+        if !matches!(self.command(id), Some(DrawTarget::DrawText(..))) {
+            warn!("{:?} is not a text of the scene", id);
+            return false;
+        }
+        self.update_shape(id, |command| {
+            if let DrawTarget::DrawText(_, info, _, _, _, _, string) = command {
+                f(info, string);
+            }
+        })
+    }
+
+    /// Drops the cached commands of a group and the groups it is in
+    fn invalidate(&mut self, mut id: Option<NodeId>) {
+        while let Some(node) = id.and_then(|i| self.nodes.get_mut(&i)) {
+            match &mut node.kind {
+                // The groups above an invalid cache are invalid already
+                Kind::Group { cache: cache @ Some(_), .. } => *cache = None,
+                _ => break,
+            }
+            id = node.parent;
+        }
+    }
+
+    /// Rebuilds the cached commands of a group and of the groups in it that changed
+    fn flatten(&mut self, id: NodeId) {
+        let children = match &self.nodes[&id].kind {
+            Kind::Group { cache: None, children, .. } => children.clone(),
+            _ => return,
+        };
+
+        let mut commands = vec![];
+        for child in children {
+            self.flatten(child);
+            match &self.nodes[&child].kind {
+                Kind::Shape(command) => commands.push(command.clone()),
+                Kind::Group { x: 0, y: 0, cache: Some(cache), .. } => commands.extend_from_slice(cache),
+                Kind::Group { x, y, cache: Some(cache), .. } => {
+                    commands.extend(cache.iter().map(|i| i.translate(*x, *y)))
+                }
+                Kind::Group { cache: None, .. } => unreachable!(),
+            }
+        }
+
+        if let Some(Kind::Group { cache, .. }) = self.nodes.get_mut(&id).map(|i| &mut i.kind) {
+            *cache = Some(commands);
+        }
+    }
+}
+
+impl Node {
+    fn group(parent: Option<NodeId>, x: u32, y: u32) -> Self {
+        Self {
+            parent,
+            kind: Kind::Group {
+                x,
+                y,
+                children: vec![],
+                cache: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(color: Color, x: u32, y: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, x, y, 10, 10)
+    }
+
+    /// Where the flattened commands of a group are stored, None if it has to be flattened again
+    fn cache(scene: &Scene, id: NodeId) -> Option<*const DrawTarget> {
+        match &scene.nodes[&id].kind {
+            Kind::Group { cache, .. } => cache.as_ref().map(|i| i.as_ptr()),
+            Kind::Shape(_) => None,
+        }
+    }
+
+    #[test]
+    fn groups_move_their_children() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        scene.add(root, DrawTarget::Clear(Color::White)).unwrap();
+        let group = scene.add_group(root, 100, 50).unwrap();
+        let inner = scene.add_group(group, 1, 2).unwrap();
+        scene.add(inner, fill(Color::Red, 3, 4)).unwrap();
+        scene.add(group, fill(Color::Blue, 0, 0)).unwrap();
+
+        assert_eq!(
+            scene.commands(),
+            [DrawTarget::Clear(Color::White), fill(Color::Red, 104, 56), fill(Color::Blue, 100, 50)]
+        );
+        assert!(scene.add(scene.children(inner).unwrap()[0], fill(Color::Red, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn ids_stay_valid_across_removal_and_reordering() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let a = scene.add(root, fill(Color::Red, 0, 0)).unwrap();
+        let group = scene.add_group(root, 0, 0).unwrap();
+        let b = scene.add(group, fill(Color::Lime, 0, 0)).unwrap();
+        let c = scene.add(root, fill(Color::Blue, 0, 0)).unwrap();
+
+        assert!(scene.lower_to_bottom(c));
+        assert!(scene.raise_to_top(a));
+        assert_eq!(scene.children(root), Some([c, group, a].as_slice()));
+        assert!(scene.remove(group));
+        assert!(!scene.contains(group) && !scene.contains(b));
+        assert_eq!(scene.len(), 3);
+
+        // The others still refer to the same shapes
+        assert!(scene.set_color(a, Color::Black));
+        let black = DrawTarget::FillRectangle(Color::Black, Color::Red, 0, 0, 10, 10);
+        assert_eq!(scene.command(a), Some(&black));
+        assert_eq!(scene.command(c), Some(&fill(Color::Blue, 0, 0)));
+        // Removed ids are not reused
+        let d = scene.add(root, fill(Color::Red, 0, 0)).unwrap();
+        assert!(![a, group, b, c].contains(&d));
+        assert!(!scene.set_color(b, Color::Red));
+        assert!(!scene.remove(root));
+        assert_eq!(scene.commands(), [fill(Color::Blue, 0, 0), black, fill(Color::Red, 0, 0)]);
+    }
+
+    #[test]
+    fn only_changed_groups_are_flattened_again() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let left = scene.add_group(root, 0, 0).unwrap();
+        let right = scene.add_group(root, 50, 0).unwrap();
+        let inner = scene.add_group(right, 0, 20).unwrap();
+        let shape = scene.add(left, fill(Color::Red, 0, 0)).unwrap();
+        let text = scene.add(inner, DrawTarget::DrawText(Color::Black, FontInfo::new(12, false, false), 0, 0, 40, 12, UString::new("a"))).unwrap();
+        scene.commands();
+        let inner_cache = cache(&scene, inner);
+
+        scene.set_position(shape, 5, 5);
+        assert_eq!(cache(&scene, left), None);
+        assert_eq!(cache(&scene, root), None);
+        assert_eq!(cache(&scene, inner), inner_cache);
+        scene.commands();
+        assert_ne!(cache(&scene, left), None);
+        assert_eq!(cache(&scene, inner), inner_cache);
+
+        // Moving a group keeps its own commands, only the groups above it are flattened again
+        let (left_cache, right_cache) = (cache(&scene, left), cache(&scene, right));
+        scene.set_position(right, 60, 0);
+        assert_eq!(cache(&scene, root), None);
+        assert_eq!(cache(&scene, right), right_cache);
+        scene.set_text(text, UString::new("b"));
+        assert_eq!(cache(&scene, inner), None);
+        assert_eq!(cache(&scene, left), left_cache);
+
+        assert_eq!(scene.commands()[1], DrawTarget::DrawText(Color::Black, FontInfo::new(12, false, false), 60, 20, 40, 12, UString::new("b")));
+        assert_eq!(cache(&scene, left), left_cache);
+    }
+}
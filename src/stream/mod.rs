@@ -0,0 +1,211 @@
+use crate::{Color, DrawTarget, FontInfo, Surface, Vec4};
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// JavaScript that draws the frames of a StreamSurface to a 2D canvas context.
+/// It defines `azusaReplay(context, frame)`, where frame is one parsed line of the stream.
+pub const REPLAYER: &str = include_str!("replay.js");
+
+/// Surface that converts contexts into Canvas2D commands, so a server can have drawings shown by a browser
+/// without WebAssembly.
+/// Every draw writes one frame as a line of JSON, an array of commands that are arrays themselves:
+///
+/// - `["c",color]` clears the canvas and fills it with a color
+/// - `["f",color,x,y,width,height]` fills a rectangle
+/// - `["s",color,lineWidth,x,y,width,height]` strokes a rectangle
+/// - `["t",color,font,underline,x,y,width,height,text]` writes text clipped to a rectangle
+/// - `["l",opacity]` starts a layer, the following commands are drawn into a transparent canvas
+/// - `["e"]` ends a layer, blending its canvas over the one below with the opacity from 0 to 1
+///
+/// Colors are CSS colors and fonts are CSS fonts, so they can be given to the context as they are.
+/// Borders are already converted to strokes inside of the rectangles, the same as on the other surfaces.
+pub struct StreamSurface<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    frames: u64,
+}
+
+impl<W: Write> StreamSurface<W> {
+    /// Creates a surface for a canvas of the given size
+    pub fn new(writer: W, width: u32, height: u32) -> Self {
+        Self {
+            writer,
+            width,
+            height,
+            frames: 0,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Number of frames written so far
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flushes the stream and returns the writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Surface for StreamSurface<W> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        let mut line = to_json(ctx);
+        line.push('\n');
+        match self.writer.write_all(line.as_bytes()).and_then(|_| self.writer.flush()) {
+            Ok(_) => self.frames += 1,
+            Err(e) => {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+/// Converts a context into the JSON of a single frame
+pub fn to_json(ctx: &[DrawTarget]) -> String {
+    let mut commands = vec![];
+    for i in ctx {
+        match i {
+            DrawTarget::Clear(color) => {
+                commands.push(format!("[\"c\",{}]", css(*color)));
+            }
+            DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                stroke_rectangle(&mut commands, *border_color, 1, (*x, *y, *width, *height));
+                if *width > 2 && *height > 2 {
+                    commands.push(format!("[\"f\",{},{},{},{},{}]", css(*color), x + 1, y + 1, width - 2, height - 2));
+                }
+            }
+            DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                stroke_rectangle(&mut commands, *color, *thickness, (*x, *y, *width, *height));
+            }
+            DrawTarget::DrawText(color, info, x, y, width, height, string) => {
+                let text = String::from_utf16_lossy(string.as_utf16());
+                commands.push(format!(
+                    "[\"t\",{},{},{},{},{},{},{},{}]",
+                    css(*color),
+                    json_string(&font(info)),
+                    u8::from(info.2),
+                    x,
+                    y,
+                    width,
+                    height,
+                    json_string(&text)
+                ));
+            }
+            DrawTarget::BeginLayer(opacity) => {
+                commands.push(format!("[\"l\",{}]", *opacity as f64 / 255.0));
+            }
+            DrawTarget::EndLayer => commands.push("[\"e\"]".to_string()),
+        }
+    }
+    format!("[{}]", commands.join(","))
+}
+
+/// Outlines a rectangle with a stroke inside of it, thick borders that fill the rectangle become fills
+fn stroke_rectangle(commands: &mut Vec<String>, color: Color, thickness: u32, (x, y, width, height): (u32, u32, u32, u32)) {
+    if thickness.saturating_mul(2) >= width || thickness.saturating_mul(2) >= height {
+        commands.push(format!("[\"f\",{},{},{},{},{}]", css(color), x, y, width, height));
+        return;
+    }
+
+    let half = thickness as f64 / 2.0;
+    commands.push(format!(
+        "[\"s\",{},{},{},{},{},{}]",
+        css(color),
+        thickness,
+        x as f64 + half,
+        y as f64 + half,
+        width - thickness,
+        height - thickness
+    ));
+}
+
+fn css(color: Color) -> String {
+    let color = Vec4::from(color);
+    if color.3 == 255.0 {
+        format!("\"#{:02x}{:02x}{:02x}\"", color.0 as u8, color.1 as u8, color.2 as u8)
+    } else {
+        format!(
+            "\"rgba({},{},{},{})\"",
+            color.0 as u8,
+            color.1 as u8,
+            color.2 as u8,
+            color.3 / 255.0
+        )
+    }
+}
+
+fn font(info: &FontInfo) -> String {
+    let style = if info.1 { "italic " } else { "" };
+    format!("{}{}px sans-serif", style, info.0)
+}
+
+fn json_string(string: &str) -> String {
+    let mut output = String::with_capacity(string.len() + 2);
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            // Line and paragraph separators are not allowed in JavaScript string literals
+            c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UString;
+
+    #[test]
+    fn frames() {
+        let mut surface = StreamSurface::new(vec![], 100, 50);
+        surface.draw(&[
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Silver, 1, 2, 10, 5),
+            DrawTarget::DrawRectangle(Color::Blue, 3, 0, 0, 20, 10),
+            // Borders that fill the rectangle
+            DrawTarget::DrawRectangle(Color::Blue, 2, 0, 0, 4, 10),
+            DrawTarget::FillRectangle(Color::Red, Color::Black, 0, 0, 2, 2),
+        ]);
+        surface.draw(&[
+            DrawTarget::BeginLayer(51),
+            DrawTarget::DrawText(Color::Black, FontInfo::new(12, true, true), 5, 6, 70, 20, UString::new("a \"b\"\\\n\u{1}\u{2028}")),
+            DrawTarget::EndLayer,
+        ]);
+        assert_eq!(surface.frame_count(), 2);
+
+        let expected = concat!(
+            r##"[["c","#ffffff"],["s","rgba(192,192,192,0.7529411764705882)",1,1.5,2.5,9,4],["f","#ff0000",2,3,8,3],"##,
+            r##"["s","#0000ff",3,1.5,1.5,17,7],["f","#0000ff",0,0,4,10],["f","#000000",0,0,2,2]]"##,
+            "\n",
+            r##"[["l",0.2],["t","#000000","italic 12px sans-serif",1,5,6,70,20,"a \"b\"\\\n\u0001\u2028"],["e"]]"##,
+            "\n",
+        );
+        let output = String::from_utf8(surface.into_inner().unwrap()).unwrap();
+        assert_eq!(output, expected);
+        for line in output.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+    }
+}
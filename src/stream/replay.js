@@ -0,0 +1,65 @@
+// Draws a frame written by azusa::stream::StreamSurface to a CanvasRenderingContext2D
+function azusaReplay(context, frame) {
+  // Layers are drawn into offscreen canvases and blended over the context below them when they end
+  const layers = [];
+  const endLayer = () => {
+    const { canvas, below, opacity } = layers.pop();
+    below.save();
+    below.setTransform(1, 0, 0, 1, 0, 0);
+    below.globalAlpha = opacity;
+    below.drawImage(canvas, 0, 0);
+    below.restore();
+    context = below;
+  };
+  for (const command of frame) {
+    switch (command[0]) {
+      case "l": {
+        const canvas = new OffscreenCanvas(context.canvas.width, context.canvas.height);
+        const layer = canvas.getContext("2d");
+        layer.setTransform(context.getTransform());
+        layers.push({ canvas, below: context, opacity: command[1] });
+        context = layer;
+        break;
+      }
+      case "e":
+        if (layers.length > 0) endLayer();
+        break;
+      case "c":
+        context.clearRect(0, 0, context.canvas.width, context.canvas.height);
+        context.fillStyle = command[1];
+        context.fillRect(0, 0, context.canvas.width, context.canvas.height);
+        break;
+      case "f":
+        context.fillStyle = command[1];
+        context.fillRect(command[2], command[3], command[4], command[5]);
+        break;
+      case "s":
+        context.strokeStyle = command[1];
+        context.lineWidth = command[2];
+        context.strokeRect(command[3], command[4], command[5], command[6]);
+        break;
+      case "t": {
+        const [, color, font, underline, x, y, width, height, text] = command;
+        const px = parseFloat(font.match(/(\d+)px/)[1]);
+        context.save();
+        context.beginPath();
+        context.rect(x, y, width, height);
+        context.clip();
+        context.fillStyle = color;
+        context.font = font;
+        context.textBaseline = "top";
+        text.split("\n").forEach((line, i) => {
+          const top = y + i * px;
+          if (top >= y + height) return;
+          context.fillText(line, x, top);
+          if (underline) {
+            context.fillRect(x, top + px * 0.9, context.measureText(line).width, Math.max(px / 14, 1));
+          }
+        });
+        context.restore();
+        break;
+      }
+    }
+  }
+  while (layers.length > 0) endLayer();
+}
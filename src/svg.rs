@@ -0,0 +1,283 @@
+use crate::{Color, DrawTarget, FontInfo, Surface, UString, Vec4};
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Surface that writes SVG images.
+/// Every draw replaces the image, like the ImageSurface does.
+pub struct SvgSurface {
+    width: f64,
+    height: f64,
+    scale: f64,
+
+    body: String,
+    clips: usize,
+    /// Lengths of the body where the open layers start
+    layers: Vec<usize>,
+}
+
+impl SvgSurface {
+    pub fn new(width: f64, height: f64) -> Self {
+        Self {
+            width,
+            height,
+            scale: 1.0,
+            body: String::new(),
+            clips: 0,
+            layers: vec![],
+        }
+    }
+
+    pub fn resize(&mut self, width: f64, height: f64) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Sets the size the image is shown at by default, the drawing itself keeps its logical coordinates
+    pub fn set_scale_factor(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    /// Writes the image to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(file);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        write!(
+            w,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n{}</svg>\n",
+            self.width * self.scale,
+            self.height * self.scale,
+            self.width,
+            self.height,
+            self.body
+        )
+    }
+
+    /// Outlines a rectangle, the border is drawn inside of the rectangle like on the other surfaces
+    fn draw_rectangle(&mut self, color: Color, thickness: u32, (x, y, width, height): (u32, u32, u32, u32)) {
+        if thickness.saturating_mul(2) >= width || thickness.saturating_mul(2) >= height {
+            self.fill_rectangle(color, (x, y, width, height));
+            return;
+        }
+
+        let half = thickness as f64 / 2.0;
+        let _ = writeln!(
+            self.body,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke-width=\"{}\" {}/>",
+            x as f64 + half,
+            y as f64 + half,
+            width - thickness,
+            height - thickness,
+            thickness,
+            paint("stroke", color)
+        );
+    }
+
+    fn fill_rectangle(&mut self, color: Color, (x, y, width, height): (u32, u32, u32, u32)) {
+        let _ = writeln!(
+            self.body,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+            x,
+            y,
+            width,
+            height,
+            paint("fill", color)
+        );
+    }
+
+    fn draw_text(&mut self, color: Color, info: &FontInfo, (x, y, width, height): (u32, u32, u32, u32), string: &UString) {
+        // Text is clipped to its rectangle like DrawText does on Windows
+        let id = self.clips;
+        self.clips += 1;
+        let _ = writeln!(
+            self.body,
+            "<clipPath id=\"clip{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath>",
+            id, x, y, width, height
+        );
+
+        let _ = write!(
+            self.body,
+            "<text clip-path=\"url(#clip{})\" font-family=\"sans-serif\" font-size=\"{}\" dominant-baseline=\"text-before-edge\" {}",
+            id,
+            info.0,
+            paint("fill", color)
+        );
+        if info.1 {
+            self.body.push_str(" font-style=\"italic\"");
+        }
+        if info.2 {
+            self.body.push_str(" text-decoration=\"underline\"");
+        }
+        self.body.push('>');
+
+        let text = String::from_utf16_lossy(string.as_utf16());
+        for (i, line) in text.split('\n').enumerate() {
+            let _ = write!(
+                self.body,
+                "<tspan x=\"{}\" y=\"{}\">{}</tspan>",
+                x,
+                y + i as u32 * info.0,
+                escape(line)
+            );
+        }
+        self.body.push_str("</text>\n");
+    }
+}
+
+impl Surface for SvgSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.body.clear();
+        self.clips = 0;
+        self.layers.clear();
+
+        for i in ctx {
+            match *i {
+                DrawTarget::Clear(color) => {
+                    // Everything before in the same layer is covered, so it does not have to be kept
+                    self.body.truncate(self.layers.last().copied().unwrap_or(0));
+                    let (width, height) = self.get_client_size();
+                    self.fill_rectangle(color, (0, 0, width, height));
+                }
+                DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                    self.draw_rectangle(border_color, 1, (x, y, width, height));
+                    if width > 2 && height > 2 {
+                        self.fill_rectangle(color, (x + 1, y + 1, width - 2, height - 2));
+                    }
+                }
+                DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                    self.draw_rectangle(color, thickness, (x, y, width, height));
+                }
+                DrawTarget::DrawText(color, info, x, y, width, height, ref string) => {
+                    self.draw_text(color, &info, (x, y, width, height), string);
+                }
+                // Group opacity is applied to the group as a whole
+                DrawTarget::BeginLayer(opacity) => {
+                    let _ = writeln!(self.body, "<g opacity=\"{}\">", opacity as f64 / 255.0);
+                    self.layers.push(self.body.len());
+                }
+                DrawTarget::EndLayer => {
+                    if self.layers.pop().is_some() {
+                        self.body.push_str("</g>\n");
+                    }
+                }
+            }
+        }
+        for _ in self.layers.drain(..) {
+            self.body.push_str("</g>\n");
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.scale
+    }
+}
+
+/// Fill or stroke attributes of a color
+fn paint(attribute: &str, color: Color) -> String {
+    let color = Vec4::from(color);
+    let mut paint = format!(
+        "{}=\"#{:02x}{:02x}{:02x}\"",
+        attribute, color.0 as u8, color.1 as u8, color.2 as u8
+    );
+    if color.3 != 255.0 {
+        let _ = write!(paint, " {}-opacity=\"{}\"", attribute, color.3 / 255.0);
+    }
+    paint
+}
+
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            // Not allowed in XML
+            c if c.is_control() && c != '\t' => {}
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn svg(surface: &SvgSurface) -> String {
+        let mut output = vec![];
+        surface.write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn golden() {
+        let mut surface = SvgSurface::new(100.0, 50.0);
+        surface.set_scale_factor(2.0);
+        surface.draw(&[
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Black, 10, 10, 30, 20),
+            DrawTarget::DrawRectangle(Color::Silver, 2, 50, 5, 40, 30),
+            DrawTarget::BeginLayer(128),
+            DrawTarget::DrawRectangle(Color::Blue, 20, 0, 0, 10, 10),
+            DrawTarget::DrawText(Color::Navy, FontInfo::new(10, true, true), 5, 35, 80, 15, UString::new("a<b\n&\"c\"")),
+        ]);
+        let expected = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100" viewBox="0 0 100 50">
+<rect x="0" y="0" width="100" height="50" fill="#ffffff"/>
+<rect x="10.5" y="10.5" width="29" height="19" fill="none" stroke-width="1" stroke="#000000"/>
+<rect x="11" y="11" width="28" height="18" fill="#ff0000"/>
+<rect x="51" y="6" width="38" height="28" fill="none" stroke-width="2" stroke="#c0c0c0" stroke-opacity="0.7529411764705882"/>
+<g opacity="0.5019607843137255">
+<rect x="0" y="0" width="10" height="10" fill="#0000ff"/>
+<clipPath id="clip0"><rect x="5" y="35" width="80" height="15"/></clipPath>
+<text clip-path="url(#clip0)" font-family="sans-serif" font-size="10" dominant-baseline="text-before-edge" fill="#000080" font-style="italic" text-decoration="underline"><tspan x="5" y="35">a&lt;b</tspan><tspan x="5" y="45">&amp;&quot;c&quot;</tspan></text>
+</g>
+</svg>
+"##;
+        assert_eq!(svg(&surface), expected);
+    }
+
+    #[test]
+    fn clear_only_removes_its_own_layer() {
+        let mut surface = SvgSurface::new(10.0, 10.0);
+        surface.draw(&[
+            DrawTarget::FillRectangle(Color::Red, Color::Red, 0, 0, 2, 2),
+            DrawTarget::BeginLayer(255),
+            DrawTarget::FillRectangle(Color::Blue, Color::Blue, 0, 0, 2, 2),
+            DrawTarget::Clear(Color::Lime),
+            DrawTarget::EndLayer,
+        ]);
+        let expected = r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10">
+<rect x="0" y="0" width="2" height="2" fill="#ff0000"/>
+<g opacity="1">
+<rect x="0" y="0" width="10" height="10" fill="#00ff00"/>
+</g>
+</svg>
+"##;
+        assert_eq!(svg(&surface), expected);
+    }
+
+    #[test]
+    fn draw_replaces_the_image() {
+        let mut surface = SvgSurface::new(10.0, 10.0);
+        surface.draw(&[DrawTarget::DrawText(Color::Black, FontInfo::new(5, false, false), 0, 0, 5, 5, UString::new("a"))]);
+        surface.draw(&[DrawTarget::DrawText(Color::Black, FontInfo::new(5, false, false), 0, 0, 5, 5, UString::new("b"))]);
+        let svg = svg(&surface);
+        assert!(!svg.contains(">a<"));
+        // Clip paths are numbered from the start again
+        assert!(svg.contains("<clipPath id=\"clip0\">"));
+        assert!(!svg.contains("clip1"));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::raster::Pixmap;
+use crate::{DrawTarget, Surface};
+
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminalMode {
+    /// Upper half block characters with 24-bit foreground and background colors,
+    /// two pixels per cell
+    HalfBlock,
+    /// DEC sixel graphics with a 216 color palette
+    Sixel,
+    /// Kitty graphics protocol with RGBA pixels
+    Kitty,
+}
+
+/// Pixels with less alpha are left to the terminal background
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Surface that shows drawings in a terminal
+pub struct TerminalSurface<W: Write> {
+    writer: W,
+    mode: TerminalMode,
+
+    cols: u32,
+    rows: u32,
+    cell_width: u32,
+    cell_height: u32,
+
+    pixmap: Pixmap,
+}
+
+impl<W: Write> TerminalSurface<W> {
+    /// Creates a surface covering cols x rows cells of the terminal
+    pub fn new(writer: W, cols: u32, rows: u32, mode: TerminalMode) -> Self {
+        let mut surface = Self {
+            writer,
+            mode,
+            cols,
+            rows,
+            // A common cell size, terminals do not report it reliably
+            cell_width: 8,
+            cell_height: 16,
+            pixmap: Pixmap::new(0, 0),
+        };
+        surface.resize(cols, rows);
+        surface
+    }
+
+    /// Size of a cell in pixels, used by the sixel and kitty modes
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.cell_width = width;
+        self.cell_height = height;
+        self.resize(self.cols, self.rows);
+    }
+
+    pub fn resize(&mut self, cols: u32, rows: u32) {
+        self.cols = cols;
+        self.rows = rows;
+        match self.mode {
+            TerminalMode::HalfBlock => self.pixmap.resize(cols, rows * 2),
+            TerminalMode::Sixel | TerminalMode::Kitty => {
+                self.pixmap.resize(cols * self.cell_width, rows * self.cell_height)
+            }
+        }
+    }
+
+    /// Flushes the output and returns the writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_half_blocks(&mut self) -> std::io::Result<()> {
+        let mut output = String::new();
+        for row in 0..self.rows {
+            let mut current = (None, None);
+            for col in 0..self.cols {
+                let top = opaque(self.pixmap.pixel(col, row * 2));
+                let bottom = opaque(self.pixmap.pixel(col, row * 2 + 1));
+
+                // Only one color can be set for a transparent half, so the other half becomes the foreground
+                let (c, foreground, background) = match (top, bottom) {
+                    (Some(top), bottom) => ('▀', Some(top), bottom),
+                    (None, Some(bottom)) => ('▄', Some(bottom), None),
+                    (None, None) => (' ', None, None),
+                };
+
+                if (foreground, background) != current {
+                    output.push_str("\x1b[0m");
+                    if let Some([r, g, b]) = foreground {
+                        output.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                    }
+                    if let Some([r, g, b]) = background {
+                        output.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+                    }
+                    current = (foreground, background);
+                }
+                output.push(c);
+            }
+            output.push_str("\x1b[0m\n");
+        }
+
+        self.writer.write_all(output.as_bytes())
+    }
+
+    fn write_sixel(&mut self) -> std::io::Result<()> {
+        let width = self.pixmap.width();
+        let height = self.pixmap.height();
+
+        // P2 = 1 keeps pixels that are not set transparent
+        let mut output = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+        for index in 0..216u32 {
+            let (r, g, b) = (index / 36, index / 6 % 6, index % 6);
+            output.push_str(&format!("#{};2;{};{};{}", index, r * 20, g * 20, b * 20));
+        }
+
+        for band in (0..height).step_by(6) {
+            let mut bits = vec![0u8; width as usize * 216];
+            let mut used = [false; 216];
+            for y in band..(band + 6).min(height) {
+                for x in 0..width {
+                    if let Some(color) = opaque(self.pixmap.pixel(x, y)) {
+                        let index = palette_index(color);
+                        used[index] = true;
+                        bits[index * width as usize + x as usize] |= 1 << (y - band);
+                    }
+                }
+            }
+
+            for (index, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+                output.push_str(&format!("#{}", index));
+                let row = &bits[index * width as usize..(index + 1) * width as usize];
+                let mut x = 0;
+                while x < row.len() {
+                    let run = row[x..].iter().take_while(|bits| **bits == row[x]).count();
+                    let c = (b'?' + row[x]) as char;
+                    if run > 3 {
+                        output.push_str(&format!("!{}{}", run, c));
+                    } else {
+                        output.extend(std::iter::repeat_n(c, run));
+                    }
+                    x += run;
+                }
+                // Go back to the start of the band for the next color
+                output.push('$');
+            }
+            output.push('-');
+        }
+        output.push_str("\x1b\\\n");
+
+        self.writer.write_all(output.as_bytes())
+    }
+
+    fn write_kitty(&mut self) -> std::io::Result<()> {
+        let encoded = base64(self.pixmap.as_slice());
+        // The payload has to be sent in chunks of at most 4096 bytes
+        let chunks = encoded.as_bytes().chunks(4096).collect::<Vec<_>>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = usize::from(i + 1 < chunks.len());
+            if i == 0 {
+                write!(
+                    self.writer,
+                    "\x1b_Ga=T,f=32,q=2,s={},v={},c={},r={},m={};",
+                    self.pixmap.width(),
+                    self.pixmap.height(),
+                    self.cols,
+                    self.rows,
+                    more
+                )?;
+            } else {
+                write!(self.writer, "\x1b_Gm={};", more)?;
+            }
+            self.writer.write_all(chunk)?;
+            self.writer.write_all(b"\x1b\\")?;
+        }
+        self.writer.write_all(b"\n")
+    }
+}
+
+impl<W: Write> Surface for TerminalSurface<W> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.pixmap.as_mut_slice().fill(0);
+        self.pixmap.draw(ctx);
+
+        let result = match self.mode {
+            TerminalMode::HalfBlock => self.write_half_blocks(),
+            TerminalMode::Sixel => self.write_sixel(),
+            TerminalMode::Kitty => self.write_kitty(),
+        };
+        match result.and_then(|_| self.writer.flush()) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.pixmap.width(), self.pixmap.height())
+    }
+}
+
+fn opaque(pixel: Option<[u8; 4]>) -> Option<[u8; 3]> {
+    match pixel {
+        Some([r, g, b, a]) if a >= ALPHA_THRESHOLD => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+/// Nearest color of a 6x6x6 color cube
+fn palette_index(color: [u8; 3]) -> usize {
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    level(color[0]) * 36 + level(color[1]) * 6 + level(color[2])
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn output(mode: TerminalMode, cols: u32, rows: u32, cell: (u32, u32), ctx: &[DrawTarget]) -> String {
+        let mut surface = TerminalSurface::new(vec![], cols, rows, mode);
+        surface.set_cell_size(cell.0, cell.1);
+        surface.draw(ctx);
+        String::from_utf8(surface.into_inner().unwrap()).unwrap()
+    }
+
+    fn fill(color: Color, x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, x, y, width, height)
+    }
+
+    #[test]
+    fn half_blocks() {
+        // Red top left pixel, a blue bottom half, a lime column and transparent cells
+        let ctx = [fill(Color::Red, 0, 0, 1, 1), fill(Color::Blue, 1, 1, 1, 1), fill(Color::Lime, 2, 0, 1, 4)];
+        let expected = concat!(
+            "\x1b[0m\x1b[38;2;255;0;0m▀\x1b[0m\x1b[38;2;0;0;255m▄\x1b[0m\x1b[38;2;0;255;0m\x1b[48;2;0;255;0m▀\x1b[0m \x1b[0m\n",
+            "  \x1b[0m\x1b[38;2;0;255;0m\x1b[48;2;0;255;0m▀\x1b[0m \x1b[0m\n",
+        );
+        assert_eq!(output(TerminalMode::HalfBlock, 4, 2, (8, 16), &ctx), expected);
+    }
+
+    #[test]
+    fn sixel() {
+        // Two bands of 2x6 pixels, translucent silver is still drawn
+        let ctx = [fill(Color::Red, 0, 0, 2, 3), fill(Color::Blue, 1, 2, 1, 4), fill(Color::Silver, 0, 6, 1, 1)];
+        let actual = output(TerminalMode::Sixel, 1, 2, (2, 4), &ctx);
+
+        let mut palette = String::new();
+        for index in 0..216 {
+            palette.push_str(&format!("#{};2;{};{};{}", index, index / 36 * 20, index / 6 % 6 * 20, index % 6 * 20));
+        }
+        let expected = format!("\x1bP0;1;0q\"1;1;2;8{}#5?{{$#180FB$-#172@?$-\x1b\\\n", palette);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sixel_runs_are_compressed() {
+        let actual = output(TerminalMode::Sixel, 1, 1, (8, 1), &[fill(Color::White, 0, 0, 5, 1)]);
+        assert!(actual.ends_with("#215!5@???$-\x1b\\\n"), "{:?}", actual);
+    }
+
+    #[test]
+    fn kitty() {
+        let actual = output(TerminalMode::Kitty, 1, 1, (2, 1), &[fill(Color::Red, 0, 0, 1, 1)]);
+        assert_eq!(actual, "\x1b_Ga=T,f=32,q=2,s=2,v=1,c=1,r=1,m=0;/wAA/wAAAAA=\x1b\\\n");
+    }
+
+    #[test]
+    fn kitty_payload_is_chunked() {
+        // 32x33 pixels are 4224 bytes, 5632 in base64
+        let actual = output(TerminalMode::Kitty, 4, 3, (8, 11), &[]);
+        let chunk = "A".repeat(4096);
+        let rest = "A".repeat(1536);
+        let expected = format!("\x1b_Ga=T,f=32,q=2,s=32,v=33,c=4,r=3,m=1;{}\x1b\\\x1b_Gm=0;{}\x1b\\\n", chunk, rest);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+    }
+}
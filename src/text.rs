@@ -0,0 +1,277 @@
+use crate::{Color, DrawTarget, Surface, Vec4};
+
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextCharset {
+    /// Box drawing and block element characters
+    Unicode,
+    /// Only printable ASCII, for consoles without Unicode support
+    Ascii,
+}
+
+struct Glyphs {
+    horizontal: char,
+    vertical: char,
+    corners: [char; 4],
+    /// From dark to light
+    shades: [char; 4],
+}
+
+const UNICODE: Glyphs = Glyphs {
+    horizontal: '─',
+    vertical: '│',
+    corners: ['┌', '┐', '└', '┘'],
+    shades: ['█', '▓', '▒', '░'],
+};
+
+const ASCII: Glyphs = Glyphs {
+    horizontal: '-',
+    vertical: '|',
+    corners: ['+', '+', '+', '+'],
+    shades: ['#', '%', '+', '.'],
+};
+
+/// Surface that renders into a grid of characters.
+/// Outlines are drawn with box drawing characters, fills are shaded by the brightness of the color
+/// and text is written as it is, which makes the output usable for plain text snapshots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextSurface {
+    cols: u32,
+    rows: u32,
+    cell_width: u32,
+    cell_height: u32,
+    charset: TextCharset,
+
+    cells: Vec<char>,
+}
+
+impl TextSurface {
+    pub fn new(cols: u32, rows: u32, charset: TextCharset) -> Self {
+        Self {
+            cols,
+            rows,
+            // Same as the TerminalSurface, so both show drawings at a similar size
+            cell_width: 8,
+            cell_height: 16,
+            charset,
+            cells: vec![' '; cols as usize * rows as usize],
+        }
+    }
+
+    /// Size of a cell in context pixels. 1x1 maps every pixel to a character.
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.cell_width = width.max(1);
+        self.cell_height = height.max(1);
+    }
+
+    pub fn resize(&mut self, cols: u32, rows: u32) {
+        self.cols = cols;
+        self.rows = rows;
+        self.cells = vec![' '; cols as usize * rows as usize];
+    }
+
+    /// Gets the character of a cell
+    pub fn cell(&self, col: u32, row: u32) -> Option<char> {
+        if col >= self.cols || row >= self.rows {
+            return None;
+        }
+        Some(self.cells[(row * self.cols + col) as usize])
+    }
+
+    /// Rows of the grid with trailing spaces removed
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.cols.max(1) as usize)
+            .map(|row| row.iter().collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    fn glyphs(&self) -> &'static Glyphs {
+        match self.charset {
+            TextCharset::Unicode => &UNICODE,
+            TextCharset::Ascii => &ASCII,
+        }
+    }
+
+    fn set(&mut self, col: u32, row: u32, c: char) {
+        if col < self.cols && row < self.rows {
+            self.cells[(row * self.cols + col) as usize] = c;
+        }
+    }
+
+    /// Cells covered by a rectangle as (left, top, right, bottom), inclusive
+    fn cells_of(&self, x: u32, y: u32, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some((
+            x / self.cell_width,
+            y / self.cell_height,
+            x.saturating_add(width - 1) / self.cell_width,
+            y.saturating_add(height - 1) / self.cell_height,
+        ))
+    }
+
+    fn fill(&mut self, c: char, (left, top, right, bottom): (u32, u32, u32, u32)) {
+        for row in top..=bottom.min(self.rows.saturating_sub(1)) {
+            for col in left..=right.min(self.cols.saturating_sub(1)) {
+                self.set(col, row, c);
+            }
+        }
+    }
+
+    fn outline(&mut self, (left, top, right, bottom): (u32, u32, u32, u32)) {
+        let glyphs = self.glyphs();
+        if left == right || top == bottom {
+            // Too small for corners
+            let c = if top == bottom { glyphs.horizontal } else { glyphs.vertical };
+            self.fill(c, (left, top, right, bottom));
+            return;
+        }
+
+        for col in left + 1..right {
+            self.set(col, top, glyphs.horizontal);
+            self.set(col, bottom, glyphs.horizontal);
+        }
+        for row in top + 1..bottom {
+            self.set(left, row, glyphs.vertical);
+            self.set(right, row, glyphs.vertical);
+        }
+        self.set(left, top, glyphs.corners[0]);
+        self.set(right, top, glyphs.corners[1]);
+        self.set(left, bottom, glyphs.corners[2]);
+        self.set(right, bottom, glyphs.corners[3]);
+    }
+
+    fn shade(&self, color: Color) -> char {
+        let color = Vec4::from(color);
+        let luminance = 0.299 * color.0 + 0.587 * color.1 + 0.114 * color.2;
+        self.glyphs().shades[((luminance / 64.0) as usize).min(3)]
+    }
+}
+
+impl Surface for TextSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        for i in ctx {
+            match *i {
+                DrawTarget::Clear(_) => {
+                    self.cells.fill(' ');
+                }
+                DrawTarget::FillRectangle(color, _, x, y, width, height) => {
+                    if let Some(cells) = self.cells_of(x, y, width, height) {
+                        let (left, top, right, bottom) = cells;
+                        if right - left >= 2 && bottom - top >= 2 {
+                            self.outline(cells);
+                            self.fill(self.shade(color), (left + 1, top + 1, right - 1, bottom - 1));
+                        } else {
+                            self.fill(self.shade(color), cells);
+                        }
+                    }
+                }
+                DrawTarget::DrawRectangle(_, _, x, y, width, height) => {
+                    if let Some(cells) = self.cells_of(x, y, width, height) {
+                        self.outline(cells);
+                    }
+                }
+                DrawTarget::DrawText(_, _, x, y, width, height, ref string) => {
+                    if let Some((left, top, right, bottom)) = self.cells_of(x, y, width, height) {
+                        let (mut col, mut row) = (left, top);
+                        for c in char::decode_utf16(string.as_utf16().iter().copied()) {
+                            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+                            if c == '\n' {
+                                col = left;
+                                row = row.saturating_add(1);
+                                continue;
+                            }
+                            if row > bottom {
+                                break;
+                            }
+                            if col <= right && !c.is_control() {
+                                self.set(col, row, c);
+                            }
+                            col = col.saturating_add(1);
+                        }
+                    }
+                }
+                // Characters cannot be translucent, so layers are only drawn in order
+                DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+            }
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.cols * self.cell_width, self.rows * self.cell_height)
+    }
+}
+
+impl Display for TextSurface {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in self.lines() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FontInfo, UString};
+
+    fn text(x: u32, y: u32, width: u32, height: u32, string: &str) -> DrawTarget {
+        DrawTarget::DrawText(Color::Black, FontInfo::new(12, false, false), x, y, width, height, UString::new(string))
+    }
+
+    fn surface(charset: TextCharset) -> TextSurface {
+        let mut surface = TextSurface::new(8, 5, charset);
+        surface.set_cell_size(1, 1);
+        surface.draw(&[
+            DrawTarget::FillRectangle(Color::White, Color::Black, 0, 0, 5, 4),
+            DrawTarget::DrawRectangle(Color::Black, 1, 5, 0, 3, 3),
+            DrawTarget::FillRectangle(Color::Black, Color::Black, 6, 3, 2, 1),
+            // Cut at the end of its box
+            text(1, 4, 2, 1, "hi\nyou"),
+        ]);
+        surface
+    }
+
+    #[test]
+    fn unicode() {
+        let expected = "┌───┐┌─┐\n│░░░││ │\n│░░░│└─┘\n└───┘ ██\n hi\n";
+        assert_eq!(surface(TextCharset::Unicode).to_string(), expected);
+    }
+
+    #[test]
+    fn ascii() {
+        let expected = "+---++-+\n|...|| |\n|...|+-+\n+---+ ##\n hi\n";
+        assert_eq!(surface(TextCharset::Ascii).to_string(), expected);
+    }
+
+    #[test]
+    fn cells_scale_with_the_cell_size() {
+        let mut surface = TextSurface::new(4, 2, TextCharset::Ascii);
+        assert_eq!(surface.get_client_size(), (32, 32));
+        // A 1px line in the second cell row is still drawn
+        surface.draw(&[DrawTarget::DrawRectangle(Color::Black, 1, 0, 16, 17, 1)]);
+        assert_eq!(surface.lines(), ["", "---"]);
+        assert_eq!(surface.cell(2, 1), Some('-'));
+        assert_eq!(surface.cell(4, 1), None);
+
+        surface.draw(&[DrawTarget::Clear(Color::White), text(8, 0, 24, 32, "ab\nc")]);
+        assert_eq!(surface.lines(), [" ab", " c"]);
+    }
+
+    #[test]
+    fn commands_near_the_end_of_the_coordinates_are_ignored() {
+        let max = u32::MAX;
+        let mut surface = TextSurface::new(4, 2, TextCharset::Ascii);
+        surface.set_cell_size(1, 1);
+        surface.draw(&[
+            DrawTarget::FillRectangle(Color::Black, Color::Black, max - 1, 0, 10, 10),
+            DrawTarget::DrawRectangle(Color::Black, 1, 0, max, 3, 3),
+            text(max, max, 5, 5, "ab\ncd"),
+        ]);
+        assert_eq!(surface.lines(), ["", ""]);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::raster::Pixmap;
+use crate::{DrawTarget, Surface};
+
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoFormat {
+    /// YUV4MPEG2 stream with 4:2:0 chroma subsampling
+    Y4m,
+    /// RGBA pixels without any header.
+    /// The reader has to be told the size and frame rate, e.g. `ffmpeg -f rawvideo -pix_fmt rgba -s 320x240 -r 30 -i -`
+    RawRgba,
+}
+
+/// Surface that writes every draw as a video frame, e.g. to a file or the stdin of ffmpeg
+pub struct VideoSurface<W: Write> {
+    writer: W,
+    format: VideoFormat,
+    pixmap: Pixmap,
+
+    frame_rate: (u32, u32),
+    frames: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> VideoSurface<W> {
+    pub fn new(writer: W, width: f64, height: f64, format: VideoFormat) -> Self {
+        Self {
+            writer,
+            format,
+            pixmap: Pixmap::new(width as u32, height as u32),
+            frame_rate: (30, 1),
+            frames: 0,
+            buffer: vec![],
+        }
+    }
+
+    /// Frame rate as a fraction, 30 frames per second by default.
+    /// It is written to the stream header, so it has to be set before the first frame.
+    pub fn set_frame_rate(&mut self, numerator: u32, denominator: u32) {
+        if self.frames != 0 {
+            warn!("The frame rate cannot be changed after the first frame");
+            return;
+        }
+        self.frame_rate = (numerator, denominator);
+    }
+
+    /// Number of frames written so far
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flushes the stream and returns the writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_frame(&mut self) -> std::io::Result<()> {
+        match self.format {
+            VideoFormat::Y4m => {
+                // The samples use the full range, which readers assume to be limited without the tag
+                if self.frames == 0 {
+                    writeln!(
+                        self.writer,
+                        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+                        self.pixmap.width(),
+                        self.pixmap.height(),
+                        self.frame_rate.0,
+                        self.frame_rate.1
+                    )?;
+                }
+                to_yuv420(&self.pixmap, &mut self.buffer);
+                self.writer.write_all(b"FRAME\n")?;
+                self.writer.write_all(&self.buffer)?;
+            }
+            VideoFormat::RawRgba => {
+                self.writer.write_all(self.pixmap.as_slice())?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Surface for VideoSurface<W> {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.pixmap.as_mut_slice().fill(0);
+        self.pixmap.draw(ctx);
+
+        match self.write_frame() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+            }
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.pixmap.width(), self.pixmap.height())
+    }
+}
+
+/// Converts to planar full range BT.601 YCbCr, averaging the chroma of 2x2 blocks
+fn to_yuv420(pixmap: &Pixmap, buffer: &mut Vec<u8>) {
+    let width = pixmap.width() as usize;
+    let height = pixmap.height() as usize;
+    let data = pixmap.as_slice();
+    let rgb = |x: usize, y: usize| {
+        let index = (y * width + x) * 4;
+        (data[index] as f64, data[index + 1] as f64, data[index + 2] as f64)
+    };
+
+    buffer.clear();
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = rgb(x, y);
+            buffer.push((0.299 * r + 0.587 * g + 0.114 * b).round() as u8);
+        }
+    }
+
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let mut cb = Vec::with_capacity(chroma_width * chroma_height);
+    let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut r, mut g, mut b, mut count) = (0.0, 0.0, 0.0, 0.0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let pixel = rgb(x, y);
+                    r += pixel.0;
+                    g += pixel.1;
+                    b += pixel.2;
+                    count += 1.0;
+                }
+            }
+            let (r, g, b) = (r / count, g / count, b / count);
+            cb.push((128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b).round().clamp(0.0, 255.0) as u8);
+            cr.push((128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b).round().clamp(0.0, 255.0) as u8);
+        }
+    }
+
+    buffer.extend_from_slice(&cb);
+    buffer.extend_from_slice(&cr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn fill(color: Color, x: u32, y: u32, width: u32, height: u32) -> DrawTarget {
+        DrawTarget::FillRectangle(color, color, x, y, width, height)
+    }
+
+    #[test]
+    fn y4m_header_and_frames() {
+        let mut surface = VideoSurface::new(vec![], 3.0, 3.0, VideoFormat::Y4m);
+        surface.set_frame_rate(25, 2);
+        surface.draw(&[fill(Color::White, 0, 0, 3, 3)]);
+        // Ignored after the first frame
+        surface.set_frame_rate(60, 1);
+        surface.draw(&[fill(Color::Red, 0, 0, 3, 3)]);
+        assert_eq!(surface.frame_count(), 2);
+
+        let data = surface.into_inner().unwrap();
+        let header = b"YUV4MPEG2 W3 H3 F25:2 Ip A1:1 C420jpeg XCOLORRANGE=FULL\n";
+        assert!(data.starts_with(header));
+
+        // 3x3 luma and 2x2 for each chroma plane
+        let frames = data[header.len()..].split_at(6 + 9 + 4 + 4);
+        let white = [b"FRAME\n".as_slice(), &[255; 9], &[128; 4], &[128; 4]].concat();
+        let red = [b"FRAME\n".as_slice(), &[76; 9], &[85; 4], &[255; 4]].concat();
+        assert_eq!(frames.0, white);
+        assert_eq!(frames.1, red);
+    }
+
+    #[test]
+    fn chroma_is_averaged_over_2x2_blocks() {
+        let mut pixmap = Pixmap::new(2, 1);
+        pixmap.draw(&[fill(Color::White, 0, 0, 1, 1), fill(Color::Black, 1, 0, 1, 1)]);
+        let mut buffer = vec![];
+        to_yuv420(&pixmap, &mut buffer);
+        // Gray cancels out in the chroma
+        assert_eq!(buffer, [255, 0, 128, 128]);
+    }
+
+    #[test]
+    fn raw_rgba_has_no_header() {
+        let mut surface = VideoSurface::new(vec![], 2.0, 1.0, VideoFormat::RawRgba);
+        surface.draw(&[fill(Color::Blue, 0, 0, 1, 1)]);
+        surface.draw(&[]);
+        assert_eq!(surface.into_inner().unwrap(), [0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
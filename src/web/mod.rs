@@ -1,17 +1,110 @@
+mod offscreen;
+
+pub use offscreen::{from_message, to_message, OffscreenSurface};
+
 use crate::Surface;
 
-use crate::DrawTarget;
-use crate::Vec4;
+use crate::{Color, DrawTarget, FontInfo, Rect, UString, Vec4};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use wasm_bindgen::JsValue;
-use web_sys::{CanvasRenderingContext2d as Context, HtmlCanvasElement};
+use web_sys::{
+    CanvasRenderingContext2d as Context, HtmlCanvasElement, OffscreenCanvas, OffscreenCanvasRenderingContext2d,
+};
 
 #[wasm_bindgen]
 extern "C" {
     pub fn alert(s: &str);
 }
 
+/// Methods shared by the 2D contexts of HTML canvases and offscreen canvases
+trait Context2d {
+    fn clear_rect(&self, x: f64, y: f64, width: f64, height: f64);
+    fn fill_rect(&self, x: f64, y: f64, width: f64, height: f64);
+    fn stroke_rect(&self, x: f64, y: f64, width: f64, height: f64);
+    fn rect(&self, x: f64, y: f64, width: f64, height: f64);
+    fn set_fill_style_str(&self, style: &str);
+    fn set_stroke_style_str(&self, style: &str);
+    fn set_line_width(&self, width: f64);
+    fn set_font(&self, font: &str);
+    fn set_text_baseline(&self, baseline: &str);
+    fn fill_text(&self, text: &str, x: f64, y: f64) -> Result<(), JsValue>;
+    fn measure_text_width(&self, text: &str) -> f64;
+    fn save(&self);
+    fn restore(&self);
+    fn begin_path(&self);
+    fn clip(&self);
+    fn set_transform(&self, a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Result<(), JsValue>;
+    fn set_global_alpha(&self, alpha: f64);
+    fn draw_offscreen_canvas(&self, canvas: &OffscreenCanvas, x: f64, y: f64) -> Result<(), JsValue>;
+}
+
+macro_rules! impl_context_2d {
+    ($t:ty) => {
+        impl Context2d for $t {
+            fn clear_rect(&self, x: f64, y: f64, width: f64, height: f64) {
+                <$t>::clear_rect(self, x, y, width, height)
+            }
+            fn fill_rect(&self, x: f64, y: f64, width: f64, height: f64) {
+                <$t>::fill_rect(self, x, y, width, height)
+            }
+            fn stroke_rect(&self, x: f64, y: f64, width: f64, height: f64) {
+                <$t>::stroke_rect(self, x, y, width, height)
+            }
+            fn rect(&self, x: f64, y: f64, width: f64, height: f64) {
+                <$t>::rect(self, x, y, width, height)
+            }
+            fn set_fill_style_str(&self, style: &str) {
+                <$t>::set_fill_style_str(self, style)
+            }
+            fn set_stroke_style_str(&self, style: &str) {
+                <$t>::set_stroke_style_str(self, style)
+            }
+            fn set_line_width(&self, width: f64) {
+                <$t>::set_line_width(self, width)
+            }
+            fn set_font(&self, font: &str) {
+                <$t>::set_font(self, font)
+            }
+            fn set_text_baseline(&self, baseline: &str) {
+                <$t>::set_text_baseline(self, baseline)
+            }
+            fn fill_text(&self, text: &str, x: f64, y: f64) -> Result<(), JsValue> {
+                <$t>::fill_text(self, text, x, y)
+            }
+            fn measure_text_width(&self, text: &str) -> f64 {
+                <$t>::measure_text(self, text).map(|m| m.width()).unwrap_or(0.0)
+            }
+            fn save(&self) {
+                <$t>::save(self)
+            }
+            fn restore(&self) {
+                <$t>::restore(self)
+            }
+            fn begin_path(&self) {
+                <$t>::begin_path(self)
+            }
+            fn clip(&self) {
+                <$t>::clip(self)
+            }
+            fn set_transform(&self, a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Result<(), JsValue> {
+                <$t>::set_transform(self, a, b, c, d, e, f)
+            }
+            fn set_global_alpha(&self, alpha: f64) {
+                <$t>::set_global_alpha(self, alpha)
+            }
+            fn draw_offscreen_canvas(&self, canvas: &OffscreenCanvas, x: f64, y: f64) -> Result<(), JsValue> {
+                <$t>::draw_image_with_offscreen_canvas(self, canvas, x, y)
+            }
+        }
+    };
+}
+
+impl_context_2d!(Context);
+impl_context_2d!(OffscreenCanvasRenderingContext2d);
+
+/// Surface for a canvas element.
+/// The canvas is rendered at the device pixel ratio, so it stays sharp on HiDPI displays.
+/// Its logical size is the size the canvas is laid out with.
 pub struct WebSurface {
     canvas: HtmlCanvasElement,
     ctx: Context,
@@ -28,37 +121,236 @@ impl WebSurface {
 
         Self { canvas, ctx }
     }
+
+    /// Makes the pixels of the canvas match the physical size it is laid out with.
+    /// Returns true if the size changed, which clears the canvas.
+    fn update_size(&self) -> bool {
+        let scale = self.scale_factor();
+        let (logical_width, logical_height) = self.get_client_size();
+        let width = (logical_width as f64 * scale).round() as u32;
+        let height = (logical_height as f64 * scale).round() as u32;
+        // Setting the size clears the canvas, even if it does not change
+        if self.canvas.width() != width || self.canvas.height() != height {
+            // Without a CSS size, the canvas would be laid out at its new pixel size
+            let style = self.canvas.style();
+            let _ = style.set_property("width", &format!("{}px", logical_width));
+            let _ = style.set_property("height", &format!("{}px", logical_height));
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+            return true;
+        }
+        false
+    }
 }
 
 impl Surface for WebSurface {
-    fn draw(&mut self, ctx: Vec<DrawTarget>) {
-        self.ctx.begin_path();
-        for i in ctx {
-            match i {
-                DrawTarget::Clear(color) => {
-                    let color: Vec4 = Vec4::from(color);
-                    self.ctx.set_fill_style(&JsValue::from_str(&format!(
-                        "rgba({},{},{},{})",
-                        color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8,
-                    )));
-                    self.ctx.rect(0.0, 0.0, 300.0, 150.0);
-                    self.ctx.fill();
-                }
-                DrawTarget::FillRectangle(color, x, y, width, height) => {
-                    let color: Vec4 = Vec4::from(color);
-                    self.ctx.set_fill_style(&JsValue::from_str(&format!(
-                        "rgba({},{},{},{})",
-                        color.0 as u8, color.1 as u8, color.2 as u8, color.3 as u8,
-                    )));
-                    self.ctx
-                        .rect(x as f64, y as f64, width as f64, height as f64);
-                    self.ctx.fill();
-                }
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.update_size();
+        render(&self.ctx, self.get_client_size(), self.scale_factor(), ctx, None);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        let damage = if self.update_size() { None } else { Some(damage) };
+        render(&self.ctx, self.get_client_size(), self.scale_factor(), ctx, damage);
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        let (width, height) = (self.canvas.client_width(), self.canvas.client_height());
+        if width > 0 && height > 0 {
+            return (width as u32, height as u32);
+        }
+        // Canvases that are not laid out have no client size
+        let scale = self.scale_factor();
+        (
+            (self.canvas.width() as f64 / scale).round() as u32,
+            (self.canvas.height() as f64 / scale).round() as u32,
+        )
+    }
+
+    fn scale_factor(&self) -> f64 {
+        web_sys::window().map(|w| w.device_pixel_ratio()).filter(|r| *r > 0.0).unwrap_or(1.0)
+    }
+}
+
+/// Draws a context in logical pixels, the context is scaled to the pixels of the canvas.
+/// With damage, only the commands inside of the damaged regions are drawn, clipped to them.
+fn render(context: &impl Context2d, size: (u32, u32), scale: f64, ctx: &[DrawTarget], damage: Option<&[Rect]>) {
+    let _ = context.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
+    let Some(damage) = damage else {
+        draw_commands(context, size, scale, ctx, None);
+        return;
+    };
+
+    for rect in damage {
+        context.save();
+        clip(context, rect);
+        draw_commands(context, size, scale, ctx, Some(rect));
+        context.restore();
+    }
+}
+
+fn clip(context: &impl Context2d, rect: &Rect) {
+    context.begin_path();
+    context.rect(rect.x as f64, rect.y as f64, rect.width as f64, rect.height as f64);
+    context.clip();
+}
+
+/// Draws commands, skipping the ones outside of the clip rectangle
+fn draw_commands(context: &impl Context2d, size: (u32, u32), scale: f64, ctx: &[DrawTarget], clip: Option<&Rect>) {
+    let mut rest = ctx;
+    while let Some((command, tail)) = rest.split_first() {
+        rest = tail;
+        match *command {
+            DrawTarget::BeginLayer(opacity) => {
+                let (layer, tail) = split_layer(rest);
+                rest = tail;
+                draw_layer(context, size, scale, layer, opacity, clip);
             }
+            DrawTarget::EndLayer => {}
+            _ if clip.is_some_and(|clip| !command.intersects(clip)) => {}
+            _ => draw_command(context, size, command),
         }
     }
+}
 
-    fn get_client_size(&self) -> (u32, u32) {
-        (0, 0)
+/// Splits the commands after a BeginLayer into the ones of the layer and the ones after its EndLayer
+fn split_layer(ctx: &[DrawTarget]) -> (&[DrawTarget], &[DrawTarget]) {
+    let mut depth = 0;
+    for (index, command) in ctx.iter().enumerate() {
+        match command {
+            DrawTarget::BeginLayer(_) => depth += 1,
+            DrawTarget::EndLayer if depth == 0 => return (&ctx[..index], &ctx[index + 1..]),
+            DrawTarget::EndLayer => depth -= 1,
+            _ => {}
+        }
     }
+    // A layer that is not ended lasts until the end of the context
+    (ctx, &[])
+}
+
+/// Draws a layer into an offscreen canvas and blends it over the context with its opacity
+fn draw_layer(context: &impl Context2d, size: (u32, u32), scale: f64, ctx: &[DrawTarget], opacity: u8, rect: Option<&Rect>) {
+    let width = (size.0 as f64 * scale).round() as u32;
+    let height = (size.1 as f64 * scale).round() as u32;
+    let Ok(canvas) = OffscreenCanvas::new(width.max(1), height.max(1)) else {
+        return;
+    };
+    let Some(layer) = canvas
+        .get_context("2d")
+        .ok()
+        .flatten()
+        .and_then(|layer| layer.dyn_into::<OffscreenCanvasRenderingContext2d>().ok())
+    else {
+        return;
+    };
+
+    let _ = layer.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
+    if let Some(rect) = rect {
+        clip(&layer, rect);
+    }
+    draw_commands(&layer, size, scale, ctx, rect);
+
+    context.save();
+    let _ = context.set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+    context.set_global_alpha(opacity as f64 / 255.0);
+    let _ = context.draw_offscreen_canvas(&canvas, 0.0, 0.0);
+    context.restore();
+}
+
+fn draw_command(context: &impl Context2d, (canvas_width, canvas_height): (u32, u32), command: &DrawTarget) {
+    match *command {
+        DrawTarget::Clear(color) => {
+            let (width, height) = (canvas_width as f64, canvas_height as f64);
+            context.clear_rect(0.0, 0.0, width, height);
+            context.set_fill_style_str(&css(color));
+            context.fill_rect(0.0, 0.0, width, height);
+        }
+        DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+            draw_rectangle(context, border_color, 1, (x, y, width, height));
+            if width > 2 && height > 2 {
+                context.set_fill_style_str(&css(color));
+                context.fill_rect(
+                    (x + 1) as f64,
+                    (y + 1) as f64,
+                    (width - 2) as f64,
+                    (height - 2) as f64,
+                );
+            }
+        }
+        DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+            draw_rectangle(context, color, thickness, (x, y, width, height));
+        }
+        DrawTarget::DrawText(color, info, x, y, width, height, ref string) => {
+            draw_text(context, color, &info, (x, y, width, height), string);
+        }
+        // Handled by draw_commands
+        DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
+    }
+}
+
+/// Outlines a rectangle, the border is drawn inside of the rectangle like on the other surfaces
+fn draw_rectangle(context: &impl Context2d, color: Color, thickness: u32, (x, y, width, height): (u32, u32, u32, u32)) {
+    let (x, y, width, height) = (x as f64, y as f64, width as f64, height as f64);
+    let thickness = thickness as f64;
+    if thickness * 2.0 >= width || thickness * 2.0 >= height {
+        context.set_fill_style_str(&css(color));
+        context.fill_rect(x, y, width, height);
+        return;
+    }
+
+    context.set_stroke_style_str(&css(color));
+    context.set_line_width(thickness);
+    context.stroke_rect(
+        x + thickness / 2.0,
+        y + thickness / 2.0,
+        width - thickness,
+        height - thickness,
+    );
+}
+
+fn draw_text(context: &impl Context2d, color: Color, info: &FontInfo, (x, y, width, height): (u32, u32, u32, u32), string: &UString) {
+    let (x, y, width, height) = (x as f64, y as f64, width as f64, height as f64);
+    let px = info.0 as f64;
+    let text = String::from_utf16_lossy(string.as_utf16());
+
+    // Text is clipped to its rectangle like DrawText does on Windows
+    context.save();
+    context.begin_path();
+    context.rect(x, y, width, height);
+    context.clip();
+
+    context.set_fill_style_str(&css(color));
+    context.set_font(&font(info));
+    context.set_text_baseline("top");
+    for (i, line) in text.split('\n').enumerate() {
+        let top = y + i as f64 * px;
+        if top >= y + height {
+            break;
+        }
+        let _ = context.fill_text(line, x, top);
+        if info.2 {
+            let line_width = context.measure_text_width(line);
+            context.fill_rect(x, top + px * 0.9, line_width, (px / 14.0).max(1.0));
+        }
+    }
+
+    context.restore();
+}
+
+/// CSS color of a Color, the alpha of rgba() goes from 0 to 1
+fn css(color: Color) -> String {
+    let color = Vec4::from(color);
+    format!(
+        "rgba({},{},{},{})",
+        color.0 as u8,
+        color.1 as u8,
+        color.2 as u8,
+        color.3 / 255.0,
+    )
+}
+
+/// CSS font of a FontInfo, underlines are drawn separately
+fn font(info: &FontInfo) -> String {
+    let style = if info.1 { "italic " } else { "" };
+    format!("{}{}px sans-serif", style, info.0)
 }
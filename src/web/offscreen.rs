@@ -0,0 +1,81 @@
+use crate::web::render;
+use crate::{codec, Azusa, DrawTarget, Rect, Surface};
+
+use js_sys::Uint8Array;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{OffscreenCanvas, OffscreenCanvasRenderingContext2d};
+
+/// Surface for an OffscreenCanvas, e.g. one that was transferred to a web worker with
+/// `HtmlCanvasElement::transfer_control_to_offscreen`
+pub struct OffscreenSurface {
+    canvas: OffscreenCanvas,
+    ctx: OffscreenCanvasRenderingContext2d,
+    scale: f64,
+}
+
+impl OffscreenSurface {
+    pub fn new(canvas: OffscreenCanvas) -> Self {
+        let ctx: OffscreenCanvasRenderingContext2d = canvas
+            .get_context("2d")
+            .unwrap()
+            .unwrap()
+            .dyn_into()
+            .unwrap();
+
+        Self {
+            canvas,
+            ctx,
+            scale: 1.0,
+        }
+    }
+
+    /// Changes the size of the canvas in logical pixels.
+    /// A transferred canvas cannot be resized by the main thread, so the worker has to do it.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.canvas.set_width((width as f64 * self.scale).round() as u32);
+        self.canvas.set_height((height as f64 * self.scale).round() as u32);
+    }
+
+    /// Workers cannot see the device pixel ratio, so the main thread has to send it.
+    /// Call resize afterwards to apply it to the size of the canvas.
+    pub fn set_scale_factor(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+}
+
+impl Surface for OffscreenSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        render(&self.ctx, self.get_client_size(), self.scale, ctx, None);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        render(&self.ctx, self.get_client_size(), self.scale, ctx, Some(damage));
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (
+            (self.canvas.width() as f64 / self.scale).round() as u32,
+            (self.canvas.height() as f64 / self.scale).round() as u32,
+        )
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.scale
+    }
+}
+
+/// Serializes the commands of a context into a message for `Worker::post_message`.
+/// The buffer of the array can be transferred instead of copied with `post_message_with_transfer`.
+pub fn to_message(azusa: &Azusa) -> Uint8Array {
+    Uint8Array::from(codec::encode(azusa.get_ctx()).as_slice())
+}
+
+/// Reads a context from the data of a message created by to_message
+pub fn from_message(message: &JsValue) -> Result<Azusa, JsValue> {
+    let array = message
+        .dyn_ref::<Uint8Array>()
+        .ok_or_else(|| JsValue::from_str("The message is not a Uint8Array"))?;
+    codec::decode(&array.to_vec())
+        .map(Azusa::from_ctx)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -1,12 +1,12 @@
 use crate::window::Backend;
-use crate::{Color, FontInfo, UString, Vec4};
+use crate::{Color, FontInfo, Rect, UString, Vec4};
 
 use std::ffi::{c_int, c_void};
 use std::ptr::null_mut;
 
 use winapi::shared::windef::{DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, HBITMAP, HDC, HGDIOBJ, HWND, LPRECT, RECT};
-use winapi::um::wingdi::{BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetStockObject, Rectangle, SelectObject, SetDCBrushColor, SetDCPenColor, DC_BRUSH, DC_PEN, RGB, SRCCOPY, SetBkColor, TRANSPARENT, SetBkMode, SetTextColor, CreateFontW, CLIP_DEFAULT_PRECIS, OUT_DEFAULT_PRECIS, DEFAULT_CHARSET, FW_REGULAR, FF_ROMAN, DEFAULT_QUALITY, FF_MODERN};
-use winapi::um::winuser::{DrawTextW, DT_WORD_ELLIPSIS, GetClientRect, GetDC, ReleaseDC, SetProcessDpiAwarenessContext};
+use winapi::um::wingdi::{AlphaBlend, BitBlt, BLENDFUNCTION, AC_SRC_OVER, IntersectClipRect, SelectClipRgn, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetStockObject, Rectangle, SelectObject, SetDCBrushColor, SetDCPenColor, DC_BRUSH, DC_PEN, RGB, SRCCOPY, SetBkColor, TRANSPARENT, SetBkMode, SetTextColor, CreateFontW, CLIP_DEFAULT_PRECIS, OUT_DEFAULT_PRECIS, DEFAULT_CHARSET, FW_REGULAR, FF_ROMAN, DEFAULT_QUALITY, FF_MODERN};
+use winapi::um::winuser::{DrawTextW, DT_WORD_ELLIPSIS, GetClientRect, GetDC, GetDpiForWindow, ReleaseDC, SetProcessDpiAwarenessContext};
 
 pub struct GDIBackend {
     hwnd: HWND,
@@ -15,9 +15,24 @@ pub struct GDIBackend {
 
     bitmap: HBITMAP,
     obmp: HGDIOBJ,
+    bitmap_size: (i32, i32),
 
     rect: RECT,
     clear_color: Color,
+    clip: Option<Rect>,
+    /// Layers that are being drawn, the innermost last
+    layers: Vec<Layer>,
+}
+
+/// Bitmap a layer is drawn into. It starts as a copy of the pixels below it,
+/// so blending the whole bitmap with the opacity only changes what the layer drew.
+struct Layer {
+    /// Device context of the pixels below the layer
+    below: HDC,
+    hdc: HDC,
+    bitmap: HBITMAP,
+    obmp: HGDIOBJ,
+    opacity: u8,
 }
 
 impl GDIBackend {
@@ -33,6 +48,7 @@ impl GDIBackend {
             dc: 0 as HDC,
             bitmap: 0 as HBITMAP,
             obmp: 0 as HGDIOBJ,
+            bitmap_size: (0, 0),
             rect: RECT {
                 left: 0,
                 top: 0,
@@ -40,7 +56,23 @@ impl GDIBackend {
                 bottom: 0,
             },
             clear_color: Color::Black,
+            clip: None,
+            layers: vec![],
+        }
+    }
+
+    /// Releases the bitmap frames are drawn into
+    fn destroy_bitmap(&mut self) {
+        if self.hdc.is_null() {
+            return;
+        }
+        unsafe {
+            SelectObject(self.hdc, self.obmp);
+            DeleteDC(self.hdc);
+            DeleteObject(self.bitmap as HGDIOBJ);
         }
+        self.hdc = 0 as HDC;
+        self.bitmap = 0 as HBITMAP;
     }
 
     /// Regenerate Target (to accommodate window resizing)
@@ -69,9 +101,18 @@ impl Backend for GDIBackend {
             GetClientRect(self.hwnd, &mut self.rect);
 
             self.dc = GetDC(self.hwnd);
-            self.hdc = CreateCompatibleDC(self.dc);
-            self.bitmap = CreateCompatibleBitmap(self.dc, self.rect.right, self.rect.bottom);
-            self.obmp = SelectObject(self.hdc, self.bitmap as HGDIOBJ);
+        }
+
+        // The bitmap is kept between frames, so damaged frames only repaint parts of it
+        let size = (self.rect.right, self.rect.bottom);
+        if self.hdc.is_null() || self.bitmap_size != size {
+            self.destroy_bitmap();
+            unsafe {
+                self.hdc = CreateCompatibleDC(self.dc);
+                self.bitmap = CreateCompatibleBitmap(self.dc, size.0, size.1);
+                self.obmp = SelectObject(self.hdc, self.bitmap as HGDIOBJ);
+            }
+            self.bitmap_size = size;
         }
     }
 
@@ -151,7 +192,7 @@ impl Backend for GDIBackend {
         }
     }
 
-    fn draw_text(&mut self,color: Color,string: UString,info:FontInfo,x:u32,y:u32,width:u32,height:u32) {
+    fn draw_text(&mut self,color: Color,string: &UString,info:FontInfo,x:u32,y:u32,width:u32,height:u32) {
         unsafe {
             let text_color = Vec4::from(color);
             let font = CreateFontW((info.0*2) as c_int, info.0 as c_int, 0, 0, FW_REGULAR, info.1 as u32, info.2 as u32, 0, DEFAULT_CHARSET, OUT_DEFAULT_PRECIS , CLIP_DEFAULT_PRECIS, DEFAULT_QUALITY, FF_MODERN, null_mut());
@@ -167,24 +208,72 @@ impl Backend for GDIBackend {
         }
     }
 
-    fn end(&mut self) {
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
         unsafe {
-            BitBlt(
-                self.dc,
-                self.rect.left,
-                self.rect.top,
-                self.rect.right,
-                self.rect.bottom,
-                self.hdc,
-                0,
-                0,
-                SRCCOPY,
-            );
-            SelectObject(self.hdc, self.obmp);
+            SelectClipRgn(self.hdc, null_mut());
+            if let Some(rect) = clip {
+                IntersectClipRect(self.hdc, rect.x as c_int, rect.y as c_int, rect.right() as c_int, rect.bottom() as c_int);
+            }
+        }
+    }
 
-            DeleteDC(self.hdc);
-            DeleteObject(self.bitmap as HGDIOBJ);
+    fn begin_layer(&mut self, opacity: u8) {
+        let (width, height) = self.bitmap_size;
+        unsafe {
+            let hdc = CreateCompatibleDC(self.dc);
+            let bitmap = CreateCompatibleBitmap(self.dc, width, height);
+            let obmp = SelectObject(hdc, bitmap as HGDIOBJ);
+            BitBlt(hdc, 0, 0, width, height, self.hdc, 0, 0, SRCCOPY);
+            self.layers.push(Layer {
+                below: self.hdc,
+                hdc,
+                bitmap,
+                obmp,
+                opacity,
+            });
+            self.hdc = hdc;
+        }
+        self.set_clip(self.clip);
+    }
+
+    fn end_layer(&mut self) {
+        let Some(layer) = self.layers.pop() else {
+            return;
+        };
+        let (width, height) = self.bitmap_size;
+        let blend = BLENDFUNCTION {
+            BlendOp: AC_SRC_OVER,
+            BlendFlags: 0,
+            SourceConstantAlpha: layer.opacity,
+            AlphaFormat: 0,
+        };
+        unsafe {
+            // The clip of the DC below limits the blend to the damaged region
+            AlphaBlend(layer.below, 0, 0, width, height, layer.hdc, 0, 0, width, height, blend);
+            SelectObject(layer.hdc, layer.obmp);
+            DeleteDC(layer.hdc);
+            DeleteObject(layer.bitmap as HGDIOBJ);
+        }
+        self.hdc = layer.below;
+    }
 
+    fn end(&mut self, damage: Option<&[Rect]>) {
+        let whole = [Rect::new(0, 0, self.rect.right as u32, self.rect.bottom as u32)];
+        unsafe {
+            for rect in damage.unwrap_or(&whole) {
+                BitBlt(
+                    self.dc,
+                    rect.x as c_int,
+                    rect.y as c_int,
+                    rect.width as c_int,
+                    rect.height as c_int,
+                    self.hdc,
+                    rect.x as c_int,
+                    rect.y as c_int,
+                    SRCCOPY,
+                );
+            }
             ReleaseDC(self.hwnd, self.dc);
         }
     }
@@ -195,4 +284,18 @@ impl Backend for GDIBackend {
             self.rect.bottom.try_into().unwrap(),
         )
     }
+
+    fn scale_factor(&self) -> f64 {
+        // 96 DPI is 100%
+        match unsafe { GetDpiForWindow(self.hwnd) } {
+            0 => 1.0,
+            dpi => dpi as f64 / 96.0,
+        }
+    }
+}
+
+impl Drop for GDIBackend {
+    fn drop(&mut self) {
+        self.destroy_bitmap();
+    }
 }
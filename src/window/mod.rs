@@ -1,9 +1,15 @@
 #[cfg(target_os = "windows")]
 mod gdi;
+pub mod software;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+mod x11;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+mod wayland;
 
-use crate::{Color, DrawTarget, FontInfo, Surface, UString};
+use crate::{scale_ctx, Color, DrawTarget, FontInfo, Rect, Surface, UString};
+use software::{Presenter, SoftwareBackend};
 #[cfg(feature = "window")]
-use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle};
 
 pub trait Backend {
     fn begin(&mut self);
@@ -26,10 +32,26 @@ pub trait Backend {
         width: f32,
         height: f32,
     );
-    fn draw_text(&mut self,color: Color,string: UString,info:FontInfo,x:u32,y:u32,width:u32,height:u32);
-    fn end(&mut self);
+    #[allow(clippy::too_many_arguments)]
+    fn draw_text(&mut self,color: Color,string: &UString,info:FontInfo,x:u32,y:u32,width:u32,height:u32);
+    /// Restricts drawing to a rectangle in physical pixels, None draws to the whole window
+    fn set_clip(&mut self, clip: Option<Rect>);
+    /// Following drawings go to a transparent layer that is blended over the window with the opacity by end_layer
+    fn begin_layer(&mut self, opacity: u8);
+    /// Blends the innermost layer over what was drawn before it
+    fn end_layer(&mut self);
+    /// Shows the frame. With damage, only those regions in physical pixels changed since the last frame.
+    fn end(&mut self, damage: Option<&[Rect]>);
 
+    /// Size of the window in physical pixels
     fn get_client_size(&self) -> (u32, u32);
+
+    /// Tells the backend the size of the window in physical pixels, for platforms where it cannot be queried
+    fn resize(&mut self, _width: u32, _height: u32) {}
+    /// Ratio of device pixels to logical pixels
+    fn scale_factor(&self) -> f64 {
+        1.0
+    }
 }
 
 pub struct WindowSurface {
@@ -37,67 +59,158 @@ pub struct WindowSurface {
 }
 
 impl WindowSurface {
-    pub fn new(handle: &impl HasRawWindowHandle) -> Result<Self, ()> {
+    #[allow(clippy::result_unit_err)]
+    pub fn new(handle: &(impl HasRawWindowHandle + HasRawDisplayHandle)) -> Result<Self, ()> {
+        let display = handle.raw_display_handle();
         let handle = handle.raw_window_handle();
-        let backend = Box::new(match handle {
+        let backend: Box<dyn Backend> = match handle {
             RawWindowHandle::UiKit(_) => return Err(()),
             RawWindowHandle::AppKit(_) => return Err(()),
             RawWindowHandle::Orbital(_) => return Err(()),
-            RawWindowHandle::Xlib(_) => return Err(()),
-            RawWindowHandle::Xcb(_) => return Err(()),
-            RawWindowHandle::Wayland(_) => return Err(()),
+            #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+            RawWindowHandle::Xlib(handle) => {
+                let display = match display {
+                    RawDisplayHandle::Xlib(display) => display.display,
+                    _ => std::ptr::null_mut(),
+                };
+                Box::new(SoftwareBackend::new(x11::X11Presenter::new(display, handle.window)?))
+            }
+            #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+            RawWindowHandle::Xcb(handle) => {
+                Box::new(SoftwareBackend::new(x11::X11Presenter::new(std::ptr::null_mut(), handle.window as _)?))
+            }
+            #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios", target_os = "android"))))]
+            RawWindowHandle::Wayland(handle) => match display {
+                RawDisplayHandle::Wayland(display) => {
+                    Box::new(SoftwareBackend::new(wayland::WaylandPresenter::new(display.display, handle.surface)?))
+                }
+                _ => return Err(()),
+            },
             RawWindowHandle::Drm(_) => return Err(()),
             RawWindowHandle::Gbm(_) => return Err(()),
             #[cfg(target_os = "windows")]
-            RawWindowHandle::Win32(handle) => gdi::GDIBackend::new(handle.hwnd),
+            RawWindowHandle::Win32(handle) => Box::new(gdi::GDIBackend::new(handle.hwnd)),
             RawWindowHandle::WinRt(_) => return Err(()),
             RawWindowHandle::Web(_) => return Err(()),
             RawWindowHandle::AndroidNdk(_) => return Err(()),
             RawWindowHandle::Haiku(_) => return Err(()),
             _ => return Err(()),
-        });
+        };
 
         Ok(Self { backend })
     }
-}
 
-impl Surface for WindowSurface {
-    fn draw(&mut self, ctx: Vec<DrawTarget>) {
+    /// Creates a surface for a window system that is not supported by azusa itself.
+    /// The context is rasterized in software and every frame is handed to the presenter.
+    pub fn with_presenter(presenter: impl Presenter + 'static) -> Self {
+        Self {
+            backend: Box::new(SoftwareBackend::new(presenter)),
+        }
+    }
+
+    /// Should be called with the physical size of the window when it is resized.
+    /// Wayland windows are not drawn until the size is set.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.backend.resize(width, height);
+    }
+
+    /// Draws a frame, only inside of the damaged regions if there are any
+    fn paint(&mut self, ctx: &[DrawTarget], damage: Option<&[Rect]>) {
+        let scale = self.backend.scale_factor();
+        let ctx = scale_ctx(ctx, scale);
+        let size = self.backend.get_client_size();
         self.backend.begin();
-        for i in ctx {
-            match i {
-                DrawTarget::Clear(color) => {
-                    self.backend.clear(color);
-                }
-                DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
-                    self.backend.fill_rectangle(
-                        color,
-                        border_color,
-                        x as f32,
-                        y as f32,
-                        width as f32,
-                        height as f32,
-                    );
+
+        // The contents of the window are lost when its size changes
+        let damage: Option<Vec<Rect>> = damage
+            .filter(|_| self.backend.get_client_size() == size)
+            .map(|damage| damage.iter().map(|rect| rect.scale(scale)).collect());
+        match &damage {
+            Some(damage) => {
+                for rect in damage {
+                    self.backend.set_clip(Some(*rect));
+                    // Commands outside of the region would be clipped away completely
+                    self.draw_commands(ctx.iter().filter(|i| i.intersects(rect)));
                 }
-                DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
-                    self.backend.draw_rectangle(
-                        color,
-                        thickness,
-                        x as f32,
-                        y as f32,
-                        width as f32,
-                        height as f32,
-                    );
+                self.backend.set_clip(None);
+            }
+            None => self.draw_commands(ctx.iter()),
+        }
+        self.backend.end(damage.as_deref());
+    }
+
+    /// Draws commands, ending the layers they leave open
+    fn draw_commands<'a>(&mut self, ctx: impl Iterator<Item = &'a DrawTarget>) {
+        let mut layers = 0;
+        for i in ctx {
+            match *i {
+                DrawTarget::BeginLayer(opacity) => {
+                    self.backend.begin_layer(opacity);
+                    layers += 1;
                 }
-                DrawTarget::DrawText(color,info,x,y,width,height,string) => {
-                    self.backend.draw_text(color,string,info,x,y,width,height);
+                DrawTarget::EndLayer if layers > 0 => {
+                    self.backend.end_layer();
+                    layers -= 1;
                 }
+                DrawTarget::EndLayer => {}
+                _ => self.draw_command(i),
+            }
+        }
+        for _ in 0..layers {
+            self.backend.end_layer();
+        }
+    }
+
+    fn draw_command(&mut self, command: &DrawTarget) {
+        match *command {
+            DrawTarget::Clear(color) => {
+                self.backend.clear(color);
+            }
+            DrawTarget::FillRectangle(color, border_color, x, y, width, height) => {
+                self.backend.fill_rectangle(
+                    color,
+                    border_color,
+                    x as f32,
+                    y as f32,
+                    width as f32,
+                    height as f32,
+                );
+            }
+            DrawTarget::DrawRectangle(color, thickness, x, y, width, height) => {
+                self.backend.draw_rectangle(
+                    color,
+                    thickness,
+                    x as f32,
+                    y as f32,
+                    width as f32,
+                    height as f32,
+                );
             }
+            DrawTarget::DrawText(color,info,x,y,width,height,ref string) => {
+                self.backend.draw_text(color,string,info,x,y,width,height);
+            }
+            // Handled by draw_commands
+            DrawTarget::BeginLayer(_) | DrawTarget::EndLayer => {}
         }
-        self.backend.end();
+    }
+}
+
+impl Surface for WindowSurface {
+    fn draw(&mut self, ctx: &[DrawTarget]) {
+        self.paint(ctx, None);
+    }
+
+    fn draw_damaged(&mut self, ctx: &[DrawTarget], damage: &[Rect]) {
+        self.paint(ctx, Some(damage));
     }
 
     fn get_client_size(&self) -> (u32, u32) {
-        self.backend.get_client_size()
+        let (width, height) = self.backend.get_client_size();
+        let scale = self.backend.scale_factor();
+        ((width as f64 / scale).round() as u32, (height as f64 / scale).round() as u32)
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.backend.scale_factor()
     }
 }
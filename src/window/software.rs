@@ -0,0 +1,200 @@
+use crate::raster::Pixmap;
+use crate::window::Backend;
+use crate::{Color, FontInfo, Rect, UString};
+
+/// Shows frames rasterized by a SoftwareBackend in a window.
+/// Supporting a new window system only needs an implementation of this trait.
+pub trait Presenter {
+    /// Called before a frame is drawn.
+    /// Returns the size of the window in pixels, or None if the window system cannot report it,
+    /// in which case the size is set with WindowSurface::resize.
+    fn begin(&mut self) -> Option<(u32, u32)>;
+    /// Copies the pixels of a frame to the window
+    fn present(&mut self, pixmap: &Pixmap);
+    /// Copies the pixels of the regions that changed since the last frame to the window.
+    /// Presenters that cannot update parts of the window present the whole frame.
+    fn present_damaged(&mut self, pixmap: &Pixmap, _damage: &[Rect]) {
+        self.present(pixmap);
+    }
+    /// Ratio of device pixels to logical pixels
+    fn scale_factor(&self) -> f64 {
+        1.0
+    }
+}
+
+/// Backend that rasterizes into a CPU pixel buffer and hands it to a Presenter.
+/// The output is the same as the one of an ImageSurface.
+/// Like it, the backend cannot draw text yet, DrawText commands are skipped with a warning the first time.
+pub struct SoftwareBackend<P: Presenter> {
+    presenter: P,
+    pixmap: Pixmap,
+}
+
+impl<P: Presenter> SoftwareBackend<P> {
+    pub fn new(presenter: P) -> Self {
+        Self {
+            presenter,
+            pixmap: Pixmap::new(0, 0),
+        }
+    }
+
+    pub fn presenter(&self) -> &P {
+        &self.presenter
+    }
+
+    pub fn presenter_mut(&mut self) -> &mut P {
+        &mut self.presenter
+    }
+}
+
+impl<P: Presenter> Backend for SoftwareBackend<P> {
+    fn begin(&mut self) {
+        if let Some((width, height)) = self.presenter.begin() {
+            self.resize(width, height);
+        }
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.pixmap.clear(color);
+    }
+
+    fn fill_rectangle(
+        &mut self,
+        color: Color,
+        border_color: Color,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) {
+        self.pixmap.fill_bordered_rectangle(color, border_color, x as u32, y as u32, width as u32, height as u32);
+    }
+
+    fn draw_rectangle(
+        &mut self,
+        color: Color,
+        thickness: u32,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) {
+        self.pixmap.draw_rectangle(color, thickness, x as u32, y as u32, width as u32, height as u32);
+    }
+
+    fn draw_text(&mut self, _color: Color, _string: &UString, _info: FontInfo, _x: u32, _y: u32, _width: u32, _height: u32) {
+        crate::raster::skip_text();
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.pixmap.set_clip(clip);
+    }
+
+    fn begin_layer(&mut self, opacity: u8) {
+        self.pixmap.begin_layer(opacity);
+    }
+
+    fn end_layer(&mut self) {
+        self.pixmap.end_layer();
+    }
+
+    fn end(&mut self, damage: Option<&[Rect]>) {
+        if self.pixmap.width() == 0 || self.pixmap.height() == 0 {
+            return;
+        }
+        match damage {
+            Some(damage) => self.presenter.present_damaged(&self.pixmap, damage),
+            None => self.presenter.present(&self.pixmap),
+        }
+    }
+
+    fn get_client_size(&self) -> (u32, u32) {
+        (self.pixmap.width(), self.pixmap.height())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width != self.pixmap.width() || height != self.pixmap.height() {
+            self.pixmap.resize(width, height);
+        }
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.presenter.scale_factor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WindowSurface;
+    use crate::{DrawTarget, Surface};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Keeps the pixels that were presented, like a window does
+    struct Mock {
+        size: (u32, u32),
+        frame: Rc<RefCell<Pixmap>>,
+    }
+
+    impl Presenter for Mock {
+        fn begin(&mut self) -> Option<(u32, u32)> {
+            Some(self.size)
+        }
+
+        fn present(&mut self, pixmap: &Pixmap) {
+            *self.frame.borrow_mut() = pixmap.clone();
+        }
+
+        fn present_damaged(&mut self, pixmap: &Pixmap, damage: &[Rect]) {
+            let mut frame = self.frame.borrow_mut();
+            for rect in damage {
+                for y in rect.y..rect.bottom().min(pixmap.height()) {
+                    for x in rect.x..rect.right().min(pixmap.width()) {
+                        let start = ((y * pixmap.width() + x) * 4) as usize;
+                        frame.as_mut_slice()[start..start + 4].copy_from_slice(&pixmap.as_slice()[start..start + 4]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn surface() -> (WindowSurface, Rc<RefCell<Pixmap>>) {
+        let frame = Rc::new(RefCell::new(Pixmap::new(0, 0)));
+        let mock = Mock {
+            size: (20, 10),
+            frame: frame.clone(),
+        };
+        (WindowSurface::with_presenter(mock), frame)
+    }
+
+    #[test]
+    fn presented_frames_match_the_pixmap() {
+        let (mut surface, frame) = surface();
+        let mut expected = Pixmap::new(20, 10);
+        let ctx = vec![
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Black, 2, 1, 8, 6),
+            DrawTarget::BeginLayer(128),
+            DrawTarget::FillRectangle(Color::Blue, Color::Blue, 5, 3, 10, 5),
+            DrawTarget::DrawRectangle(Color::Silver, 2, 0, 0, 20, 10),
+            DrawTarget::EndLayer,
+            DrawTarget::DrawRectangle(Color::Lime, 1, 12, 2, 6, 6),
+        ];
+        surface.draw(&ctx);
+        expected.draw(&ctx);
+        assert_eq!(frame.borrow().as_slice(), expected.as_slice());
+
+        // Only the damaged regions are presented, with a layer left open
+        let ctx = vec![
+            DrawTarget::Clear(Color::White),
+            DrawTarget::FillRectangle(Color::Red, Color::Black, 2, 1, 8, 6),
+            DrawTarget::BeginLayer(64),
+            DrawTarget::FillRectangle(Color::Navy, Color::Yellow, 4, 2, 12, 7),
+        ];
+        let damage = [Rect::new(4, 2, 12, 7)];
+        surface.draw_damaged(&ctx, &damage);
+        expected.draw_damaged(&ctx, &damage);
+        assert_eq!(frame.borrow().as_slice(), expected.as_slice());
+    }
+}
@@ -0,0 +1,478 @@
+use crate::raster::Pixmap;
+use crate::window::software::Presenter;
+use crate::Rect;
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::os::fd::{AsFd, FromRawFd};
+use std::os::unix::fs::FileExt;
+
+use wayland_backend::client::{Backend as WlBackend, ObjectId};
+use wayland_client::protocol::wl_buffer::{self, WlBuffer};
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::protocol::wl_shm::{Format, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::protocol::wl_surface::{self, WlSurface};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+
+/// Buffers the compositor can hold while the next frame is drawn
+const BUFFER_COUNT: usize = 2;
+
+#[derive(Default)]
+struct State {
+    shm: Option<WlShm>,
+    outputs: Outputs<WlOutput>,
+    busy: Busy,
+}
+
+/// Scales of the outputs and the outputs the surface has entered
+struct Outputs<T> {
+    scales: Vec<(T, i32)>,
+    entered: Vec<T>,
+}
+
+impl<T> Default for Outputs<T> {
+    fn default() -> Self {
+        Self {
+            scales: vec![],
+            entered: vec![],
+        }
+    }
+}
+
+impl<T: PartialEq> Outputs<T> {
+    fn add(&mut self, output: T) {
+        self.scales.push((output, 1));
+    }
+
+    fn set_scale(&mut self, output: &T, factor: i32) {
+        if let Some((_, scale)) = self.scales.iter_mut().find(|(i, _)| i == output) {
+            *scale = factor;
+        }
+    }
+
+    fn enter(&mut self, output: T) {
+        if !self.entered.contains(&output) {
+            self.entered.push(output);
+        }
+    }
+
+    fn leave(&mut self, output: &T) {
+        self.entered.retain(|i| i != output);
+    }
+
+    /// Largest scale of the outputs the surface is on, so it is sharp on all of them.
+    /// Until the surface has entered an output, the largest scale of all outputs.
+    fn scale(&self) -> i32 {
+        self.scales
+            .iter()
+            .filter(|(output, _)| self.entered.is_empty() || self.entered.contains(output))
+            .map(|(_, scale)| *scale)
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+/// Buffers that the compositor has not released yet
+#[derive(Default)]
+struct Busy([bool; BUFFER_COUNT]);
+
+impl Busy {
+    /// Buffer that can be drawn to
+    fn free(&self) -> Option<usize> {
+        self.0.iter().position(|busy| !busy)
+    }
+
+    /// The buffer was attached, it must not be written until the compositor releases it
+    fn attach(&mut self, index: usize) {
+        self.0[index] = true;
+    }
+
+    fn release(&mut self, index: usize) {
+        self.0[index] = false;
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for State {
+    fn event(
+        state: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global { name, interface, version } = event {
+            match interface.as_str() {
+                "wl_shm" => state.shm = Some(registry.bind(name, 1, qh, ())),
+                // The scale event was added in version 2
+                "wl_output" if version >= 2 => state.outputs.add(registry.bind(name, 2, qh, ())),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Scale { factor } = event {
+            state.outputs.set_scale(output, factor);
+        }
+    }
+}
+
+impl Dispatch<WlSurface, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &WlSurface,
+        event: wl_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_surface::Event::Enter { output } => state.outputs.enter(output),
+            wl_surface::Event::Leave { output } => state.outputs.leave(&output),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, usize> for State {
+    fn event(
+        state: &mut Self,
+        _: &WlBuffer,
+        event: wl_buffer::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            state.busy.release(*index);
+        }
+    }
+}
+
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: WlShmPool);
+
+/// Bytes of an XRGB8888 buffer, wl_shm takes sizes as i32
+fn buffer_size(width: u32, height: u32) -> Option<i32> {
+    i32::try_from(width).ok()?.checked_mul(i32::try_from(height).ok()?)?.checked_mul(4)
+}
+
+struct Buffer {
+    file: File,
+    pool: WlShmPool,
+    buffer: WlBuffer,
+}
+
+pub struct WaylandPresenter {
+    connection: Connection,
+    queue: EventQueue<State>,
+    state: State,
+    surface: WlSurface,
+
+    pixels: Vec<u8>,
+    buffers: Vec<Buffer>,
+    buffer_size: (u32, u32),
+}
+
+impl WaylandPresenter {
+    /// Create a new presenter for the wl_surface of a window.
+    /// Wayland does not tell clients the size of a surface, so it has to be set with WindowSurface::resize().
+    /// The scale is the largest one of the outputs the surface entered. The enter and leave events of a surface
+    /// created by another library, like the one of a winit window, go to that library, so the largest scale of all outputs is used then.
+    pub fn new(display: *mut c_void, surface: *mut c_void) -> Result<Self, ()> {
+        let backend = unsafe { WlBackend::from_foreign_display(display.cast()) };
+        let connection = Connection::from_backend(backend);
+
+        let surface = unsafe { ObjectId::from_ptr(WlSurface::interface(), surface.cast()) }
+            .map_err(|e| error!("{}", e))
+            .and_then(|id| WlSurface::from_id(&connection, id).map_err(|e| error!("{}", e)))?;
+
+        let mut queue = connection.new_event_queue();
+        let mut state = State::default();
+        connection.display().get_registry(&queue.handle(), ());
+        // The first roundtrip lists the globals, the second one delivers the output scales
+        for _ in 0..2 {
+            if let Err(e) = queue.roundtrip(&mut state) {
+                error!("{}", e);
+                return Err(());
+            }
+        }
+        if state.shm.is_none() {
+            error!("The compositor does not support wl_shm");
+            return Err(());
+        }
+
+        Ok(Self {
+            connection,
+            queue,
+            state,
+            surface,
+            pixels: vec![],
+            buffers: vec![],
+            buffer_size: (0, 0),
+        })
+    }
+
+    fn create_buffers(&mut self, width: u32, height: u32) -> Option<()> {
+        let shm = self.state.shm.as_ref()?;
+        let qh = self.queue.handle();
+        let Some(size) = buffer_size(width, height) else {
+            error!("A buffer of {}x{} pixels is too large for wl_shm", width, height);
+            return None;
+        };
+        let (width, height) = (width as i32, height as i32);
+
+        for index in 0..BUFFER_COUNT {
+            let fd = unsafe { libc::memfd_create(c"azusa".as_ptr(), libc::MFD_CLOEXEC) };
+            if fd < 0 {
+                error!("{}", std::io::Error::last_os_error());
+                return None;
+            }
+            let file = unsafe { File::from_raw_fd(fd) };
+            if let Err(e) = file.set_len(size as u64) {
+                error!("{}", e);
+                return None;
+            }
+
+            let pool = shm.create_pool(file.as_fd(), size, &qh, ());
+            let buffer = pool.create_buffer(0, width, height, width * 4, Format::Xrgb8888, &qh, index);
+            self.buffers.push(Buffer { file, pool, buffer });
+            self.state.busy.release(index);
+        }
+        self.buffer_size = (width as u32, height as u32);
+        Some(())
+    }
+
+    /// Buffers that are still used by the compositor stay alive until it releases them
+    fn destroy_buffers(&mut self) {
+        self.buffer_size = (0, 0);
+        for buffer in self.buffers.drain(..) {
+            buffer.buffer.destroy();
+            buffer.pool.destroy();
+        }
+    }
+
+    /// Waits until the compositor releases one of the buffers
+    fn free_buffer(&mut self) -> Option<usize> {
+        loop {
+            if let Some(index) = self.state.busy.free() {
+                return Some(index);
+            }
+            if let Err(e) = self.queue.blocking_dispatch(&mut self.state) {
+                error!("{}", e);
+                return None;
+            }
+        }
+    }
+}
+
+impl Presenter for WaylandPresenter {
+    fn begin(&mut self) -> Option<(u32, u32)> {
+        if let Err(e) = self.queue.dispatch_pending(&mut self.state) {
+            error!("{}", e);
+        }
+        None
+    }
+
+    fn present(&mut self, pixmap: &Pixmap) {
+        self.present_damaged(pixmap, &[Rect::new(0, 0, pixmap.width(), pixmap.height())]);
+    }
+
+    /// The whole buffer is written, since the buffer that is free may hold an older frame,
+    /// but the compositor only has to update the damaged regions
+    fn present_damaged(&mut self, pixmap: &Pixmap, damage: &[Rect]) {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        if self.buffer_size != (width, height) {
+            self.destroy_buffers();
+            if self.create_buffers(width, height).is_none() {
+                self.destroy_buffers();
+                return;
+            }
+        }
+        let index = match self.free_buffer() {
+            Some(index) => index,
+            None => return,
+        };
+
+        // XRGB8888 is stored as little endian
+        self.pixels.clear();
+        for pixel in pixmap.as_slice().chunks_exact(4) {
+            self.pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+        }
+        let buffer = &self.buffers[index];
+        if let Err(e) = buffer.file.write_all_at(&self.pixels, 0) {
+            error!("{}", e);
+            return;
+        }
+
+        self.surface.attach(Some(&buffer.buffer), 0, 0);
+        self.surface.set_buffer_scale(self.scale_factor() as i32);
+        for rect in damage {
+            self.surface.damage_buffer(rect.x as i32, rect.y as i32, rect.width as i32, rect.height as i32);
+        }
+        self.surface.commit();
+        self.state.busy.attach(index);
+
+        if let Err(e) = self.connection.flush() {
+            error!("{}", e);
+        }
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.state.outputs.scale() as f64
+    }
+}
+
+impl Drop for WaylandPresenter {
+    fn drop(&mut self) {
+        self.destroy_buffers();
+        for (output, _) in self.state.outputs.scales.drain(..) {
+            if output.version() >= 3 {
+                output.release();
+            }
+        }
+        let _ = self.connection.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayland_client::protocol::wl_compositor::WlCompositor;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn scale_follows_the_entered_outputs() {
+        let mut outputs = Outputs::default();
+        assert_eq!(outputs.scale(), 1);
+        outputs.add(1);
+        outputs.add(2);
+        outputs.set_scale(&2, 2);
+        // Nothing entered yet
+        assert_eq!(outputs.scale(), 2);
+
+        outputs.enter(1);
+        assert_eq!(outputs.scale(), 1);
+        outputs.enter(2);
+        outputs.enter(2);
+        assert_eq!(outputs.scale(), 2);
+        outputs.leave(&2);
+        assert_eq!(outputs.scale(), 1);
+        outputs.set_scale(&1, 3);
+        assert_eq!(outputs.scale(), 3);
+    }
+
+    #[test]
+    fn attached_buffers_are_not_reused_until_released() {
+        let mut busy = Busy::default();
+        assert_eq!(busy.free(), Some(0));
+        busy.attach(0);
+        assert_eq!(busy.free(), Some(1));
+        busy.attach(1);
+        assert_eq!(busy.free(), None);
+
+        busy.release(1);
+        assert_eq!(busy.free(), Some(1));
+        busy.release(0);
+        assert_eq!(busy.free(), Some(0));
+    }
+
+    #[test]
+    fn buffer_sizes_that_do_not_fit_are_rejected() {
+        assert_eq!(buffer_size(640, 480), Some(640 * 480 * 4));
+        assert_eq!(buffer_size(0, 480), Some(0));
+        assert_eq!(buffer_size(40000, 40000), None);
+        assert_eq!(buffer_size(u32::MAX, 1), None);
+        assert_eq!(buffer_size(1 << 29, 1), None);
+    }
+
+    #[derive(Default)]
+    struct Globals {
+        compositor: Option<WlCompositor>,
+    }
+
+    impl Dispatch<WlRegistry, ()> for Globals {
+        fn event(
+            state: &mut Self,
+            registry: &WlRegistry,
+            event: wl_registry::Event,
+            _: &(),
+            _: &Connection,
+            qh: &QueueHandle<Self>,
+        ) {
+            if let wl_registry::Event::Global { name, interface, .. } = event {
+                if interface == "wl_compositor" {
+                    state.compositor = Some(registry.bind(name, 1, qh, ()));
+                }
+            }
+        }
+    }
+
+    delegate_noop!(Globals: ignore WlCompositor);
+    delegate_noop!(Globals: ignore WlSurface);
+
+    /// Runs against the compositor socket named by AZUSA_WAYLAND_DISPLAY, e.g.
+    /// `weston --backend=headless-backend.so --socket=azusa-test &` and
+    /// `AZUSA_WAYLAND_DISPLAY=azusa-test cargo test --features window wayland`
+    #[test]
+    fn present_and_resize() {
+        let Ok(name) = std::env::var("AZUSA_WAYLAND_DISPLAY") else {
+            return;
+        };
+        let runtime = std::env::var("XDG_RUNTIME_DIR").expect("XDG_RUNTIME_DIR is not set");
+        let stream = UnixStream::connect(std::path::Path::new(&runtime).join(name)).unwrap();
+        let connection = Connection::from_socket(stream).unwrap();
+        let mut queue = connection.new_event_queue();
+        let mut globals = Globals::default();
+        connection.display().get_registry(&queue.handle(), ());
+        queue.roundtrip(&mut globals).unwrap();
+        let surface = globals.compositor.as_ref().expect("no wl_compositor").create_surface(&queue.handle(), ());
+        queue.roundtrip(&mut globals).unwrap();
+
+        let mut presenter = WaylandPresenter::new(
+            connection.backend().display_ptr().cast(),
+            surface.id().as_ptr().cast(),
+        )
+        .unwrap();
+        assert_eq!(presenter.begin(), None);
+
+        let mut pixmap = Pixmap::new(20, 10);
+        pixmap.clear(crate::Color::Red);
+        presenter.present(&pixmap);
+        assert_eq!(presenter.buffer_size, (20, 10));
+        assert_eq!(presenter.buffers.len(), BUFFER_COUNT);
+        // The pixels are converted to XRGB8888
+        assert_eq!(&presenter.pixels[..4], &[0, 0, 255, 255]);
+
+        // More frames than buffers only go through when the compositor releases them
+        for _ in 0..BUFFER_COUNT * 2 {
+            presenter.present_damaged(&pixmap, &[Rect::new(2, 2, 4, 4)]);
+        }
+        connection.roundtrip().unwrap();
+        presenter.begin();
+        assert!(presenter.state.busy.free().is_some());
+
+        // The buffers follow the size of the frames
+        pixmap.resize(30, 40);
+        presenter.present(&pixmap);
+        assert_eq!(presenter.buffer_size, (30, 40));
+        assert_eq!(presenter.buffers.len(), BUFFER_COUNT);
+        assert_eq!(presenter.pixels.len(), 30 * 40 * 4);
+
+        drop(presenter);
+        surface.destroy();
+        connection.roundtrip().unwrap();
+    }
+}
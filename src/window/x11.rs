@@ -0,0 +1,436 @@
+use crate::raster::Pixmap;
+use crate::window::software::Presenter;
+use crate::Rect;
+
+use std::ffi::{c_char, c_int, c_uint, c_ulong, c_void, CStr};
+use std::mem::MaybeUninit;
+use std::ptr::{null, null_mut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use x11_dl::xlib::{Display, Visual, XErrorEvent, XImage, Xlib, GC, LSBFirst, ZPixmap};
+use x11_dl::xshm::{XShmSegmentInfo, Xext};
+
+/// Image the rasterized pixels are copied into before they are sent to the server
+enum Image {
+    /// Shared memory, the server reads the pixels without copying them through the socket
+    Shm(*mut XImage, Box<XShmSegmentInfo>),
+    /// Plain XPutImage
+    Client(*mut XImage, Vec<u8>),
+}
+
+pub struct X11Presenter {
+    xlib: Xlib,
+    xext: Option<Xext>,
+
+    display: *mut Display,
+    /// Whether the presenter opened the display itself and has to close it
+    owns_display: bool,
+    window: c_ulong,
+    gc: GC,
+    visual: *mut Visual,
+    depth: c_int,
+
+    width: u32,
+    height: u32,
+    image: Option<Image>,
+    scale: f64,
+}
+
+impl X11Presenter {
+    /// Create a new presenter drawing through the Display of the application, or a connection of its own if it is null.
+    /// Xlib cannot use an XCB connection, so windows created through XCB pass a null display.
+    /// Only visuals with 32 bits per pixel are supported.
+    pub fn new(display: *mut c_void, window: c_ulong) -> Result<Self, ()> {
+        let xlib = match Xlib::open() {
+            Ok(xlib) => xlib,
+            Err(e) => {
+                error!("{}", e);
+                return Err(());
+            }
+        };
+        // MIT-SHM is optional
+        let xext = Xext::open().ok();
+
+        let owns_display = display.is_null();
+        let display = match owns_display {
+            true => unsafe { (xlib.XOpenDisplay)(null()) },
+            false => display as *mut Display,
+        };
+        if display.is_null() {
+            error!("Cannot open the X display");
+            return Err(());
+        }
+        let gc = unsafe { (xlib.XCreateGC)(display, window, 0, null_mut()) };
+
+        let mut presenter = Self {
+            xlib,
+            xext,
+            display,
+            owns_display,
+            window,
+            gc,
+            visual: null_mut(),
+            depth: 0,
+            width: 0,
+            height: 0,
+            image: None,
+            scale: 1.0,
+        };
+        presenter.scale = presenter.read_scale().unwrap_or(1.0);
+        if !presenter.update_size() {
+            error!("Cannot get the attributes of window {}", window);
+            return Err(());
+        }
+        let bits_per_pixel = presenter.bits_per_pixel();
+        if bits_per_pixel != Some(32) {
+            error!("Only visuals with 32 bits per pixel are supported, window {} has {:?}", window, bits_per_pixel);
+            return Err(());
+        }
+
+        Ok(presenter)
+    }
+
+    /// X11 has no scale of its own, desktops set the Xft.dpi resource instead
+    fn read_scale(&self) -> Option<f64> {
+        let dpi = unsafe {
+            let value = (self.xlib.XGetDefault)(self.display, c"Xft".as_ptr(), c"dpi".as_ptr());
+            if value.is_null() {
+                return None;
+            }
+            CStr::from_ptr(value).to_str().ok()?.parse::<f64>().ok()?
+        };
+        // 96 DPI is 100%
+        (dpi > 0.0).then_some(dpi / 96.0)
+    }
+
+    /// Bits per pixel of images with the depth of the window
+    fn bits_per_pixel(&self) -> Option<c_int> {
+        unsafe {
+            let mut count = 0;
+            let formats = (self.xlib.XListPixmapFormats)(self.display, &mut count);
+            if formats.is_null() {
+                return None;
+            }
+            let bits_per_pixel = std::slice::from_raw_parts(formats, count.max(0) as usize)
+                .iter()
+                .find(|i| i.depth == self.depth)
+                .map(|i| i.bits_per_pixel);
+            (self.xlib.XFree)(formats as *mut c_void);
+            bits_per_pixel
+        }
+    }
+
+    /// Follows the size of the window, returns false if the window is gone
+    fn update_size(&mut self) -> bool {
+        let attributes = unsafe {
+            let mut attributes = MaybeUninit::zeroed();
+            if (self.xlib.XGetWindowAttributes)(self.display, self.window, attributes.as_mut_ptr()) == 0 {
+                return false;
+            }
+            attributes.assume_init()
+        };
+
+        let width = attributes.width.max(0) as u32;
+        let height = attributes.height.max(0) as u32;
+        if width != self.width || height != self.height || self.image.is_none() {
+            self.visual = attributes.visual;
+            self.depth = attributes.depth;
+            self.width = width;
+            self.height = height;
+            self.destroy_image();
+            if width != 0 && height != 0 {
+                self.image = self.create_shm_image().or_else(|| self.create_image());
+            }
+        }
+        true
+    }
+
+    fn create_shm_image(&self) -> Option<Image> {
+        let xext = self.xext.as_ref()?;
+        unsafe {
+            if (xext.XShmQueryExtension)(self.display) == 0 {
+                return None;
+            }
+
+            let mut info = Box::new(MaybeUninit::<XShmSegmentInfo>::zeroed().assume_init());
+            let image = (xext.XShmCreateImage)(
+                self.display,
+                self.visual,
+                self.depth as c_uint,
+                ZPixmap,
+                null_mut(),
+                &mut *info,
+                self.width,
+                self.height,
+            );
+            if image.is_null() {
+                return None;
+            }
+
+            let size = (*image).bytes_per_line as usize * (*image).height as usize;
+            info.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if info.shmid < 0 {
+                (self.xlib.XDestroyImage)(image);
+                return None;
+            }
+            info.shmaddr = libc::shmat(info.shmid, null(), 0) as *mut c_char;
+            (*image).data = info.shmaddr;
+            info.readOnly = 0;
+
+            // A remote server fails the attach with BadAccess, which only arrives with the sync and would
+            // make the default error handler exit the process, so the error is caught to fall back to XPutImage
+            (self.xlib.XSync)(self.display, 0);
+            SHM_ERROR.store(false, Ordering::SeqCst);
+            let previous = (self.xlib.XSetErrorHandler)(Some(catch_shm_error));
+            let attached = (xext.XShmAttach)(self.display, &mut *info) != 0;
+            (self.xlib.XSync)(self.display, 0);
+            (self.xlib.XSetErrorHandler)(previous);
+            let attached = attached && !SHM_ERROR.load(Ordering::SeqCst);
+            // The segment is freed as soon as both sides have detached it
+            libc::shmctl(info.shmid, libc::IPC_RMID, null_mut());
+
+            if !attached {
+                warn!("MIT-SHM is not available for window {}, falling back to XPutImage", self.window);
+                libc::shmdt(info.shmaddr as *const _);
+                (*image).data = null_mut();
+                (self.xlib.XDestroyImage)(image);
+                return None;
+            }
+
+            Some(Image::Shm(image, info))
+        }
+    }
+
+    fn create_image(&self) -> Option<Image> {
+        unsafe {
+            let image = (self.xlib.XCreateImage)(
+                self.display,
+                self.visual,
+                self.depth as c_uint,
+                ZPixmap,
+                0,
+                null_mut(),
+                self.width,
+                self.height,
+                32,
+                0,
+            );
+            if image.is_null() {
+                error!("Cannot create an image for window {}", self.window);
+                return None;
+            }
+
+            let mut data = vec![0u8; (*image).bytes_per_line as usize * (*image).height as usize];
+            (*image).data = data.as_mut_ptr() as *mut c_char;
+            Some(Image::Client(image, data))
+        }
+    }
+
+    fn destroy_image(&mut self) {
+        unsafe {
+            match self.image.take() {
+                Some(Image::Shm(image, mut info)) => {
+                    if let Some(xext) = self.xext.as_ref() {
+                        (xext.XShmDetach)(self.display, &mut *info);
+                    }
+                    libc::shmdt(info.shmaddr as *const _);
+                    // XDestroyImage would free the data
+                    (*image).data = null_mut();
+                    (self.xlib.XDestroyImage)(image);
+                }
+                Some(Image::Client(image, _data)) => {
+                    (*image).data = null_mut();
+                    (self.xlib.XDestroyImage)(image);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Set by catch_shm_error while a shared memory segment is attached
+static SHM_ERROR: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn catch_shm_error(_: *mut Display, _: *mut XErrorEvent) -> c_int {
+    SHM_ERROR.store(true, Ordering::SeqCst);
+    0
+}
+
+/// Converts the RGBA pixels of a region to the pixel format of a 32 bits per pixel TrueColor image
+unsafe fn convert(pixmap: &Pixmap, image: *mut XImage, rect: Rect) {
+    let image = &mut *image;
+    if image.bits_per_pixel != 32 {
+        return;
+    }
+
+    let shift = |mask: c_ulong| mask.trailing_zeros();
+    let (red, green, blue) = (shift(image.red_mask), shift(image.green_mask), shift(image.blue_mask));
+    let stride = image.bytes_per_line as usize;
+    let data = std::slice::from_raw_parts_mut(image.data as *mut u8, stride * image.height as usize);
+
+    let rows = pixmap.as_slice().chunks_exact(pixmap.width() as usize * 4);
+    let (left, right) = (rect.x as usize * 4, rect.right() as usize * 4);
+    for (y, row) in rows.enumerate().skip(rect.y as usize).take(rect.height as usize) {
+        let line = &mut data[y * stride + left..y * stride + right];
+        for (source, target) in row[left..right].chunks_exact(4).zip(line.chunks_exact_mut(4)) {
+            let pixel = (source[0] as u32) << red | (source[1] as u32) << green | (source[2] as u32) << blue;
+            let bytes = if image.byte_order == LSBFirst {
+                pixel.to_le_bytes()
+            } else {
+                pixel.to_be_bytes()
+            };
+            target.copy_from_slice(&bytes);
+        }
+    }
+}
+
+impl Presenter for X11Presenter {
+    fn begin(&mut self) -> Option<(u32, u32)> {
+        if !self.update_size() {
+            warn!("Window {} is not available", self.window);
+            return None;
+        }
+        Some((self.width, self.height))
+    }
+
+    fn present(&mut self, pixmap: &Pixmap) {
+        self.present_damaged(pixmap, &[Rect::new(0, 0, pixmap.width(), pixmap.height())]);
+    }
+
+    fn present_damaged(&mut self, pixmap: &Pixmap, damage: &[Rect]) {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        if width != self.width || height != self.height {
+            return;
+        }
+        let bounds = Rect::new(0, 0, width, height);
+        let regions = damage.iter().filter_map(|rect| rect.intersection(&bounds));
+        unsafe {
+            match &self.image {
+                Some(Image::Shm(image, _)) => {
+                    if let Some(xext) = self.xext.as_ref() {
+                        for rect in regions {
+                            convert(pixmap, *image, rect);
+                            let (x, y) = (rect.x as c_int, rect.y as c_int);
+                            (xext.XShmPutImage)(self.display, self.window, self.gc, *image, x, y, x, y, rect.width, rect.height, 0);
+                        }
+                    }
+                    // The shared memory must not be written before the server has read it
+                    (self.xlib.XSync)(self.display, 0);
+                }
+                Some(Image::Client(image, _)) => {
+                    for rect in regions {
+                        convert(pixmap, *image, rect);
+                        let (x, y) = (rect.x as c_int, rect.y as c_int);
+                        (self.xlib.XPutImage)(self.display, self.window, self.gc, *image, x, y, x, y, rect.width, rect.height);
+                    }
+                    (self.xlib.XFlush)(self.display);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.scale
+    }
+}
+
+impl Drop for X11Presenter {
+    fn drop(&mut self) {
+        self.destroy_image();
+        unsafe {
+            (self.xlib.XFreeGC)(self.display, self.gc);
+            if self.owns_display {
+                (self.xlib.XCloseDisplay)(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, DrawTarget, Surface};
+
+    /// Window on the X server in DISPLAY
+    struct TestWindow {
+        xlib: Xlib,
+        display: *mut Display,
+        window: c_ulong,
+    }
+
+    impl TestWindow {
+        fn new(width: u32, height: u32) -> Self {
+            let xlib = Xlib::open().unwrap();
+            unsafe {
+                let display = (xlib.XOpenDisplay)(null());
+                assert!(!display.is_null(), "Cannot open the X display");
+                let root = (xlib.XDefaultRootWindow)(display);
+                let window = (xlib.XCreateSimpleWindow)(display, root, 0, 0, width, height, 0, 0, 0);
+                (xlib.XMapWindow)(display, window);
+                (xlib.XSync)(display, 0);
+                Self { xlib, display, window }
+            }
+        }
+
+        /// RGB of a pixel on the screen, for the visuals of a 24 bit screen
+        fn pixel(&self, x: i32, y: i32) -> c_ulong {
+            unsafe {
+                let image = (self.xlib.XGetImage)(self.display, self.window, x, y, 1, 1, !0, ZPixmap);
+                assert!(!image.is_null());
+                let pixel = (self.xlib.XGetPixel)(image, 0, 0) & 0xffffff;
+                (self.xlib.XDestroyImage)(image);
+                pixel
+            }
+        }
+
+        fn resize(&self, width: u32, height: u32) {
+            unsafe {
+                (self.xlib.XResizeWindow)(self.display, self.window, width, height);
+                (self.xlib.XSync)(self.display, 0);
+            }
+        }
+    }
+
+    impl Drop for TestWindow {
+        fn drop(&mut self) {
+            unsafe {
+                (self.xlib.XDestroyWindow)(self.display, self.window);
+                (self.xlib.XCloseDisplay)(self.display);
+            }
+        }
+    }
+
+    fn pixmap(width: u32, height: u32, ctx: &[DrawTarget]) -> Pixmap {
+        let mut pixmap = Pixmap::new(width, height);
+        pixmap.draw(ctx);
+        pixmap
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run -s \"-screen 0 640x480x24\" cargo test --features window -- --ignored x11"]
+    fn present_and_resize() {
+        let window = TestWindow::new(40, 30);
+        let mut presenter = X11Presenter::new(window.display as *mut c_void, window.window).unwrap();
+        assert_eq!(presenter.begin(), Some((40, 30)));
+
+        presenter.present(&pixmap(40, 30, &[DrawTarget::Clear(Color::Red)]));
+        assert_eq!(window.pixel(0, 0), 0xff0000);
+        assert_eq!(window.pixel(39, 29), 0xff0000);
+
+        // Only the damaged region is sent
+        let lime = pixmap(40, 30, &[DrawTarget::Clear(Color::Lime)]);
+        presenter.present_damaged(&lime, &[Rect::new(10, 5, 2, 2)]);
+        assert_eq!(window.pixel(11, 6), 0x00ff00);
+        assert_eq!(window.pixel(12, 6), 0xff0000);
+        assert_eq!(window.pixel(0, 0), 0xff0000);
+
+        window.resize(60, 20);
+        assert_eq!(presenter.begin(), Some((60, 20)));
+        // Frames of the old size are dropped
+        presenter.present(&lime);
+        presenter.present(&pixmap(60, 20, &[DrawTarget::Clear(Color::Blue)]));
+        assert_eq!(window.pixel(59, 19), 0x0000ff);
+        assert_eq!(window.pixel(0, 0), 0x0000ff);
+    }
+}